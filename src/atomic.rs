@@ -0,0 +1,82 @@
+//! Crash-safe file writes.
+//!
+//! Content goes to a temp file in the target's directory, is flushed to disk,
+//! and is then renamed over the target, so readers see either the old file or
+//! the complete new one — never a truncated secrets file.
+
+use anyhow::{Context, Result};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Atomically replace `path` with `bytes`.  The file gets permission `mode`
+/// (Unix) when it is created; the temp file is removed if anything fails.
+pub fn write(path: &Path, bytes: &[u8], mode: u32) -> Result<()> {
+    let tmp = temp_path(path);
+    let _ = std::fs::remove_file(&tmp);
+
+    let mut opts = std::fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        opts.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let result = (|| -> Result<()> {
+        let mut file = opts
+            .open(&tmp)
+            .with_context(|| format!("failed to create {}", tmp.display()))?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// `dir/.name.<pid>.<n>.tmp`, next to the target so the rename stays on one
+/// filesystem; `n` counts writes, so concurrent writers in one process
+/// never share a temp file.
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .map_or_else(|| "out".into(), |n| n.to_string_lossy().into_owned());
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{name}.{}.{n}.tmp", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_file_and_leaves_no_temp() {
+        let dir = std::env::temp_dir().join(format!("bwenv-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("out.env");
+        std::fs::write(&target, "old").unwrap();
+
+        write(&target, b"A=1\n", 0o600).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"A=1\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            // A replaced file takes the temp file's mode.
+            let mode = target.metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(write(&dir.join("missing/out.env"), b"x", 0o600).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+//! Opt-in on-disk read-through cache of namespace notes.
+//!
+//! Enabled by setting `BWENV_CACHE_TTL` to a number of seconds; unset or `0`
+//! disables it.  Entries are keyed by (folder, name) and hold only the notes —
+//! never the Login password.  The file lives under the XDG
+//! cache dir, is created `0600` inside a `0700` directory, and is deleted
+//! whenever bwenv finds the vault locked, so cached secrets never outlive the
+//! unlocked session.  It is not encrypted.
+//!
+//! Only read paths consult the cache; writes always fetch fresh data and
+//! invalidate the entry they touch.  All cache I/O is best-effort: failures
+//! fall back to asking rbw.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL_ENV: &str = "BWENV_CACHE_TTL";
+
+/// Held across every load-modify-save, so parallel fetches (see
+/// `rbw::get_notes_many`) don't drop each other's entries.
+static UPDATE: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    folder: String,
+    name: String,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    notes: String,
+}
+
+/// Configured TTL, or `None` when caching is disabled.
+pub fn ttl() -> Option<Duration> {
+    let raw = std::env::var(TTL_ENV).ok()?;
+    match raw.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            eprintln!("WARNING: ignoring invalid {TTL_ENV}={raw:?} (expected seconds)");
+            None
+        }
+    }
+}
+
+/// Return fresh cached notes for (folder, name), if any.
+pub fn get(name: &str, folder: &str) -> Option<String> {
+    let ttl = ttl()?;
+    let now = now_secs();
+    load(&path()?)
+        .into_iter()
+        .find(|e| {
+            e.folder == folder && e.name == name && now.saturating_sub(e.fetched_at) < ttl.as_secs()
+        })
+        .map(|e| e.notes)
+}
+
+/// Record `notes` for (folder, name), dropping expired entries on the way.
+pub fn put(name: &str, folder: &str, notes: &str) {
+    let Some(ttl) = ttl() else { return };
+    let Some(path) = path() else { return };
+    put_in(&path, ttl, name, folder, notes);
+}
+
+fn put_in(path: &Path, ttl: Duration, name: &str, folder: &str, notes: &str) {
+    let _update = UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+    let now = now_secs();
+    let mut entries = load(path);
+    entries.retain(|e| {
+        !(e.folder == folder && e.name == name) && now.saturating_sub(e.fetched_at) < ttl.as_secs()
+    });
+    entries.push(Entry {
+        folder: folder.to_string(),
+        name: name.to_string(),
+        fetched_at: now,
+        notes: notes.to_string(),
+    });
+    save(path, &entries);
+}
+
+/// Forget (folder, name) after it was written or deleted.
+pub fn invalidate(name: &str, folder: &str) {
+    if ttl().is_none() {
+        return;
+    }
+    let Some(path) = path() else { return };
+    let _update = UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = load(&path);
+    let before = entries.len();
+    entries.retain(|e| !(e.folder == folder && e.name == name));
+    if entries.len() != before {
+        save(&path, &entries);
+    }
+}
+
+/// Delete the cache file (e.g. because the vault is locked).
+pub fn clear() {
+    if let Some(path) = path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    file_path("cache")
+}
+
+/// `$XDG_CACHE_HOME/bwenv/<stem>.json` (default `~/.cache`), or
+/// `<stem>-<profile>.json` under an rbw profile so accounts never serve each
+/// other's data.
+pub fn file_path(stem: &str) -> Option<PathBuf> {
+    let file = match crate::rbw::profile() {
+        Some(profile) => format!("{stem}-{profile}.json"),
+        None => format!("{stem}.json"),
+    };
+    Some(dir()?.join(file))
+}
+
+/// `$XDG_CACHE_HOME/bwenv` (default `~/.cache/bwenv`).
+pub fn dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(base.join("bwenv"))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn load(path: &Path) -> Vec<Entry> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn save(path: &Path, entries: &[Entry]) {
+    let result = serde_json::to_vec(entries)
+        .map_err(anyhow::Error::from)
+        .and_then(|bytes| write_private(path, &bytes));
+    if let Err(e) = result
+        && crate::verbose()
+    {
+        eprintln!("note: could not write cache: {e:#}");
+    }
+}
+
+/// Atomically replace `path` with `bytes`, as a `0600` file inside a `0700`
+/// directory.
+pub fn write_private(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+
+    create_private_dir(path.parent().context("cache path has no parent")?)?;
+    crate::atomic::write(path, bytes, 0o600)
+}
+
+/// Create `dir` (and parents) with mode `0700`.
+pub fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt as _;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_puts_keep_every_entry() {
+        let dir = std::env::temp_dir().join(format!("bwenv-cache-test-{}", std::process::id()));
+        let path = dir.join("cache.json");
+        let ttl = Duration::from_secs(60);
+        std::thread::scope(|scope| {
+            for n in 0..16 {
+                let path = &path;
+                scope.spawn(move || put_in(path, ttl, &format!("ns{n}"), "bwenv", "A=1"));
+            }
+        });
+        let mut names: Vec<String> = load(&path).into_iter().map(|e| e.name).collect();
+        names.sort();
+        let mut expected: Vec<String> = (0..16).map(|n| format!("ns{n}")).collect();
+        expected.sort();
+        assert_eq!(names, expected);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
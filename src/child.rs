@@ -0,0 +1,274 @@
+//! Run a child process while bwenv stays resident as its parent.
+//!
+//! Exec mode replaces the bwenv process outright, but some commands have work
+//! left to do once the child exits (e.g. `shell --lock`).  Those spawn the
+//! child through [`run`], which keeps signal delivery and exit status behaving
+//! as if the child had been exec'd.
+
+use crate::redact::Redactor;
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+
+/// Output with no newline is held back at most this long before being
+/// redacted and written anyway.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Spawn `command`, wait for it, and return the exit code bwenv should exit
+/// with.
+///
+/// While the child runs, terminal-generated SIGINT/SIGQUIT are ignored by
+/// bwenv (the child is in the same foreground process group and receives them
+/// itself), and SIGTERM/SIGHUP sent to bwenv are forwarded to the child.  A
+/// child killed by a signal maps to `128 + signo`, as in a shell.
+pub fn run(command: &mut Command) -> Result<i32> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+
+    #[cfg(unix)]
+    let _signals = unix::forward_signals(child.id());
+
+    let status = child
+        .wait()
+        .with_context(|| format!("failed to wait for {program}"))?;
+    Ok(exit_code(status))
+}
+
+/// Like [`run`], but the child's stdout and stderr are piped through
+/// `redactor` before being written to bwenv's own.
+///
+/// Output is forwarded a line at a time (or every [`MAX_PENDING`] bytes when
+/// there is no newline), so a prompt without a trailing newline may show up
+/// late.  The child sees pipes rather than a terminal and may change its
+/// buffering or colour output accordingly.
+pub fn run_redacted(command: &mut Command, redactor: Redactor) -> Result<i32> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+
+    #[cfg(unix)]
+    let _signals = unix::forward_signals(child.id());
+
+    let redactor = Arc::new(redactor);
+    let stdout = child.stdout.take().context("failed to open child stdout")?;
+    let stderr = child.stderr.take().context("failed to open child stderr")?;
+    let pumps = [
+        {
+            let r = Arc::clone(&redactor);
+            std::thread::spawn(move || pump(stdout, std::io::stdout(), &r))
+        },
+        {
+            let r = Arc::clone(&redactor);
+            std::thread::spawn(move || pump(stderr, std::io::stderr(), &r))
+        },
+    ];
+
+    let status = child
+        .wait()
+        .with_context(|| format!("failed to wait for {program}"))?;
+    for pump in pumps {
+        // A closed stdout on our side (e.g. `| head`) is not an error worth
+        // masking the child's exit status for.
+        let _ = pump.join();
+    }
+    Ok(exit_code(status))
+}
+
+/// Copy `src` to `dst`, redacting secrets.  Secret values never contain a
+/// newline, so everything up to the last newline can be redacted in full.
+fn pump(mut src: impl Read, mut dst: impl Write, redactor: &Redactor) -> std::io::Result<()> {
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..n]);
+
+        let (out, used) = match pending.iter().rposition(|&b| b == b'\n') {
+            Some(nl) => {
+                let (out, _) = redactor.redact(&pending[..=nl], true);
+                (out, nl + 1)
+            }
+            None if pending.len() > MAX_PENDING => redactor.redact(&pending, false),
+            None => continue,
+        };
+        dst.write_all(&out)?;
+        dst.flush()?;
+        pending.drain(..used);
+    }
+    let (out, _) = redactor.redact(&pending, true);
+    dst.write_all(&out)?;
+    dst.flush()
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        if let Some(signo) = status.signal() {
+            return 128 + signo;
+        }
+    }
+    1
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// PIDs of the children currently being waited on (0 = free slot).
+    /// Several can run at once (e.g. `exec-each --jobs`); a child that finds
+    /// no free slot simply doesn't get signals forwarded.
+    static CHILD_PIDS: [AtomicI32; 64] = [const { AtomicI32::new(0) }; 64];
+
+    /// Live guards, and the dispositions to restore when the last one drops.
+    static INSTALLED: Mutex<(usize, Vec<(libc::c_int, libc::sigaction)>)> =
+        Mutex::new((0, Vec::new()));
+
+    extern "C" fn forward(signo: libc::c_int) {
+        for slot in &CHILD_PIDS {
+            let pid = slot.load(Ordering::Relaxed);
+            if pid > 0 {
+                // SAFETY: kill(2) is async-signal-safe.
+                unsafe { libc::kill(pid, signo) };
+            }
+        }
+    }
+
+    /// Stops forwarding to its child, and restores the previous signal
+    /// dispositions once no child is left, when dropped.
+    pub struct Guard {
+        slot: Option<&'static AtomicI32>,
+    }
+
+    pub fn forward_signals(pid: u32) -> Guard {
+        let slot = CHILD_PIDS.iter().find(|s| {
+            s.compare_exchange(0, pid as i32, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        });
+
+        let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+        if installed.0 == 0 {
+            let forward = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            for (signo, handler) in [
+                (libc::SIGINT, libc::SIG_IGN),
+                (libc::SIGQUIT, libc::SIG_IGN),
+                (libc::SIGTERM, forward),
+                (libc::SIGHUP, forward),
+            ] {
+                // SAFETY: plain sigaction(2) calls with zero-initialised structs.
+                unsafe {
+                    let mut new: libc::sigaction = std::mem::zeroed();
+                    new.sa_sigaction = handler;
+                    libc::sigemptyset(&mut new.sa_mask);
+                    let mut old: libc::sigaction = std::mem::zeroed();
+                    if libc::sigaction(signo, &new, &mut old) == 0 {
+                        installed.1.push((signo, old));
+                    }
+                }
+            }
+        }
+        installed.0 += 1;
+        Guard { slot }
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if let Some(slot) = self.slot {
+                slot.store(0, Ordering::Relaxed);
+            }
+            let mut installed = INSTALLED.lock().unwrap_or_else(|e| e.into_inner());
+            installed.0 -= 1;
+            if installed.0 == 0 {
+                for (signo, old) in installed.1.drain(..) {
+                    // SAFETY: restoring dispositions captured in `forward_signals`.
+                    unsafe { libc::sigaction(signo, &old, std::ptr::null_mut()) };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Forwarded signals reach every running child, so tests that run
+    /// children take turns.
+    static CHILDREN: Mutex<()> = Mutex::new(());
+
+    fn serial() -> std::sync::MutexGuard<'static, ()> {
+        CHILDREN.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[test]
+    fn propagates_exit_code() {
+        let _serial = serial();
+        let code = run(Command::new("sh").args(["-c", "exit 7"])).unwrap();
+        assert_eq!(code, 7);
+    }
+
+    #[test]
+    fn concurrent_children_keep_their_exit_codes() {
+        let _serial = serial();
+        let codes: Vec<i32> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (1..=4)
+                .map(|n| {
+                    scope.spawn(move || {
+                        run(Command::new("sh").args(["-c", &format!("sleep 0.1; exit {n}")]))
+                            .unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(codes, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn pump_redacts_across_reads() {
+        let redactor = Redactor::new(["topsecret"]);
+        let mut out = Vec::new();
+        pump(
+            &b"a topsecret b\nno newline topsecret"[..],
+            &mut out,
+            &redactor,
+        )
+        .unwrap();
+        assert_eq!(out, b"a *** b\nno newline ***");
+    }
+
+    #[test]
+    fn signal_death_maps_to_128_plus_signo() {
+        let _serial = serial();
+        let code = run(Command::new("sh").args(["-c", "kill -TERM $$"])).unwrap();
+        assert_eq!(code, 128 + libc::SIGTERM);
+    }
+
+    #[test]
+    fn sigterm_to_bwenv_reaches_the_child() {
+        let _serial = serial();
+        let killer = std::thread::spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            // SAFETY: signalling our own process; `run` is forwarding SIGTERM.
+            unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+        });
+        let code =
+            run(Command::new("sh").args(["-c", "trap 'kill $!; exit 42' TERM; sleep 5 & wait $!"]))
+                .unwrap();
+        killer.join().unwrap();
+        assert_eq!(code, 42);
+    }
+}
@@ -0,0 +1,348 @@
+//! Dynamic shell completion.
+//!
+//! `bwenv completions <shell>` prints a small script that, on every TAB, runs
+//! the hidden `bwenv __complete -- <words…>` with the words typed so far
+//! (the last one being the word under the cursor).  All the logic lives here:
+//! subcommands and flags come from the clap definition, namespaces and key
+//! names from the vault.
+//!
+//! Vault lookups never prompt (a locked vault just yields no candidates) and
+//! are cached for [`TTL`] in `complete.json` next to the notes cache, so
+//! repeated TABs stay fast.  Only names are cached, never values.
+
+use crate::{Cli, cache, rbw, store};
+use anyhow::Result;
+use clap::{CommandFactory as _, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL: Duration = Duration::from_secs(30);
+
+/// Subcommands whose first positional is a namespace.
+const NAMESPACE_COMMANDS: &[&str] = &[
+    "shell",
+    "env",
+    "set",
+    "set-raw",
+    "import",
+    "push",
+    "pull",
+    "create",
+    "rotate",
+    "ensure",
+    "list",
+    "unset",
+    "cp-key",
+    "export",
+    "snapshot",
+    "scaffold",
+    "describe",
+    "info",
+    "exists",
+    "fingerprint",
+    "log",
+    "open",
+    "watch-ns",
+    "compare",
+    "merge-namespaces",
+    "with-file",
+];
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+const BASH: &str = r#"_bwenv() {
+    local IFS=$'\n'
+    COMPREPLY=($(bwenv __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _bwenv bwenv
+"#;
+
+const ZSH: &str = r#"#compdef bwenv
+_bwenv() {
+    local -a candidates
+    candidates=(${(f)"$(bwenv __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    if (( ${#candidates} )); then
+        compadd -a candidates
+    else
+        _files
+    fi
+}
+compdef _bwenv bwenv
+"#;
+
+const FISH: &str = r#"complete -c bwenv -a '(bwenv __complete -- (commandline -opc)[2..] (commandline -ct) 2>/dev/null)'
+"#;
+
+/// The completion script for `shell`.
+pub fn script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+/// What the word under the cursor should be completed from.
+#[derive(Debug, PartialEq, Eq)]
+enum Source {
+    Flags(Vec<String>),
+    SubcommandsAndNamespaces(Vec<String>),
+    Namespaces,
+    Keys(String),
+    Executables,
+    Nothing,
+}
+
+/// Print candidates for `words` (everything after `bwenv`, ending with the
+/// partial word), one per line.
+pub fn run(folder: &str, words: &[String]) -> Result<()> {
+    rbw::set_no_unlock(true);
+    let (current, before) = match words.split_last() {
+        Some((current, before)) => (current.as_str(), before),
+        None => ("", words),
+    };
+    let candidates = match source(before, current) {
+        Source::Flags(flags) => flags,
+        Source::SubcommandsAndNamespaces(mut subcommands) => {
+            subcommands.extend(namespaces(folder));
+            subcommands
+        }
+        Source::Namespaces => namespaces(folder),
+        Source::Keys(namespace) => keys(folder, &namespace),
+        Source::Executables => executables(current),
+        Source::Nothing => Vec::new(),
+    };
+    for candidate in candidates.iter().filter(|c| c.starts_with(current)) {
+        println!("{candidate}");
+    }
+    Ok(())
+}
+
+fn source(before: &[String], current: &str) -> Source {
+    let root = Cli::command();
+    let mut sub: Option<clap::Command> = None;
+    let mut positionals: Vec<&str> = Vec::new();
+
+    let mut iter = before.iter();
+    while let Some(word) = iter.next() {
+        if word == "--" {
+            continue;
+        }
+        if let Some(long) = word.strip_prefix("--") {
+            if !long.contains('=')
+                && takes_value(&root, sub.as_ref(), |a| a.get_long() == Some(long))
+            {
+                iter.next();
+            }
+            continue;
+        }
+        if let Some(shorts) = word.strip_prefix('-') {
+            // `-f work` skips the value; `-fwork` (and `-vfwork`) carry it.
+            let mut chars = shorts.chars();
+            while let Some(c) = chars.next() {
+                if takes_value(&root, sub.as_ref(), |a| a.get_short() == Some(c)) {
+                    if chars.as_str().is_empty() {
+                        iter.next();
+                    }
+                    break;
+                }
+            }
+            continue;
+        }
+        if sub.is_none()
+            && positionals.is_empty()
+            && let Some(found) = root.find_subcommand(word)
+        {
+            sub = Some(found.clone());
+            continue;
+        }
+        positionals.push(word);
+    }
+
+    if current.starts_with('-') {
+        return Source::Flags(long_flags(&root, sub.as_ref()));
+    }
+
+    match &sub {
+        None => match positionals.len() {
+            0 => Source::SubcommandsAndNamespaces(
+                root.get_subcommands()
+                    .filter(|s| !s.is_hide_set())
+                    .map(|s| s.get_name().to_string())
+                    .collect(),
+            ),
+            // Exec mode: `bwenv <namespace> <prog> [args]`.
+            1 => Source::Executables,
+            _ => Source::Nothing,
+        },
+        Some(sub) if NAMESPACE_COMMANDS.contains(&sub.get_name()) => {
+            match (sub.get_name(), positionals.as_slice()) {
+                (_, []) | ("compare" | "cp-key", [_]) => Source::Namespaces,
+                ("cp-key", [ns, _]) => Source::Keys(ns.to_string()),
+                ("set" | "unset", [ns, ..]) | ("describe" | "rotate", [ns]) => {
+                    Source::Keys(ns.to_string())
+                }
+                _ => Source::Nothing,
+            }
+        }
+        Some(_) => Source::Nothing,
+    }
+}
+
+fn takes_value(
+    root: &clap::Command,
+    sub: Option<&clap::Command>,
+    is_flag: impl Fn(&clap::Arg) -> bool,
+) -> bool {
+    root.get_arguments()
+        .chain(sub.into_iter().flat_map(|s| s.get_arguments()))
+        .find(|a| is_flag(a))
+        .is_some_and(|a| a.get_action().takes_values())
+}
+
+fn long_flags(root: &clap::Command, sub: Option<&clap::Command>) -> Vec<String> {
+    let mut flags: Vec<String> = root
+        .get_arguments()
+        .filter(|a| sub.is_none() || a.is_global_set())
+        .chain(sub.into_iter().flat_map(|s| s.get_arguments()))
+        .filter(|a| !a.is_hide_set())
+        .filter_map(|a| a.get_long().map(|l| format!("--{l}")))
+        .collect();
+    flags.push("--help".to_string());
+    flags.sort();
+    flags.dedup();
+    flags
+}
+
+fn namespaces(folder: &str) -> Vec<String> {
+    cached(&format!("namespaces:{folder}"), || {
+        Ok(rbw::list_items(folder)?
+            .into_iter()
+            .map(|i| i.name)
+            .collect())
+    })
+}
+
+fn keys(folder: &str, namespace: &str) -> Vec<String> {
+    cached(&format!("keys:{folder}/{namespace}"), || {
+        let notes = rbw::get_notes(namespace, folder)?.unwrap_or_default();
+        let mut keys: Vec<String> = store::parse(&notes).into_keys().collect();
+        keys.sort();
+        Ok(keys)
+    })
+}
+
+fn executables(prefix: &str) -> Vec<String> {
+    let Some(path) = std::env::var_os("PATH") else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    candidates: Vec<String>,
+}
+
+/// Candidates for `key`, from the completion cache if fresh, otherwise from
+/// `fetch` (failures just mean no candidates).
+fn cached(key: &str, fetch: impl FnOnce() -> Result<Vec<String>>) -> Vec<String> {
+    let path = cache::file_path("complete");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut entries: Vec<Entry> = path
+        .as_ref()
+        .and_then(|p| std::fs::read(p).ok())
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    entries.retain(|e| now.saturating_sub(e.fetched_at) < TTL.as_secs());
+    if let Some(entry) = entries.iter().find(|e| e.key == key) {
+        return entry.candidates.clone();
+    }
+
+    let Ok(candidates) = fetch() else {
+        return Vec::new();
+    };
+    entries.push(Entry {
+        key: key.to_string(),
+        fetched_at: now,
+        candidates: candidates.clone(),
+    });
+    if let Some(path) = path
+        && let Ok(bytes) = serde_json::to_vec(&entries)
+    {
+        let _ = cache::write_private(&path, &bytes);
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src(before: &[&str], current: &str) -> Source {
+        let before: Vec<String> = before.iter().map(|w| w.to_string()).collect();
+        source(&before, current)
+    }
+
+    #[test]
+    fn completes_subcommands_then_namespaces() {
+        let Source::SubcommandsAndNamespaces(subs) = src(&[], "") else {
+            panic!("expected subcommands");
+        };
+        assert!(subs.contains(&"list".to_string()));
+        assert!(!subs.iter().any(|s| s == "__complete"));
+        assert_eq!(src(&["list"], ""), Source::Namespaces);
+        assert_eq!(src(&["--folder", "work", "info"], ""), Source::Namespaces);
+        assert_eq!(src(&["-f", "work", "list"], ""), Source::Namespaces);
+        assert_eq!(src(&["-fwork", "list"], ""), Source::Namespaces);
+        assert_eq!(src(&["export", "-o", ".env"], ""), Source::Namespaces);
+        assert_eq!(src(&["compare", "staging"], ""), Source::Namespaces);
+    }
+
+    #[test]
+    fn completes_keys_after_namespace() {
+        assert_eq!(src(&["unset", "dev", "A"], ""), Source::Keys("dev".into()));
+        assert_eq!(src(&["describe", "dev"], "DB"), Source::Keys("dev".into()));
+        assert_eq!(src(&["describe", "dev", "DB"], ""), Source::Nothing);
+        assert_eq!(
+            src(&["cp-key", "dev", "prod"], ""),
+            Source::Keys("dev".into())
+        );
+        assert_eq!(src(&["list", "--sort", "name", "dev"], ""), Source::Nothing);
+    }
+
+    #[test]
+    fn exec_mode_completes_programs() {
+        assert_eq!(src(&["dev"], "no"), Source::Executables);
+        assert_eq!(src(&["--redact", "dev"], ""), Source::Executables);
+        assert_eq!(src(&["dev", "node"], ""), Source::Nothing);
+    }
+
+    #[test]
+    fn completes_flags_for_context() {
+        let Source::Flags(flags) = src(&["list"], "--") else {
+            panic!("expected flags");
+        };
+        assert!(flags.contains(&"--sort".to_string()));
+        assert!(flags.contains(&"--folder".to_string()));
+        assert!(!flags.contains(&"--redact".to_string()));
+    }
+}
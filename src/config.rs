@@ -0,0 +1,157 @@
+//! Optional user configuration, read from `$XDG_CONFIG_HOME/bwenv/config.json`
+//! (or the file named by `BWENV_CONFIG`).  A missing file means defaults.
+//!
+//! ```json
+//! { "policy": { "deny": ["AWS_SECRET_*", "shared/*_PASSWORD"], "allow": [] } }
+//! ```
+
+use crate::glob_match;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const CONFIG_ENV: &str = "BWENV_CONFIG";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub policy: Policy,
+    /// Default folder, below `--folder` and `BWENV_FOLDER`.
+    pub folder: Option<String>,
+    /// Default for `--field`.
+    pub field: Option<String>,
+    /// Web vault base URL for `open` (defaults to rbw's server, then
+    /// vault.bitwarden.com).
+    pub vault_url: Option<String>,
+    /// Namespace template for `--from-git` (`{repo}`, `{branch}`).
+    pub git_namespace: Option<String>,
+    /// Always behave as with `--strict-keys`.
+    pub strict_keys: bool,
+    /// Default `export --format` by namespace; the first matching rule wins.
+    pub export_formats: Vec<ExportFormatRule>,
+    /// What `list --tree` splits namespace names on (default `/`).
+    pub namespace_separator: Option<String>,
+}
+
+/// `{ "namespace": "docker/*", "format": "env-json" }`: export namespaces
+/// matching the glob in that format unless `--format` says otherwise.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportFormatRule {
+    pub namespace: String,
+    pub format: String,
+}
+
+impl Config {
+    /// The configured export format name for `namespace`, if any rule matches.
+    pub fn export_format(&self, namespace: &str) -> Option<&str> {
+        self.export_formats
+            .iter()
+            .find(|rule| glob_match(&rule.namespace, namespace))
+            .map(|rule| rule.format.as_str())
+    }
+}
+
+/// Which key names may be written.  Patterns are globs (`*`, `?`) matched
+/// against the key, or against `namespace/KEY` when they contain a `/`.
+/// A deny match always wins; a non-empty allow list rejects everything it
+/// does not match.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl Policy {
+    /// Fail if writing `key` into `namespace` violates the policy, naming
+    /// the rule responsible.
+    pub fn check(&self, namespace: &str, key: &str) -> Result<()> {
+        let qualified = format!("{namespace}/{key}");
+        let matches = |pattern: &String| {
+            let subject = if pattern.contains('/') {
+                &qualified
+            } else {
+                key
+            };
+            glob_match(pattern, subject)
+        };
+        if let Some(rule) = self.deny.iter().find(|p| matches(p)) {
+            bail!(
+                "key `{key}` in namespace `{namespace}` is forbidden by policy rule deny `{rule}`"
+            );
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(matches) {
+            bail!("key `{key}` in namespace `{namespace}` matches no policy allow rule");
+        }
+        Ok(())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The loaded configuration (read once).
+pub fn get() -> Result<&'static Config> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+    let config = match path() {
+        Some(path) => match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("failed to parse config `{}`", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read config `{}`", path.display()));
+            }
+        },
+        None => Config::default(),
+    };
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("bwenv").join("config.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deny_wins_and_allow_restricts() {
+        let policy: Policy = serde_json::from_str(
+            r#"{"allow": ["APP_*", "shared/*"], "deny": ["*_SECRET", "shared/AWS_*"]}"#,
+        )
+        .unwrap();
+        assert!(policy.check("dev", "APP_PORT").is_ok());
+        assert!(policy.check("shared", "LOG_LEVEL").is_ok());
+        let err = policy.check("dev", "APP_SECRET").unwrap_err().to_string();
+        assert!(err.contains("deny `*_SECRET`"), "{err}");
+        assert!(policy.check("shared", "AWS_REGION").is_err());
+        assert!(policy.check("dev", "AWS_REGION").is_err());
+        assert!(Policy::default().check("dev", "ANYTHING").is_ok());
+    }
+
+    #[test]
+    fn first_matching_export_format_wins() {
+        let config: Config = serde_json::from_str(
+            r#"{"export_formats": [
+                {"namespace": "docker/*", "format": "env-json"},
+                {"namespace": "*", "format": "shell"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.export_format("docker/web"), Some("env-json"));
+        assert_eq!(config.export_format("prod"), Some("shell"));
+        assert_eq!(Config::default().export_format("prod"), None);
+    }
+}
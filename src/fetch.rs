@@ -0,0 +1,136 @@
+//! HTTPS downloads for `import --url`, via the system `curl`.
+//!
+//! Shelling out (as bwenv already does for rbw) keeps an HTTP/TLS stack out
+//! of the build.  The URL and headers are handed to curl as a config file on
+//! stdin, so tokens in them don't show up in curl's command line.  curl
+//! verifies certificates as usual, and only `https://` is allowed, also for
+//! redirects.
+
+use crate::spinner;
+use anyhow::{Context, Result, bail};
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+/// Fetch `url` with the given `Name: value` headers and return the body of
+/// a 2xx response.
+pub fn get(url: &str, headers: &[String]) -> Result<Vec<u8>> {
+    if !url.starts_with("https://") {
+        bail!("only https:// URLs can be imported from");
+    }
+    for header in headers {
+        if !header.contains(':') {
+            bail!("invalid header {header:?} (expected `Name: value`)");
+        }
+    }
+    let shown = display_url(url);
+
+    let mut cmd = Command::new("curl");
+    cmd.args([
+        "--silent",
+        "--show-error",
+        "--location",
+        "--proto",
+        "=https",
+        "--proto-redir",
+        "=https",
+        "--max-time",
+        "60",
+        "--write-out",
+        "\n%{http_code}",
+        "--config",
+        "-",
+    ])
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+
+    let sp = spinner::start(format!("Downloading {shown}…"));
+    let output = (|| -> Result<_> {
+        let mut child = cmd.spawn().map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                anyhow::anyhow!("`curl` is required for --url but was not found on PATH")
+            } else {
+                anyhow::Error::new(e).context("failed to run curl")
+            }
+        })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(curl_config(url, headers).as_bytes())?;
+        }
+        Ok(child.wait_with_output()?)
+    })();
+    sp.stop();
+    let output = output?;
+
+    if !output.status.success() {
+        bail!(
+            "failed to fetch {shown}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let (body, status) = split_status(output.stdout).context("unexpected curl output")?;
+    if !(200..300).contains(&status) {
+        bail!("failed to fetch {shown}: HTTP {status}");
+    }
+    Ok(body)
+}
+
+/// `url` without its query string, which may carry a token.
+pub fn display_url(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(base, _)| base)
+}
+
+/// A curl config file setting the URL and headers.
+fn curl_config(url: &str, headers: &[String]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut config = format!("url = {}\n", quote(url));
+    for header in headers {
+        config.push_str(&format!("header = {}\n", quote(header)));
+    }
+    config
+}
+
+/// Split curl's stdout into the body and the status code that
+/// `--write-out "\n%{http_code}"` appended.
+fn split_status(mut stdout: Vec<u8>) -> Option<(Vec<u8>, u16)> {
+    let newline = stdout.iter().rposition(|&b| b == b'\n')?;
+    let status = std::str::from_utf8(&stdout[newline + 1..])
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    stdout.truncate(newline);
+    Some((stdout, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_quotes_url_and_headers() {
+        let config = curl_config(
+            "https://example.com/a?b=\"c\"",
+            &["Authorization: Bearer x\\y".to_string()],
+        );
+        assert_eq!(
+            config,
+            "url = \"https://example.com/a?b=\\\"c\\\"\"\nheader = \"Authorization: Bearer x\\\\y\"\n"
+        );
+    }
+
+    #[test]
+    fn status_is_split_from_body() {
+        assert_eq!(
+            split_status(b"A=1\nB=2\n\n200".to_vec()),
+            Some((b"A=1\nB=2\n".to_vec(), 200))
+        );
+        assert_eq!(split_status(b"\n404".to_vec()), Some((Vec::new(), 404)));
+        assert_eq!(split_status(b"garbage".to_vec()), None);
+        assert_eq!(display_url("https://h/p?token=s"), "https://h/p");
+    }
+
+    #[test]
+    fn rejects_plain_http() {
+        assert!(get("http://example.com/.env", &[]).is_err());
+    }
+}
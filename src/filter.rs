@@ -0,0 +1,105 @@
+//! `--value-filter`: pipe each value through an external command.
+//!
+//! For values that carry a second layer of encryption (age, sops, gpg, …):
+//! before a namespace is injected, previewed or exported, every text value
+//! is written to the stdin of `sh -c <command>`, and what the command prints
+//! becomes the value (one trailing newline is dropped).  The key name is in
+//! `BWENV_KEY`.  Base64 values are passed through untouched.
+//!
+//! The filter runs once per value, one at a time, so a slow command (or one
+//! that prompts) is paid for every key.  It sees the decrypted secrets, and
+//! its stderr goes to the terminal; a failing filter aborts bwenv without
+//! showing the value.
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::io::{Read as _, Write as _};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use zeroize::Zeroize as _;
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+/// Filter values through `command` from now on.
+pub fn set(command: &str) {
+    let _ = COMMAND.set(command.to_string());
+}
+
+/// Replace every value in `pairs` (except those `is_encoded` picks out) with
+/// the filter's output.  A no-op without `--value-filter`.
+pub fn apply(
+    pairs: &mut HashMap<String, String>,
+    is_encoded: impl Fn(&String) -> bool,
+) -> Result<()> {
+    let Some(command) = COMMAND.get() else {
+        return Ok(());
+    };
+    let mut keys: Vec<String> = pairs.keys().filter(|k| !is_encoded(k)).cloned().collect();
+    keys.sort();
+    for key in keys {
+        let value = pairs.get_mut(&key).expect("key present");
+        let filtered = run(command, &key, value)
+            .with_context(|| format!("--value-filter failed for `{key}`"))?;
+        value.zeroize();
+        *value = filtered;
+    }
+    Ok(())
+}
+
+fn run(command: &str, key: &str, value: &str) -> Result<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .env("BWENV_KEY", key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("failed to run `sh`")?;
+    let mut stdin = child.stdin.take().context("failed to open filter stdin")?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .context("failed to open filter stdout")?;
+
+    // Feed stdin from a separate thread so a filter that writes before it has
+    // read everything can't deadlock against us.
+    let mut output = Vec::new();
+    let read = std::thread::scope(|scope| {
+        scope.spawn(move || {
+            // A filter that exits without reading is not an error by itself;
+            // its exit status decides.
+            let _ = stdin.write_all(value.as_bytes());
+        });
+        stdout.read_to_end(&mut output)
+    });
+    let status = child.wait().context("failed to wait for the filter")?;
+    if let Err(e) = read {
+        output.zeroize();
+        return Err(e).context("failed to read the filter's output");
+    }
+    if !status.success() {
+        output.zeroize();
+        bail!("filter exited with {status}");
+    }
+    if output.ends_with(b"\n") {
+        output.pop();
+    }
+    String::from_utf8(output).map_err(|e| {
+        e.into_bytes().zeroize();
+        anyhow::anyhow!("filter output is not UTF-8")
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_transforms_value_and_reports_failure() {
+        assert_eq!(run("tr a-z A-Z", "K", "secret").unwrap(), "SECRET");
+        assert_eq!(run("printf '%s' \"$BWENV_KEY\"", "DB", "x").unwrap(), "DB");
+        assert_eq!(run("cat; echo", "K", "v").unwrap(), "v");
+        let err = run("cat >/dev/null; exit 3", "K", "hunter2").unwrap_err();
+        assert!(err.to_string().contains("exit status: 3"));
+        assert!(!err.to_string().contains("hunter2"));
+    }
+}
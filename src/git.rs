@@ -0,0 +1,90 @@
+//! `--from-git`: derive the namespace from the current git checkout.
+//!
+//! The name comes from a template (config `git_namespace`, default
+//! `{repo}/{branch}`) filled in with the repository's directory name and the
+//! checked-out branch, so every branch can have its own namespace
+//! (`myrepo/feature-x`).  Outside a repository, or on a detached HEAD, this
+//! is an error asking for an explicit namespace; bwenv never guesses.
+
+use anyhow::{Result, bail};
+use std::process::{Command, Stdio};
+
+/// Used when the config has no `git_namespace`.
+pub const DEFAULT_TEMPLATE: &str = "{repo}/{branch}";
+
+/// The namespace for the git checkout bwenv runs in.
+pub fn namespace(template: &str) -> Result<String> {
+    let Some(toplevel) = git(&["rev-parse", "--show-toplevel"])? else {
+        bail!("--from-git: not inside a git repository; name the namespace instead");
+    };
+    let repo = std::path::Path::new(&toplevel)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let Some(branch) = git(&["symbolic-ref", "--quiet", "--short", "HEAD"])? else {
+        bail!("--from-git: HEAD is not on a branch (detached); name the namespace instead");
+    };
+    render(template, &repo, &branch)
+}
+
+/// Fill `{repo}` and `{branch}` into `template`.
+fn render(template: &str, repo: &str, branch: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            bail!("unclosed `{{` in git_namespace template {template:?}");
+        };
+        match &rest[start + 1..start + len] {
+            "repo" => out.push_str(repo),
+            "branch" => out.push_str(branch),
+            other => bail!(
+                "unknown placeholder `{{{other}}}` in git_namespace template \
+                 (use {{repo}} and {{branch}})"
+            ),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Run `git args…` and return its trimmed output, or `None` when git fails
+/// (not a repository, no branch).  A missing `git` binary is an error.
+fn git(args: &[&str]) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                anyhow::anyhow!("`git` is required for --from-git but was not found on PATH")
+            } else {
+                anyhow::Error::new(e).context("failed to run git")
+            }
+        })?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!text.is_empty()).then_some(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_template() {
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, "shop", "feature/x").unwrap(),
+            "shop/feature/x"
+        );
+        assert_eq!(render("env-{branch}", "shop", "main").unwrap(), "env-main");
+        assert_eq!(render("fixed", "shop", "main").unwrap(), "fixed");
+        assert!(render("{user}/{branch}", "shop", "main").is_err());
+        assert!(render("{repo", "shop", "main").is_err());
+    }
+}
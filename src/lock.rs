@@ -0,0 +1,112 @@
+//! Per-namespace advisory locks, so two bwenv processes on one machine never
+//! interleave the read-modify-write of the same namespace.
+//!
+//! Write commands take [`namespace`] before reading the entry and hold the
+//! guard until the write is done.  Locks are `flock(2)` locks on files in
+//! `$XDG_CACHE_HOME/bwenv/locks/`; the kernel drops them when the process
+//! exits, so a crash never leaves a namespace locked.  Waiting is bounded by
+//! [`TIMEOUT`].  Locking is best-effort: without a usable cache directory
+//! (or on non-Unix systems) writes proceed unlocked.
+
+use anyhow::Result;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Held lock; released on drop.
+pub struct NamespaceLock {
+    #[cfg(unix)]
+    _file: Option<std::fs::File>,
+}
+
+/// Lock `namespace` in `folder` (under the current rbw profile) for writing,
+/// waiting up to [`TIMEOUT`] for another bwenv process to finish.
+#[cfg(unix)]
+pub fn namespace(folder: &str, namespace: &str) -> Result<NamespaceLock> {
+    use std::os::fd::AsRawFd as _;
+    use std::os::unix::fs::OpenOptionsExt as _;
+
+    let Some(dir) = crate::cache::dir().map(|d| d.join("locks")) else {
+        return Ok(NamespaceLock { _file: None });
+    };
+    let profile = crate::rbw::profile().unwrap_or_default();
+    let path = dir.join(format!(
+        "{}.lock",
+        file_name(&format!("{profile}:{folder}/{namespace}"))
+    ));
+    let opened = crate::cache::create_private_dir(&dir).and_then(|()| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)
+    });
+    let file = match opened {
+        Ok(file) => file,
+        Err(e) => {
+            if crate::verbose() {
+                eprintln!("note: writing `{namespace}` without a lock: {e}");
+            }
+            return Ok(NamespaceLock { _file: None });
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let mut announced = false;
+    // SAFETY: flock on a file descriptor we own for the whole call.
+    while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+            return Err(
+                anyhow::Error::new(err).context(format!("failed to lock {}", path.display()))
+            );
+        }
+        if start.elapsed() >= TIMEOUT {
+            anyhow::bail!(
+                "another bwenv process is still writing `{namespace}`; gave up after {}s (lock: {})",
+                TIMEOUT.as_secs(),
+                path.display()
+            );
+        }
+        if !announced {
+            eprintln!("waiting for another bwenv process writing `{namespace}`…");
+            announced = true;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    Ok(NamespaceLock { _file: Some(file) })
+}
+
+#[cfg(not(unix))]
+pub fn namespace(_folder: &str, _namespace: &str) -> Result<NamespaceLock> {
+    Ok(NamespaceLock {})
+}
+
+/// `key` as a flat file name: ASCII letters, digits, `-`, `_` and `.` are
+/// kept, every other byte becomes `%XX`.
+#[cfg(unix)]
+fn file_name(key: &str) -> String {
+    key.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.') {
+                (b as char).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_are_flat_and_distinct() {
+        assert_eq!(file_name(":bwenv/dev"), "%3Abwenv%2Fdev");
+        assert_ne!(file_name(":a/b/c"), file_name(":a_b/c"));
+        assert!(!file_name("p:work/deep/ns").contains('/'));
+    }
+}
@@ -1,16 +1,79 @@
+mod atomic;
+mod base64;
+mod cache;
+mod child;
+mod complete;
+mod config;
+mod fetch;
+mod filter;
+mod git;
+mod lock;
+mod mask;
+mod pick;
+mod random;
 mod rbw;
+mod redact;
+mod remote;
+mod resolve;
+mod sha256;
+mod spinner;
 mod store;
+mod timings;
+mod tmpfile;
 
 use anyhow::{Context, Result};
 use clap::{CommandFactory, Parser, Subcommand};
-use rpassword::read_password;
+use resolve::STORED_CMD_KEY;
+use rpassword::prompt_password;
 use std::collections::HashMap;
 use std::env;
+use std::io::IsTerminal as _;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use zeroize::Zeroize as _;
 
 const DEFAULT_FOLDER: &str = "bwenv";
 const FOLDER_ENV: &str = "BWENV_FOLDER";
+const PROFILE_ENV: &str = "BWENV_PROFILE";
+const MAX_CONCURRENCY_ENV: &str = "BWENV_MAX_CONCURRENCY";
+const MARK_MANAGED_ENV: &str = "BWENV_MARK_MANAGED";
+
+/// Inherited variables that survive `--clean-env` without an explicit `--keep`.
+const CLEAN_ENV_ALLOWLIST: &[&str] = &["PATH", "HOME"];
+
+/// Variables `snapshot` skips unless `--include-all` is given: machine- and
+/// session-specific values that would be wrong (or noisy) anywhere else.
+const SNAPSHOT_DENYLIST: &[&str] = &[
+    "PATH",
+    "HOME",
+    "PWD",
+    "OLDPWD",
+    "SHELL",
+    "SHLVL",
+    "USER",
+    "LOGNAME",
+    "TERM",
+    "TERM_*",
+    "COLORTERM",
+    "LS_COLORS",
+    "LANG",
+    "LC_*",
+    "TMPDIR",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "SSH_*",
+    "XDG_*",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "TMUX*",
+    "RBW_*",
+    "BWENV_*",
+    "_",
+];
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static STRICT_PARSE: AtomicBool = AtomicBool::new(false);
+static STRICT_KEYS: AtomicBool = AtomicBool::new(false);
+static NO_TRIM: AtomicBool = AtomicBool::new(false);
 
 // ── CLI definition ─────────────────────────────────────────────────────────────
 
@@ -20,18 +83,169 @@ const FOLDER_ENV: &str = "BWENV_FOLDER";
 #[command(about = "Inject Bitwarden secrets (via rbw) as environment variables")]
 #[command(long_about = None)]
 struct Cli {
-    /// Bitwarden folder that holds bwenv namespaces
-    /// [env: BWENV_FOLDER] [default: bwenv]
-    #[arg(long, global = true, value_name = "FOLDER")]
+    /// Bitwarden folder that holds bwenv namespaces, for every subcommand
+    /// [env: BWENV_FOLDER] [default: config "folder", else bwenv]
+    #[arg(short = 'f', long, global = true, value_name = "FOLDER")]
     folder: Option<String>,
 
+    /// Print extra diagnostics to stderr
+    #[arg(long, global = true)]
+    verbose: bool,
+
+    /// Treat questionable notes content (duplicate keys, `export` prefixes) as an error
+    #[arg(long, global = true)]
+    strict_parse: bool,
+
+    /// Refuse to write keys that aren't UPPERCASE_WITH_UNDERSCORES
+    /// [default: config "strict_keys"]
+    #[arg(long, global = true)]
+    strict_keys: bool,
+
+    /// Keep leading/trailing whitespace of unquoted values when reading
+    /// (quoted values are always kept exactly)
+    #[arg(long, global = true, overrides_with = "trim")]
+    no_trim: bool,
+
+    /// Trim whitespace around unquoted values when reading (the default)
+    #[arg(long, global = true, overrides_with = "no_trim")]
+    trim: bool,
+
+    /// Only treat `\n` and `\r\n` as line endings in notes, so a lone `\r`
+    /// stays in the value (by default it ends the line too)
+    #[arg(long, global = true)]
+    keep_cr: bool,
+
+    /// Maximum number of rbw processes to run at once in batch operations
+    /// [env: BWENV_MAX_CONCURRENCY] [default: 4]
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_concurrency: Option<u32>,
+
+    /// rbw profile to use, for separate rbw configurations per account
+    /// (passed to rbw as RBW_PROFILE) [env: BWENV_PROFILE]
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Run this command with the bwenv on [USER@]HOST, over ssh, so the vault
+    /// stays there (see README for what crosses the connection)
+    #[arg(long, global = true, value_name = "[USER@]HOST")]
+    remote: Option<String>,
+
+    /// Read namespaces from this custom field instead of the notes
+    /// (read-only: rbw cannot write custom fields)
+    #[arg(long, global = true, value_name = "NAME")]
+    field: Option<String>,
+
+    /// Also list and read entries that are in the Bitwarden trash
+    #[arg(long, global = true)]
+    include_trashed: bool,
+
+    /// Create the folder if a new namespace goes into one that doesn't
+    /// exist yet (otherwise that is an error)
+    #[arg(long, global = true)]
+    create_folder: bool,
+
+    /// Character masked values are shown with (`env`, `compare`,
+    /// `watch-ns`)
+    #[arg(long, global = true, value_name = "CHAR", default_value_t = '*')]
+    mask_char: char,
+
+    /// Show up to N characters at each end of masked values (never more
+    /// than a quarter of the value each); 0 masks them fully
+    #[arg(long, global = true, value_name = "N", default_value_t = 0)]
+    mask_show: usize,
+
+    /// Pipe each value through this shell command before it is injected,
+    /// previewed or exported (e.g. to decrypt it); the key is in $BWENV_KEY
+    #[arg(long, global = true, value_name = "CMD")]
+    value_filter: Option<String>,
+
+    /// Print how long each rbw call took, and the total, to stderr
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Print every rbw command line bwenv runs, with its duration, to stderr
+    /// (for bug reports; stdin contents are never shown)
+    #[arg(long, global = true)]
+    trace_rbw: bool,
+
+    /// Fail instead of prompting when the vault is locked (see `bwenv unlock`)
+    #[arg(long, global = true)]
+    no_unlock: bool,
+
+    /// Kill any rbw call (other than the unlock prompt) that takes longer
+    /// than this many seconds [default: no limit]
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Seconds `rbw unlock` may wait for the master password; 0 for no limit
+    #[arg(long, global = true, value_name = "SECS", default_value_t = rbw::DEFAULT_UNLOCK_TIMEOUT)]
+    timeout_unlock: u64,
+
+    /// Run `rbw sync` before reading, falling back to the local copy of the
+    /// vault (with a warning) when the server can't be reached
+    #[arg(long, global = true)]
+    sync: bool,
+
+    /// Like --sync, but fail when the server can't be reached
+    #[arg(long, global = true)]
+    require_sync: bool,
+
+    /// Never sync: work from rbw's local copy of the vault (also for `watch-ns`)
+    #[arg(long, global = true, conflicts_with_all = ["sync", "require_sync"])]
+    offline: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// Start the command with an empty environment (plus PATH, HOME and any
+    /// --keep variables) instead of inheriting bwenv's (for exec mode)
+    #[arg(long)]
+    clean_env: bool,
+
+    /// Inherited variable to preserve with --clean-env (repeatable)
+    #[arg(long, value_name = "VAR", requires = "clean_env")]
+    keep: Vec<String>,
+
+    /// Local dotenv file layered over the namespace; its values win (for exec mode)
+    #[arg(long, value_name = "PATH")]
+    env_file: Option<std::path::PathBuf>,
+
+    /// Mask injected secret values as `***` in the command's output (for exec mode)
+    #[arg(long)]
+    redact: bool,
+
+    /// Pass `KEY!=<base64>` values to the command as their decoded bytes (for exec mode)
+    #[arg(long)]
+    decode: bool,
+
+    /// Resolve `${VAR}` in values from bwenv's own environment (for exec mode)
+    #[arg(long)]
+    expand_from_env: bool,
+
+    /// With --expand-from-env, expand unset variables to "" instead of failing
+    #[arg(long, requires = "expand_from_env")]
+    allow_missing: bool,
+
+    /// Fail instead of running the command when there are no variables to
+    /// inject, e.g. for an empty or mistyped namespace (for exec mode)
+    #[arg(long)]
+    fail_empty: bool,
+
     /// Namespace (for exec mode)
     #[arg(value_name = "NAMESPACE")]
     namespace: Option<String>,
 
+    /// Take the namespace from the current git repository and branch (config
+    /// "git_namespace", default `{repo}/{branch}`); the first argument is then
+    /// the command (for exec mode)
+    #[arg(long)]
+    from_git: bool,
+
+    /// Run the command stored in the namespace's `__cmd` key (with `sh -c`)
+    /// instead of one given on the command line (for exec mode)
+    #[arg(long, conflicts_with = "exec_command")]
+    use_stored_cmd: bool,
+
     /// Command to execute (for exec mode)
     #[arg(value_name = "PROG", requires = "namespace")]
     exec_command: Option<String>,
@@ -48,18 +262,170 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Unlock the vault (prompting if needed) and exit
+    Unlock,
+
+    /// Start an interactive shell ($SHELL) with namespaces injected
+    Shell {
+        /// Namespace(s) to inject, comma-separated (later ones win)
+        #[arg(required_unless_present_any = ["select", "from_git"])]
+        namespace: Option<String>,
+
+        /// Pick the namespace from a menu (interactive terminals only)
+        #[arg(long, conflicts_with = "namespace")]
+        select: bool,
+
+        /// Use the namespace for the current git repository and branch
+        #[arg(long, conflicts_with_all = ["namespace", "select"])]
+        from_git: bool,
+
+        /// Lock the vault when the shell exits
+        #[arg(long)]
+        lock: bool,
+    },
+
+    /// Show the environment `bwenv <namespace> <command>` would inject,
+    /// without running anything (values masked)
+    Env {
+        /// Namespace(s) to merge, in order (later ones win); commas work too
+        #[arg(required_unless_present_any = ["select", "from_git"], value_name = "NAMESPACE")]
+        namespaces: Vec<String>,
+
+        /// Pick the namespace from a menu (interactive terminals only)
+        #[arg(long, conflicts_with = "namespaces")]
+        select: bool,
+
+        /// Use the namespace for the current git repository and branch
+        #[arg(long, conflicts_with_all = ["namespaces", "select"])]
+        from_git: bool,
+
+        /// Overlay a local env file on top, as with the top-level `--env-file`
+        #[arg(long, value_name = "PATH")]
+        env_file: Option<std::path::PathBuf>,
+
+        /// Print the values instead of masking them
+        #[arg(long)]
+        show_values: bool,
+    },
+
     /// Set (create or update) environment variable keys in a namespace
     Set {
         /// Namespace to store variables in
         namespace: String,
 
-        /// Environment variable names to set
-        #[arg(required = true)]
+        /// Keys to set: `KEY` prompts for the value (without echo), `KEY=VALUE`
+        /// takes it inline, `KEY+=VALUE` appends to the current value
+        #[arg(required = true, value_name = "KEY[=VALUE]")]
         vars: Vec<String>,
 
-        /// Do not echo user input
-        #[arg(short, long)]
+        /// Append every value to the key's current one instead of replacing it
+        /// (as `KEY+=VALUE` does for a single key)
+        #[arg(long, conflicts_with = "encode")]
+        append: bool,
+
+        /// Separator put between the current value and an appended one
+        #[arg(long, value_name = "SEP", default_value = ":")]
+        sep: String,
+
+        /// Write nothing; exit non-zero if the keys don't already have these
+        /// values
+        #[arg(long, conflicts_with = "encode")]
+        check: bool,
+
+        /// Only set keys the namespace doesn't have yet; existing values are
+        /// left untouched (and not prompted for)
+        #[arg(long, conflicts_with_all = ["append", "check", "encode"])]
+        if_missing: bool,
+
+        /// With --if-missing, fail without writing anything if any of the
+        /// keys already exists
+        #[arg(long, requires = "if_missing")]
+        error_if_exists: bool,
+
+        /// Read the value of the single KEY from this file (one final line
+        /// ending is dropped)
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["stdin", "encode"])]
+        value_file: Option<std::path::PathBuf>,
+
+        /// With --value-file, also trim whitespace around the value
+        #[arg(long, requires = "value_file")]
+        trim_file: bool,
+
+        /// Read values for bare `KEY`s from stdin, one line each, instead of
+        /// prompting on the terminal
+        #[arg(long)]
+        stdin: bool,
+
+        /// No effect: prompted values are never echoed
+        #[arg(short, long, hide = true)]
         noecho: bool,
+
+        /// Store the contents of this file, base64-encoded, as the single KEY
+        /// (written as `KEY!=<base64>`; read back with --decode)
+        #[arg(long, value_name = "PATH", conflicts_with = "stdin")]
+        encode: Option<std::path::PathBuf>,
+    },
+
+    /// Store stdin or a file verbatim as a namespace's notes, without
+    /// KEY=VALUE parsing (e.g. a JSON config or a PEM)
+    SetRaw {
+        /// Namespace to write (created if missing)
+        namespace: String,
+
+        /// Read the content from stdin
+        #[arg(long, required_unless_present = "input")]
+        stdin: bool,
+
+        /// Read the content from this file
+        #[arg(short, long, value_name = "PATH", conflicts_with = "stdin")]
+        input: Option<std::path::PathBuf>,
+    },
+
+    /// Copy one key (value, description and encoding) to another namespace
+    CpKey {
+        /// Namespace to copy from
+        source: String,
+
+        /// Namespace to copy into (created if missing)
+        destination: String,
+
+        /// Key to copy
+        key: String,
+
+        /// Remove the key from the source afterwards
+        #[arg(long = "move")]
+        move_key: bool,
+    },
+
+    /// Replace a key's value with a newly generated random one
+    Rotate {
+        /// Namespace holding the key
+        namespace: String,
+
+        /// Key to regenerate
+        key: String,
+
+        /// Length of the new value, in characters
+        #[arg(long, default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..=4096))]
+        length: u32,
+
+        /// Characters to draw from
+        #[arg(long, value_enum, default_value_t = random::Charset::Alnum)]
+        charset: random::Charset,
+
+        /// Print the new value to stdout (once; it is not shown again)
+        #[arg(long)]
+        print: bool,
+    },
+
+    /// Set KEY=VALUE pairs only where they differ from what is stored
+    Ensure {
+        /// Namespace to store variables in
+        namespace: String,
+
+        /// Assignments in KEY=VALUE form
+        #[arg(required = true, value_name = "KEY=VALUE")]
+        assignments: Vec<String>,
     },
 
     /// List namespaces, or list keys in a namespace
@@ -67,9 +433,59 @@ enum Commands {
         /// Namespace to list keys from (lists all namespaces if omitted)
         namespace: Option<String>,
 
+        /// Pick the namespace from a menu (interactive terminals only)
+        #[arg(long, conflicts_with = "namespace")]
+        select: bool,
+
+        /// Use the namespace for the current git repository and branch
+        #[arg(long, conflicts_with_all = ["namespace", "select"])]
+        from_git: bool,
+
         /// Show values alongside keys
         #[arg(short = 'v', long)]
         show_value: bool,
+
+        /// List namespaces in every folder, grouped by folder
+        #[arg(long, conflicts_with_all = ["namespace", "show_value", "null", "sort", "reverse", "filter", "json", "count", "porcelain"])]
+        all: bool,
+
+        /// Only list namespaces whose name matches this glob (`*`, `?`)
+        #[arg(long, value_name = "GLOB", conflicts_with = "namespace")]
+        filter: Option<String>,
+
+        /// Print namespaces as a JSON array of `{id, name, type, folder}` objects
+        #[arg(long, conflicts_with_all = ["namespace", "null"])]
+        json: bool,
+
+        /// Print only the number of namespaces (or keys), after `--filter`
+        #[arg(long, conflicts_with_all = ["show_value", "null", "json"])]
+        count: bool,
+
+        /// Stable tab-separated output for scripts (see README, "Porcelain output")
+        #[arg(long, conflicts_with_all = ["show_value", "null", "json", "count"])]
+        porcelain: bool,
+
+        /// Sort namespaces by this field (ties broken by name); keys are always
+        /// sorted by name
+        #[arg(long, value_enum, default_value_t = SortKey::Name)]
+        sort: SortKey,
+
+        /// Reverse the sort order
+        #[arg(short, long)]
+        reverse: bool,
+
+        /// Separate entries with NUL instead of newline (for `xargs -0`)
+        #[arg(short = '0', long)]
+        null: bool,
+
+        /// Show namespaces as a tree, nested by the parts of their names
+        #[arg(long, conflicts_with_all = ["namespace", "null", "json", "count", "porcelain", "sort", "reverse"])]
+        tree: bool,
+
+        /// With --tree, split names on this instead of `/`
+        /// [default: config "namespace_separator", else `/`]
+        #[arg(long, value_name = "SEP", requires = "tree")]
+        namespace_separator: Option<String>,
     },
 
     /// Remove keys from a namespace
@@ -81,278 +497,4826 @@ enum Commands {
         #[arg(required = true)]
         vars: Vec<String>,
     },
-}
 
-// ── Command implementations ────────────────────────────────────────────────────
+    /// Import keys from a dotenv or JSON file, stdin, or an HTTPS URL
+    Import {
+        /// Namespace to import into (created if missing)
+        namespace: String,
 
-fn cmd_exec(folder: &str, namespaces: &[String], cmd: &str, args: &[String]) -> Result<()> {
-    validate_identifier(folder, "folder")?;
-    for ns in namespaces {
-        validate_identifier(ns, "namespace")?;
-    }
+        /// File to read, `-` for stdin (the default without --url)
+        #[arg(short, long, value_name = "PATH", conflicts_with = "url")]
+        input: Option<std::path::PathBuf>,
 
-    // Unlock once up front so parallel fetches below don't each race to prompt.
-    rbw::unlock()?;
+        /// Download the content over HTTPS instead (uses `curl`)
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
 
-    // Build (name, folder) pairs and fetch all namespaces concurrently.
-    let requests: Vec<(String, String)> = namespaces
-        .iter()
-        .map(|ns| (ns.clone(), folder.to_string()))
-        .collect();
+        /// Request header for --url, e.g. `Authorization: Bearer …` (repeatable)
+        #[arg(long = "header", value_name = "NAME: VALUE", requires = "url")]
+        headers: Vec<String>,
 
-    let results = rbw::get_items(&requests);
+        /// Format of the input
+        #[arg(long, value_enum, default_value_t = ImportFormat::Dotenv)]
+        format: ImportFormat,
 
-    // Merge env pairs across namespaces in order; last namespace wins on conflict.
-    // Track which namespace first defined each key so we can warn accurately.
-    let mut merged: HashMap<String, String> = HashMap::new();
-    let mut origins: HashMap<String, String> = HashMap::new();
-    for (ns, result) in namespaces.iter().zip(results) {
-        let item =
-            result?.with_context(|| format!("namespace `{ns}` not found in folder `{folder}`"))?;
-        let ns_pairs: HashMap<String, String> =
-            item.notes.as_deref().map(store::parse).unwrap_or_default();
-        for (k, v) in ns_pairs {
-            if let Some(prev_ns) = origins.get(&k) {
-                eprintln!(
-                    "warning: key \"{k}\" defined in both \"{prev_ns}\" and \"{ns}\"; \
-                     using value from \"{ns}\""
-                );
-            }
-            merged.insert(k.clone(), v);
-            origins.insert(k, ns.clone());
-        }
-    }
+        /// Keep existing keys the input doesn't mention (by default they are
+        /// removed, so the namespace matches the input)
+        #[arg(long)]
+        merge: bool,
 
-    let mut pairs = merged;
+        /// Show which keys would be added, changed or removed, without writing
+        #[arg(long)]
+        dry_run: bool,
 
-    // SAFETY: single-threaded at this point; no other thread reads the env.
-    for (k, v) in &pairs {
-        unsafe { env::set_var(k, v) };
-    }
-    // Zero secret values from the in-process copy now that env vars are set.
-    for v in pairs.values_mut() {
-        v.zeroize();
-    }
+        /// With --dry-run, print the changes in the stable porcelain format
+        #[arg(long, requires = "dry_run")]
+        porcelain: bool,
 
-    // Replace current process with the target command (Unix exec semantics).
-    #[cfg(unix)]
-    {
-        use std::os::unix::process::CommandExt as _;
-        let err = Command::new(cmd).args(args).exec();
-        Err(anyhow::Error::from(err).context(format!("exec failed: {cmd}")))
-    }
-    #[cfg(not(unix))]
-    {
-        let status = Command::new(cmd)
-            .args(args)
-            .status()
-            .with_context(|| format!("failed to run {cmd}"))?;
-        std::process::exit(status.code().unwrap_or(1));
-    }
-}
+        /// Write nothing; exit non-zero if importing would change the namespace
+        #[arg(long, conflicts_with = "dry_run")]
+        check: bool,
 
-fn cmd_set(folder: &str, namespace: &str, vars: &[String], noecho: bool) -> Result<()> {
-    validate_identifier(folder, "folder")?;
-    validate_identifier(namespace, "namespace")?;
-    let (existing_notes, is_new, is_secure_note) = match existing_notes(folder, namespace)? {
-        Some((notes, secure)) => (notes, false, secure),
-        None => (String::new(), true, false),
-    };
+        /// Rewrite only the lines of keys whose value changes, leaving the
+        /// rest of the notes byte for byte, and list the keys written
+        #[arg(long)]
+        only_changed: bool,
+    },
 
-    let mut notes = existing_notes;
-    for key in vars {
-        let prompt = format!("{namespace}.{key}");
-        let mut value: String = if noecho {
-            eprint!("{prompt} (noecho): ");
-            read_password().context("failed to read password")?
-        } else {
-            eprint!("{prompt}: ");
-            let mut buf = String::new();
-            std::io::stdin()
-                .read_line(&mut buf)
-                .context("failed to read line")?;
-            buf.trim_end_matches(['\n', '\r']).to_string()
-        };
-        notes = store::update(&notes, key, &value);
-        // Zero the secret value in memory before it is dropped.
-        value.zeroize();
-    }
+    /// Import many namespaces from one JSON object of the form
+    /// `{"ns1": {"KEY": "value", …}, "ns2": {…}}`
+    ImportAll {
+        /// File to read, `-` for stdin
+        #[arg(short, long, value_name = "PATH", default_value = "-")]
+        input: std::path::PathBuf,
 
-    let result = write_namespace(folder, namespace, &notes, is_new, is_secure_note);
-    // Zero the notes string (contains all secret values) before returning.
-    notes.zeroize();
-    result
-}
+        /// Format of the input (only `json` holds several namespaces)
+        #[arg(long, value_enum, default_value_t = ImportFormat::Json)]
+        format: ImportFormat,
 
-fn cmd_list(folder: &str, namespace: Option<&str>, show_value: bool) -> Result<()> {
-    validate_identifier(folder, "folder")?;
-    if let Some(ns) = namespace {
-        validate_identifier(ns, "namespace")?;
-    }
-    match namespace {
-        None => {
-            let mut names = rbw::list_namespaces(folder)?;
-            names.sort();
-            for name in names {
-                println!("{name}");
-            }
-        }
-        Some(ns) => {
-            let pairs = load_env_pairs(folder, ns)?;
-            if pairs.is_empty() {
-                eprintln!(
-                    "WARNING: namespace `{ns}` not found or empty.\n\
-                     You can set variables via: bwenv set {ns} SOME_VAR"
-                );
-                return Ok(());
-            }
-            let mut keys: Vec<&String> = pairs.keys().collect();
-            keys.sort();
-            for key in keys {
-                if show_value {
-                    println!("{}={}", key, pairs[key]);
-                } else {
-                    println!("{key}");
-                }
-            }
-        }
-    }
-    Ok(())
-}
+        /// Keep existing keys the input doesn't mention (by default they are
+        /// removed, so each namespace matches the input)
+        #[arg(long)]
+        merge: bool,
 
-fn cmd_unset(folder: &str, namespace: &str, vars: &[String]) -> Result<()> {
-    validate_identifier(folder, "folder")?;
-    validate_identifier(namespace, "namespace")?;
-    let (existing, is_secure_note) = existing_notes(folder, namespace)?
-        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+        /// Show which keys would be added, changed or removed, without writing
+        #[arg(long)]
+        dry_run: bool,
 
-    let mut notes = existing;
-    for key in vars {
-        match store::remove(&notes, key) {
-            Some(updated) => notes = updated,
-            None => eprintln!("WARNING: key `{key}` not found in namespace `{namespace}`"),
-        }
-    }
+        /// Write nothing; exit non-zero if importing would change any namespace
+        #[arg(long, conflicts_with = "dry_run")]
+        check: bool,
 
-    write_namespace(folder, namespace, &notes, false, is_secure_note)?;
+        /// Rewrite only the lines of keys whose value changes, leaving the
+        /// rest of the notes byte for byte, and list the keys written
+        #[arg(long)]
+        only_changed: bool,
+    },
 
-    // If all keys have been removed, delete the entry entirely.
-    if store::parse(&notes).is_empty() {
-        rbw::delete_item(namespace, folder)?;
-        eprintln!("namespace `{namespace}` is now empty and has been removed");
-    }
+    /// Upload a local dotenv file into a namespace (replacing its keys)
+    Push {
+        /// Namespace to write (created if missing)
+        namespace: String,
 
-    Ok(())
-}
+        /// Dotenv file to read
+        #[arg(short, long, value_name = "PATH", default_value = ".env")]
+        input: std::path::PathBuf,
 
-// ── Helpers ────────────────────────────────────────────────────────────────────
+        /// Keep keys that are in the namespace but not in the file
+        #[arg(long)]
+        merge: bool,
 
-/// Reject values that could be misinterpreted as `rbw` flags or that contain
-/// characters unsafe to pass as CLI arguments.
-///
-/// Rules:
-/// - Must not be empty.
-/// - Must not start with `-` (would be parsed as a flag by rbw).
-/// - Must not contain a null byte (undefined behaviour in argv).
-fn validate_identifier(value: &str, label: &str) -> Result<()> {
-    if value.is_empty() {
-        anyhow::bail!("{label} must not be empty");
-    }
-    if value.starts_with('-') {
-        anyhow::bail!("{label} must not start with '-': {value:?}");
-    }
-    if value.contains('\0') {
-        anyhow::bail!("{label} must not contain null bytes");
-    }
-    if value.contains(',') {
-        anyhow::bail!("{label} must not contain commas: {value:?}");
-    }
-    Ok(())
-}
+        /// Show which keys would be added, changed or removed, without writing
+        #[arg(long)]
+        dry_run: bool,
 
-/// Resolve the folder: CLI flag > env var > default.
-fn resolve_folder(cli_folder: Option<&str>) -> String {
-    cli_folder
-        .map(str::to_string)
-        .or_else(|| env::var(FOLDER_ENV).ok())
-        .unwrap_or_else(|| DEFAULT_FOLDER.to_string())
-}
+        /// Write nothing; exit non-zero if pushing would change the namespace
+        #[arg(long, conflicts_with = "dry_run")]
+        check: bool,
+    },
 
-/// Load env pairs for a namespace from the notes-field KEY=VALUE lines.
-fn load_env_pairs(folder: &str, namespace: &str) -> Result<HashMap<String, String>> {
-    let item = rbw::get_item(namespace, folder)?
-        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    /// Download a namespace into a local dotenv file (mode 0600)
+    Pull {
+        /// Namespace to read
+        namespace: String,
 
-    Ok(item.notes.as_deref().map(store::parse).unwrap_or_default())
-}
+        /// File to write (replaced atomically)
+        #[arg(short, long, value_name = "PATH", default_value = ".env")]
+        output: std::path::PathBuf,
+    },
 
-/// Return the current notes content for a namespace, or `None` if it does not
-/// exist. Also returns whether the item is a SecureNote.
-fn existing_notes(folder: &str, namespace: &str) -> Result<Option<(String, bool)>> {
-    match rbw::get_item(namespace, folder)? {
-        Some(item) => {
-            let is_secure_note = item.item_type.as_deref() == Some("Note");
-            Ok(Some((item.notes.unwrap_or_default(), is_secure_note)))
-        }
-        None => Ok(None),
-    }
-}
+    /// Save the current process environment into a namespace (replacing it)
+    Snapshot {
+        /// Namespace to write the environment into
+        namespace: String,
 
-/// Write (create or edit) a namespace note.
-fn write_namespace(
-    folder: &str,
-    namespace: &str,
-    notes: &str,
-    is_new: bool,
-    is_secure_note: bool,
-) -> Result<()> {
-    if is_new {
-        rbw::create_item(namespace, folder, notes)
-    } else {
-        rbw::edit_item(namespace, folder, notes, is_secure_note)
-    }
-}
+        /// Only capture variables matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        only: Vec<String>,
 
-// ── Entry point ────────────────────────────────────────────────────────────────
+        /// Skip variables matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        except: Vec<String>,
 
-fn main() {
-    if let Err(e) = run() {
-        eprintln!("error: {e:#}");
-        std::process::exit(1);
-    }
-}
+        /// Also capture system variables that are skipped by default (PATH, HOME, …)
+        #[arg(long)]
+        include_all: bool,
+    },
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
-    let folder = resolve_folder(cli.folder.as_deref());
+    /// Print a namespace as `export KEY='VALUE'` lines for `eval`
+    Export {
+        /// Namespace to export
+        namespace: String,
 
-    if let Some(command) = cli.command {
-        match command {
-            Commands::Set {
-                namespace,
-                vars,
-                noecho,
-            } => cmd_set(&folder, &namespace, &vars, noecho),
+        /// Write to this inherited file descriptor instead of stdout (Unix)
+        #[arg(long, value_name = "N", conflicts_with = "output_file")]
+        fd: Option<i32>,
 
-            Commands::List {
-                namespace,
-                show_value,
-            } => cmd_list(&folder, namespace.as_deref(), show_value),
+        /// Write to this file instead of stdout (atomically, mode 0600);
+        /// repeatable, and `FORMAT:PATH` picks the format for that file
+        #[arg(short, long, value_name = "[FORMAT:]PATH")]
+        output_file: Vec<String>,
 
-            Commands::Unset { namespace, vars } => cmd_unset(&folder, &namespace, &vars),
-        }
-    } else if let (Some(namespace_arg), Some(command)) = (cli.namespace, cli.exec_command) {
-        let namespaces: Vec<String> = namespace_arg
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
+        /// With --output, write to stdout as well
+        #[arg(long, requires = "output_file")]
+        also_stdout: bool,
+
+        /// Export `KEY!=<base64>` values as their decoded bytes
+        #[arg(long)]
+        decode: bool,
+
+        /// Resolve `${VAR}` in values from bwenv's own environment
+        #[arg(long)]
+        expand_from_env: bool,
+
+        /// With --expand-from-env, expand unset variables to "" instead of failing
+        #[arg(long, requires = "expand_from_env")]
+        allow_missing: bool,
+
+        /// Output format [default: config "export_formats" for the namespace,
+        /// else shell]
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// With `--format env-json`, emit values that read as JSON numbers,
+        /// booleans, null, arrays or objects as such (see README)
+        #[arg(long)]
+        infer_types: bool,
+
+        /// Leave out the newline after the last line
+        #[arg(long)]
+        no_trailing_newline: bool,
+
+        /// Fail, writing nothing, when the namespace has no variables
+        #[arg(long)]
+        fail_empty: bool,
+
+        /// Keys in the order they are stored in instead of sorted (a key
+        /// defined twice stays where it first appears)
+        #[arg(long, conflicts_with = "sorted")]
+        preserve_order: bool,
+
+        /// Keys sorted by name, for stable diffs (the default)
+        #[arg(long)]
+        sorted: bool,
+
+        /// With `--format shell`, write each key's `# desc:` comment above
+        /// its line (see `describe`)
+        #[arg(long)]
+        keep_descriptions: bool,
+    },
+
+    /// Create a new namespace, optionally with the keys of an existing one
+    Create {
+        /// Namespace to create (must not exist yet)
+        namespace: String,
+
+        /// Start from this namespace's keys, with empty values
+        #[arg(long, value_name = "NAMESPACE")]
+        from_namespace: Option<String>,
+
+        /// With --from-namespace, copy the values too
+        #[arg(long, requires = "from_namespace")]
+        with_values: bool,
+    },
+
+    /// Write a namespace's keys with empty values (e.g. for `.env.example`)
+    Scaffold {
+        /// Namespace whose keys to list
+        namespace: String,
+
+        /// File to write instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+    },
+
+    /// Show or set the description of a key (stored as a `# desc:` comment)
+    Describe {
+        /// Namespace containing the key
+        namespace: String,
+
+        /// Key to describe
+        key: String,
+
+        /// New description (omit to print the current one, "" to clear)
+        text: Option<String>,
+    },
+
+    /// Exit 0 if a namespace (and optionally a key in it) exists, 1 if not;
+    /// prints nothing
+    Exists {
+        /// Namespace to look for
+        namespace: String,
+
+        /// Also require this key to be set in the namespace
+        #[arg(long, value_name = "KEY")]
+        key: Option<String>,
+    },
+
+    /// Print a SHA-256 fingerprint of a namespace's keys and values, for
+    /// detecting drift without revealing values (see README for the format)
+    Fingerprint {
+        /// Namespace to fingerprint
+        namespace: String,
+    },
+
+    /// Show metadata about a namespace (type, key count, last modified)
+    Info {
+        /// Namespace to inspect
+        #[arg(required_unless_present_any = ["select", "from_git"])]
+        namespace: Option<String>,
+
+        /// Pick the namespace from a menu (interactive terminals only)
+        #[arg(long, conflicts_with = "namespace")]
+        select: bool,
+
+        /// Use the namespace for the current git repository and branch
+        #[arg(long, conflicts_with_all = ["namespace", "select"])]
+        from_git: bool,
+    },
+
+    /// Show a namespace's revision timestamps, newest first (Bitwarden keeps
+    /// no history of the notes, so there are no per-revision diffs)
+    Log {
+        /// Namespace to inspect
+        namespace: String,
+    },
+
+    /// Run a command once per namespace in a folder, each with that namespace
+    /// injected (exec-mode flags such as --clean-env apply to every run)
+    ExecEach {
+        /// Folder whose namespaces to run over
+        #[arg(value_name = "FOLDER")]
+        target: String,
+
+        /// Only run for namespaces whose name matches this glob (`*`, `?`)
+        #[arg(long, value_name = "GLOB")]
+        filter: Option<String>,
+
+        /// Run up to N namespaces at once instead of one after another
+        #[arg(short, long, value_name = "N", default_value_t = 1,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        jobs: u32,
+
+        /// Command and arguments to run (put them after `--`)
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND"
+        )]
+        command: Vec<String>,
+    },
+
+    /// Run a command with a namespace (or one value) written to a temporary
+    /// file, for tools that only read secrets from a path; the file is
+    /// removed when the command exits
+    ///
+    /// The file goes under $XDG_RUNTIME_DIR, or /dev/shm without it, so it
+    /// stays in memory.  macOS has neither: set XDG_RUNTIME_DIR to a RAM disk
+    /// there.
+    WithFile {
+        /// Namespace to write
+        namespace: String,
+
+        /// Write only this key's value (decoded, if stored as `KEY!=`)
+        #[arg(long, value_name = "KEY")]
+        key: Option<String>,
+
+        /// Format of the whole namespace in the file
+        #[arg(long, value_enum, default_value_t = ExportFormat::Shell, conflicts_with = "key")]
+        format: ExportFormat,
+
+        /// Variable the command finds the file's path in
+        #[arg(long = "as", value_name = "VAR", default_value = "BWENV_FILE")]
+        var: String,
+
+        /// File name, for tools that look at the extension [default: the
+        /// key, else `env` or `env.json`]
+        #[arg(long, value_name = "NAME")]
+        name: Option<String>,
+
+        /// Command and arguments to run (put them after `--`)
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND"
+        )]
+        command: Vec<String>,
+    },
+
+    /// Check that every namespace in a folder parses cleanly (exits non-zero
+    /// if any has problems; --strict-parse also fails on duplicates/`export`)
+    Verify {
+        /// Folder whose namespaces to check
+        #[arg(value_name = "FOLDER")]
+        target: String,
+
+        /// Stable tab-separated output for scripts (see README, "Porcelain output")
+        #[arg(long)]
+        porcelain: bool,
+    },
+
+    /// Show which Bitwarden account rbw is configured for
+    Whoami,
+
+    /// Poll a namespace (syncing with the server) and print what changes,
+    /// until interrupted
+    WatchNs {
+        /// Namespace to watch
+        namespace: String,
+
+        /// Seconds between polls
+        #[arg(long, value_name = "SECS", default_value_t = 10,
+              value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Print the old and new values instead of masking them
+        #[arg(long)]
+        show_values: bool,
+    },
+
+    /// Show which keys differ between two namespaces (values masked)
+    Compare {
+        /// Namespace to compare from (e.g. staging)
+        left: String,
+
+        /// Namespace to compare to (e.g. prod); defaults to LEFT when
+        /// comparing across profiles
+        #[arg(required_unless_present_any = ["left_profile", "right_profile"])]
+        right: Option<String>,
+
+        /// rbw profile to read LEFT from (default: the one in effect)
+        #[arg(long, value_name = "PROFILE")]
+        left_profile: Option<String>,
+
+        /// rbw profile to read RIGHT from (default: the one in effect)
+        #[arg(long, value_name = "PROFILE")]
+        right_profile: Option<String>,
+
+        /// Print the differing values instead of masking them
+        #[arg(long)]
+        show_values: bool,
+
+        /// Print the differences as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Open a namespace's entry in the Bitwarden web vault
+    Open {
+        /// Namespace to open
+        #[arg(required_unless_present_any = ["select", "from_git"])]
+        namespace: Option<String>,
+
+        /// Pick the namespace from a menu (interactive terminals only)
+        #[arg(long, conflicts_with = "namespace")]
+        select: bool,
+
+        /// Use the namespace for the current git repository and branch
+        #[arg(long, conflicts_with_all = ["namespace", "select"])]
+        from_git: bool,
+
+        /// Only print the URL instead of launching a browser
+        #[arg(long)]
+        print: bool,
+    },
+
+    /// Print a shell completion script (completes namespaces and key names)
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: complete::Shell,
+    },
+
+    /// Completion candidates for the words typed so far (used by the scripts)
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+
+    /// Merge several namespaces into one (later sources win)
+    MergeNamespaces {
+        /// Namespace to write the union into (its existing keys are kept
+        /// unless a source overrides them)
+        destination: String,
+
+        /// Namespaces to merge, in order
+        #[arg(required = true)]
+        sources: Vec<String>,
+
+        /// Stop without writing if any key has different values in two places
+        #[arg(long)]
+        fail_on_conflict: bool,
+
+        /// Delete the source namespaces after the merged one is written
+        #[arg(long)]
+        prune: bool,
+    },
+
+    /// Delete every namespace in a folder (asks for confirmation)
+    DeleteAll {
+        /// Folder whose namespaces will be deleted
+        #[arg(value_name = "FOLDER")]
+        target: String,
+
+        /// Only delete namespaces whose name matches this glob (`*`, `?`)
+        #[arg(long, value_name = "GLOB")]
+        filter: Option<String>,
+
+        /// Skip the confirmation prompt (required when stdin is not a terminal)
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Stop at the first failed deletion instead of continuing
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Delete the namespaces in a folder that hold no keys (asks for
+    /// confirmation)
+    Clean {
+        /// Folder to clean up
+        #[arg(value_name = "FOLDER")]
+        target: String,
+
+        /// Only list the namespaces that would be deleted
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Skip the confirmation prompt (required when stdin is not a terminal)
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortKey {
+    Name,
+    Type,
+    Folder,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ImportFormat {
+    /// `KEY=VALUE` lines, as stored in namespaces
+    Dotenv,
+    /// A flat JSON object of string values
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ExportFormat {
+    /// `export KEY='VALUE'` lines for `eval`
+    Shell,
+    /// A JSON object of the values (strings, unless --infer-types)
+    EnvJson,
+}
+
+// ── Command implementations ────────────────────────────────────────────────────
+
+/// Exec-mode options that shape the child's environment.
+struct ExecOptions {
+    clean_env: bool,
+    keep: Vec<String>,
+    env_file: Option<std::path::PathBuf>,
+    redact: bool,
+    decode: bool,
+    /// `--expand-from-env`; `Some(allow_missing)` when enabled.
+    expand: Option<bool>,
+    /// `--fail-empty`: refuse to run with nothing to inject.
+    fail_empty: bool,
+}
+
+fn cmd_exec(
+    folder: &str,
+    namespaces: &[String],
+    cmd: &str,
+    args: &[String],
+    opts: &ExecOptions,
+) -> Result<()> {
+    let (command, redactor) = prepare_command(folder, namespaces, cmd, args, opts)?;
+    run_prepared(command, redactor)
+}
+
+/// `--use-stored-cmd`: exec mode with the command taken from the
+/// [`STORED_CMD_KEY`] of the merged namespaces, run by the shell.
+fn cmd_exec_stored(folder: &str, namespaces: &[String], opts: &ExecOptions) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    for ns in namespaces {
+        validate_namespace_entry(ns)?;
+    }
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    if find_executable(shell).is_none() {
+        anyhow::bail!("command not found: {shell}");
+    }
+    let overlay = read_overlay(opts.env_file.as_deref())?;
+    let resolved = resolve_env(folder, namespaces, overlay, opts.expand)?;
+    let Some(stored) = resolved.pairs.get(STORED_CMD_KEY) else {
+        anyhow::bail!(
+            "--use-stored-cmd: `{}` has no `{STORED_CMD_KEY}` key",
+            namespaces.join(",")
+        );
+    };
+    if resolved.encoded.contains(STORED_CMD_KEY) || stored.trim().is_empty() {
+        anyhow::bail!("--use-stored-cmd: `{STORED_CMD_KEY}` must be a non-empty text value");
+    }
+    if verbose() {
+        eprintln!(
+            "running `{STORED_CMD_KEY}` from {}",
+            resolved.origins[STORED_CMD_KEY]
+        );
+    }
+    let args = [flag.to_string(), stored.clone()];
+    let (command, redactor) = build_command(shell, &args, resolved, opts)?;
+    run_prepared(command, redactor)
+}
+
+/// Start a command from [`prepare_command`]: in place of bwenv, or as a
+/// child when its output needs redacting.
+fn run_prepared(mut command: Command, redactor: Option<redact::Redactor>) -> Result<()> {
+    match redactor {
+        // bwenv must stay in the middle of the child's output to redact it.
+        Some(redactor) => {
+            timings::report();
+            std::process::exit(child::run_redacted(&mut command, redactor)?)
+        }
+        None => exec_replace(command),
+    }
+}
+
+fn cmd_exec_each(
+    folder: &str,
+    filter: Option<&str>,
+    jobs: usize,
+    command: &[String],
+    opts: &ExecOptions,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    let (cmd, args) = command.split_first().context("no command given")?;
+    let mut names: Vec<String> = rbw::list_items(folder)?
+        .into_iter()
+        .map(|i| i.name)
+        .filter(|name| filter.is_none_or(|p| glob_match(p, name)))
+        .collect();
+    names.sort();
+    if names.is_empty() {
+        eprintln!("no matching namespaces in folder `{folder}`; nothing to run");
+        return Ok(());
+    }
+
+    let run_one = |ns: &str| -> Result<i32> {
+        let (mut command, redactor) = prepare_command(folder, &[ns.to_string()], cmd, args, opts)?;
+        command.env("BWENV_NAMESPACE", ns);
+        match redactor {
+            Some(redactor) => child::run_redacted(&mut command, redactor),
+            None => child::run(&mut command),
+        }
+    };
+
+    // Workers pull the next namespace until none are left.
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results: Vec<std::sync::Mutex<Option<Result<i32>>>> =
+        names.iter().map(|_| std::sync::Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(names.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(ns) = names.get(i) else { break };
+                    let result = run_one(ns);
+                    *results[i].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                }
+            });
+        }
+    });
+
+    let mut failed = 0;
+    for (ns, slot) in names.iter().zip(results) {
+        let result = slot.into_inner().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Some(Ok(0)) => {}
+            Some(Ok(code)) => {
+                failed += 1;
+                eprintln!("{ns}: exited with status {code}");
+            }
+            Some(Err(e)) => {
+                failed += 1;
+                eprintln!("{ns}: error: {e:#}");
+            }
+            None => {
+                failed += 1;
+                eprintln!("{ns}: did not run");
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} namespace(s) failed", names.len());
+    }
+    Ok(())
+}
+
+fn cmd_shell(folder: &str, namespaces: &[String], lock: bool, opts: &ExecOptions) -> Result<()> {
+    let shell = if cfg!(windows) {
+        env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".into())
+    } else {
+        env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into())
+    };
+    let (mut command, redactor) = prepare_command(folder, namespaces, &shell, &[], opts)?;
+    if redactor.is_some() {
+        anyhow::bail!("--redact is not supported for interactive shells");
+    }
+    if !lock {
+        return exec_replace(command);
+    }
+
+    // Stay resident so the vault can be locked once the shell exits, even
+    // when the shell couldn't be started or waited for.
+    let code = child::run(&mut command);
+    rbw::lock()?;
+    eprintln!("vault locked");
+    let code = code?;
+    timings::report();
+    std::process::exit(code);
+}
+
+/// Fetch and merge `namespaces`, then build the `Command` that runs `cmd`
+/// with them injected.  Everything that can fail does so before the caller
+/// starts the child.  With `--redact`, also returns the matcher for the
+/// injected values.
+fn prepare_command(
+    folder: &str,
+    namespaces: &[String],
+    cmd: &str,
+    args: &[String],
+    opts: &ExecOptions,
+) -> Result<(Command, Option<redact::Redactor>)> {
+    validate_identifier(folder, "folder")?;
+    for ns in namespaces {
+        validate_namespace_entry(ns)?;
+    }
+    // Fail before unlocking (and prompting) if the command cannot be run anyway.
+    if find_executable(cmd).is_none() {
+        anyhow::bail!("command not found: {cmd}");
+    }
+    // Read the local overlay up front too, so a bad path fails just as early.
+    let overlay = read_overlay(opts.env_file.as_deref())?;
+    let resolved = resolve_env(folder, namespaces, overlay, opts.expand)?;
+    build_command(cmd, args, resolved, opts)
+}
+
+/// The `Command` that runs `cmd` with the `resolved` variables injected
+/// (all but [`STORED_CMD_KEY`]), plus the `--redact` matcher.
+fn build_command(
+    cmd: &str,
+    args: &[String],
+    resolved: resolve::Resolved,
+    opts: &ExecOptions,
+) -> Result<(Command, Option<redact::Redactor>)> {
+    let resolve::Resolved {
+        mut pairs,
+        mut encoded,
+        ..
+    } = resolved;
+    if let Some(mut stored) = pairs.remove(STORED_CMD_KEY) {
+        stored.zeroize();
+    }
+    encoded.remove(STORED_CMD_KEY);
+    if opts.fail_empty && pairs.is_empty() {
+        anyhow::bail!("no variables to inject (--fail-empty); is the namespace empty?");
+    }
+
+    // By default the child inherits bwenv's environment with the namespace
+    // merged on top.  With --clean-env only the allowlist survives.
+    let mut command = Command::new(cmd);
+    command.args(args);
+    if opts.clean_env {
+        command.env_clear();
+        for name in CLEAN_ENV_ALLOWLIST
+            .iter()
+            .copied()
+            .chain(opts.keep.iter().map(String::as_str))
+        {
+            if let Some(value) = env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+    }
+    command.envs(&pairs);
+    // Decoded values that are text are masked by --redact as well.
+    let mut decoded_text: Vec<String> = Vec::new();
+    if opts.decode {
+        for key in &encoded {
+            let bytes = decode_value(key, &pairs[key])?;
+            if let Ok(text) = std::str::from_utf8(&bytes) {
+                decoded_text.push(text.to_string());
+            }
+            command.env(key, os_value(key, bytes)?);
+        }
+    }
+    let redactor = opts
+        .redact
+        .then(|| redact::Redactor::new(pairs.values().chain(&decoded_text).map(String::as_str)));
+    for v in &mut decoded_text {
+        v.zeroize();
+    }
+    // Zero secret values from the in-process copy now that they are handed off.
+    for v in pairs.values_mut() {
+        v.zeroize();
+    }
+    Ok((command, redactor))
+}
+
+/// Read and parse an `--env-file` overlay, labelled with its path.
+fn read_overlay(path: Option<&std::path::Path>) -> Result<Option<(String, store::Parsed)>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let label = path.display().to_string();
+    let parsed = read_parsed(&label, &content)?;
+    Ok(Some((label, parsed)))
+}
+
+/// Fetch `namespaces` and resolve them, with `overlay` and `expand`, into
+/// the final variables (see [`resolve`]), reporting overlaps on stderr.
+fn resolve_env(
+    folder: &str,
+    namespaces: &[String],
+    overlay: Option<(String, store::Parsed)>,
+    expand: Option<bool>,
+) -> Result<resolve::Resolved> {
+    // Build (name, folder) pairs and fetch all namespaces concurrently.
+    let requests: Vec<(String, String)> = namespaces
+        .iter()
+        .map(|entry| {
+            (
+                resolve::split_prefix(entry).1.to_string(),
+                folder.to_string(),
+            )
+        })
+        .collect();
+
+    // Unlock once up front so parallel fetches below don't each race to prompt.
+    let names: Vec<&str> = requests.iter().map(|(ns, _)| ns.as_str()).collect();
+    rbw::unlock_for(&format!("read {}", namespace_list(&names)))?;
+
+    let results = rbw::get_notes_many(&requests);
+    let mut layers = Vec::new();
+    for (entry, ((ns, _), result)) in namespaces.iter().zip(requests.iter().zip(results)) {
+        let notes =
+            result?.with_context(|| format!("namespace `{ns}` not found in folder `{folder}`"))?;
+        layers.push(resolve::Layer::new(entry, read_parsed(ns, &notes)?));
+    }
+
+    let overlay_path = overlay.as_ref().map(|(path, _)| path.clone());
+    let spec = resolve::Spec {
+        layers,
+        overlay,
+        expand,
+    };
+    let resolved = resolve::resolve_environment(spec, |name| env::var(name).ok())?;
+    for warning in &resolved.warnings {
+        eprintln!("warning: {warning}");
+    }
+    if verbose()
+        && let Some(path) = overlay_path
+    {
+        for k in &resolved.overridden {
+            eprintln!("note: {path} overrides \"{k}\"");
+        }
+    }
+    Ok(resolved)
+}
+
+/// "namespace `a`", or "namespaces `a`, `b`", for messages.
+fn namespace_list(names: &[&str]) -> String {
+    let quoted: Vec<String> = names.iter().map(|n| format!("`{n}`")).collect();
+    match quoted.as_slice() {
+        [one] => format!("namespace {one}"),
+        _ => format!("namespaces {}", quoted.join(", ")),
+    }
+}
+
+fn cmd_env(
+    folder: &str,
+    namespaces: &[String],
+    env_file: Option<&std::path::Path>,
+    expand: Option<bool>,
+    show_values: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    for ns in namespaces {
+        validate_namespace_entry(ns)?;
+    }
+    let overlay = read_overlay(env_file)?;
+    let mut resolved = resolve_env(folder, namespaces, overlay, expand)?;
+    print!("{}", format_resolved(&resolved, show_values));
+    for v in resolved.pairs.values_mut() {
+        v.zeroize();
+    }
+    Ok(())
+}
+
+/// `KEY=VALUE  # origin` lines, sorted by key; values are masked unless
+/// `show_values`.
+fn format_resolved(resolved: &resolve::Resolved, show_values: bool) -> String {
+    let mut keys: Vec<&String> = resolved.pairs.keys().collect();
+    keys.sort();
+    let mut out = String::new();
+    for key in keys {
+        let value = if show_values {
+            resolved.pairs[key].clone()
+        } else {
+            mask::mask(&resolved.pairs[key])
+        };
+        let encoded = if resolved.encoded.contains(key) {
+            ", base64"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "{key}={value}  # {}{encoded}\n",
+            resolved.origins[key]
+        ));
+    }
+    out
+}
+
+/// Run `command` in place of bwenv and never return on success.
+fn exec_replace(mut command: Command) -> Result<()> {
+    let cmd = command.get_program().to_string_lossy().into_owned();
+    timings::report();
+
+    // Replace current process with the target command (Unix exec semantics).
+    // No bwenv process remains afterwards, so Ctrl-C / SIGTERM are delivered
+    // to the command directly and its exit status is the one the caller sees;
+    // there is nothing to forward.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+        let err = command.exec();
+        Err(anyhow::Error::from(err).context(format!("exec failed: {cmd}")))
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .with_context(|| format!("failed to run {cmd}"))?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
+
+/// Under `--verbose`, point an interactive user at the safer ways to enter
+/// values given as `KEY=VALUE` arguments.  Scripts (stdin not a terminal) are
+/// left alone.
+fn warn_inline_values(vars: &[(&str, Option<&str>)]) {
+    if !verbose() || !std::io::stdin().is_terminal() {
+        return;
+    }
+    let inline: Vec<&str> = vars
+        .iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(key, _)| *key)
+        .collect();
+    if !inline.is_empty() {
+        eprintln!(
+            "note: value(s) for {} given on the command line may be kept in shell history and \
+             process listings; pass just the key to be prompted, or use --stdin",
+            inline.join(", ")
+        );
+    }
+}
+
+fn cmd_set_encoded(
+    folder: &str,
+    namespace: &str,
+    vars: &[String],
+    path: &std::path::Path,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    let [key] = vars else {
+        anyhow::bail!("--encode stores a single key; got {}", vars.len());
+    };
+    if !store::is_valid_env_key(key) {
+        anyhow::bail!("invalid key `{key}`");
+    }
+    let mut bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut encoded = base64::encode(&bytes);
+    bytes.zeroize();
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let mut notes = existing
+        .as_ref()
+        .and_then(|item| item.notes.clone())
+        .unwrap_or_default();
+    notes = store::update_encoded(&notes, key, &encoded);
+    encoded.zeroize();
+
+    let result = write_namespace(folder, namespace, &notes, existing.as_ref());
+    notes.zeroize();
+    result
+}
+
+/// `set-raw`: replace the notes with `input` (stdin when `None`) as is.
+/// The key policy and `--strict-keys` still apply to whatever KEY=VALUE
+/// lines the content parses as, but `write_namespace` is skipped: a managed
+/// header would change the content.
+fn cmd_set_raw(folder: &str, namespace: &str, input: Option<&std::path::Path>) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    let bytes = match input {
+        Some(path) => {
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?
+        }
+        None => {
+            let mut bytes = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)
+                .context("failed to read stdin")?;
+            bytes
+        }
+    };
+    let mut content = String::from_utf8(bytes).map_err(|e| {
+        e.into_bytes().zeroize();
+        anyhow::anyhow!("content is not UTF-8; store binary data with `set --encode`")
+    })?;
+    if content.ends_with('\n') {
+        content.pop();
+    }
+    let checked = rbw::check_verbatim(&content);
+    if checked.is_err() {
+        content.zeroize();
+    }
+    checked?;
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let result =
+        check_written_keys(namespace, &content, existing.as_ref()).and_then(|()| match &existing {
+            None => rbw::create_item(namespace, folder, &content),
+            Some(item) => rbw::edit_item(namespace, folder, &content, item),
+        });
+    content.zeroize();
+    result?;
+    eprintln!(
+        "stored raw content in `{namespace}`; commands that read KEY=VALUE lines \
+         (exec, export, list <namespace>, …) will not understand it"
+    );
+    Ok(())
+}
+
+struct SetOptions {
+    /// Read prompted values from stdin.
+    from_stdin: bool,
+    /// Append every value to the current one, joined by `sep` (`KEY+=VALUE`
+    /// does this for a single key).
+    append: bool,
+    sep: String,
+    /// Only report whether anything would change.
+    check: bool,
+    /// Skip keys that already exist (`--if-missing`); with `error_if_exists`,
+    /// fail instead.
+    if_missing: bool,
+    error_if_exists: bool,
+    /// `--value-file`: the value of the one bare `KEY`.
+    value_file: Option<std::path::PathBuf>,
+    trim_file: bool,
+}
+
+fn cmd_set(folder: &str, namespace: &str, vars: &[String], opts: &SetOptions) -> Result<()> {
+    let from_stdin = opts.from_stdin;
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    // Check everything that can be checked before the vault is touched.
+    let mut file_value = String::new();
+    let mut appending = Vec::new();
+    let mut vars: Vec<(&str, Option<&str>)> = vars
+        .iter()
+        .map(|var| match var.split_once('=') {
+            Some((key, value)) => match key.strip_suffix('+') {
+                Some(key) => {
+                    appending.push(key);
+                    (key, Some(value))
+                }
+                None => (key, Some(value)),
+            },
+            None => (var.as_str(), None),
+        })
+        .collect();
+    if opts.append {
+        appending = vars.iter().map(|(key, _)| *key).collect();
+    }
+    for (key, _) in &vars {
+        if !store::is_valid_env_key(key) {
+            anyhow::bail!("invalid environment variable name: {key:?}");
+        }
+        config::get()?.policy.check(namespace, key)?;
+    }
+    if strict_keys()? {
+        check_key_convention(vars.iter().map(|(key, _)| *key))?;
+    }
+    rbw::ensure_writable()?;
+    warn_inline_values(&vars);
+    if let Some(path) = &opts.value_file {
+        let [(key, None)] = vars.as_mut_slice() else {
+            anyhow::bail!("--value-file sets a single bare KEY (no `=VALUE`)");
+        };
+        file_value = read_value_file(path, opts.trim_file)
+            .with_context(|| format!("--value-file for `{key}`"))?;
+        vars[0].1 = Some(&file_value);
+    }
+    let prompting = vars.iter().any(|(_, value)| value.is_none());
+    if prompting && !from_stdin && !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "no value given and stdin is not a terminal to prompt on; \
+             pass KEY=VALUE, or --stdin to read values from stdin"
+        );
+    }
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+
+    let mut notes = existing
+        .as_ref()
+        .and_then(|item| item.notes.clone())
+        .unwrap_or_default();
+    let mut present: Vec<&str> = Vec::new();
+    if opts.if_missing {
+        let mut current = store::parse(&notes);
+        present = vars
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(|key| current.contains_key(*key))
+            .collect();
+        for value in current.values_mut() {
+            value.zeroize();
+        }
+    }
+    if opts.error_if_exists && !present.is_empty() {
+        notes.zeroize();
+        file_value.zeroize();
+        anyhow::bail!(
+            "already set in `{namespace}`: {}; nothing written",
+            present.join(", ")
+        );
+    }
+    if present.len() == vars.len() {
+        eprintln!("all keys already set in `{namespace}`; nothing to do");
+        notes.zeroize();
+        file_value.zeroize();
+        return Ok(());
+    }
+    for (key, inline) in vars {
+        if present.contains(&key) {
+            eprintln!("`{key}` is already set in `{namespace}`; left unchanged");
+            // Keep reading one line per bare KEY, so later values line up.
+            if inline.is_none() && from_stdin {
+                let mut buf = String::new();
+                std::io::stdin()
+                    .read_line(&mut buf)
+                    .context("failed to read line")?;
+                buf.zeroize();
+            }
+            continue;
+        }
+        let mut value: String = match inline {
+            Some(value) => value.to_string(),
+            None if from_stdin => {
+                let mut buf = String::new();
+                std::io::stdin()
+                    .read_line(&mut buf)
+                    .context("failed to read line")?;
+                buf.trim_end_matches(['\n', '\r']).to_string()
+            }
+            // Reads from the terminal itself with echo off, so the value shows
+            // up neither on screen nor in shell history.
+            None => {
+                prompt_password(format!("{namespace}.{key}: ")).context("failed to read value")?
+            }
+        };
+        if appending.contains(&key) {
+            let Some(updated) = store::append(&notes, key, &value, &opts.sep) else {
+                value.zeroize();
+                notes.zeroize();
+                anyhow::bail!("`{key}` holds a base64 value; it can't be appended to");
+            };
+            notes.zeroize();
+            notes = updated;
+        } else {
+            notes = store::update(&notes, key, &value);
+        }
+        // Zero the secret value in memory before it is dropped.
+        value.zeroize();
+    }
+
+    let result = if opts.check {
+        let current = existing.as_ref().and_then(|item| item.notes.as_deref());
+        let diff = store::diff(
+            &store::parse(current.unwrap_or_default()),
+            &store::parse(&notes),
+        );
+        check_result(namespace, &diff)
+    } else {
+        write_namespace(folder, namespace, &notes, existing.as_ref())
+    };
+    // Zero the notes string (contains all secret values) before returning.
+    notes.zeroize();
+    file_value.zeroize();
+    result
+}
+
+/// `set --value-file`: the file's content without its final line ending
+/// (`trim`: without any surrounding whitespace).  A value can't span lines,
+/// so anything else with a line break is refused.
+fn read_value_file(path: &std::path::Path, trim: bool) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut content = String::from_utf8(bytes).map_err(|e| {
+        e.into_bytes().zeroize();
+        anyhow::anyhow!(
+            "{} is not UTF-8; store binary data with `set --encode`",
+            path.display()
+        )
+    })?;
+    let value = if trim {
+        content.trim()
+    } else {
+        content
+            .strip_suffix("\r\n")
+            .or_else(|| content.strip_suffix('\n'))
+            .unwrap_or(&content)
+    };
+    let result = if value.contains(['\n', '\r']) {
+        Err(anyhow::anyhow!(
+            "{} has more than one line; values are single lines (store the file \
+             with `set --encode` instead)",
+            path.display()
+        ))
+    } else {
+        Ok(value.to_string())
+    };
+    content.zeroize();
+    result
+}
+
+/// `--check`: silent success when `diff` is empty, otherwise an error
+/// summarising what a real run would do.
+fn check_result(namespace: &str, diff: &store::EnvDiff) -> Result<()> {
+    if diff.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!(
+        "namespace `{namespace}` is out of date: {} to add, {} to change, {} to remove",
+        diff.added.len(),
+        diff.changed.len(),
+        diff.removed.len()
+    )
+}
+
+fn cmd_list_all() -> Result<()> {
+    // Group by folder; entries outside any folder are not bwenv namespaces.
+    let mut by_folder: std::collections::BTreeMap<String, Vec<String>> = Default::default();
+    for item in rbw::list_all()? {
+        if let Some(folder) = item.folder {
+            by_folder.entry(folder).or_default().push(item.name);
+        }
+    }
+    for (folder, mut names) in by_folder {
+        names.sort();
+        println!("{folder}:");
+        for name in names {
+            println!("  {name}");
+        }
+    }
+    Ok(())
+}
+
+/// `list --tree`: namespace names split on `sep` and nested two spaces per
+/// level, siblings sorted.  A group ends in `sep`; a name that is a
+/// namespace and a group (`app` alongside `app/api`) is listed as both.
+fn namespace_tree<'a>(names: impl IntoIterator<Item = &'a str>, sep: &str) -> String {
+    #[derive(Default)]
+    struct Node<'a> {
+        namespace: bool,
+        children: std::collections::BTreeMap<&'a str, Node<'a>>,
+    }
+    fn render(node: &Node, sep: &str, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for (part, child) in &node.children {
+            if child.namespace {
+                out.push_str(&format!("{indent}{part}\n"));
+            }
+            if !child.children.is_empty() {
+                out.push_str(&format!("{indent}{part}{sep}\n"));
+                render(child, sep, depth + 1, out);
+            }
+        }
+    }
+
+    let mut root = Node::default();
+    for name in names {
+        let mut node = &mut root;
+        for part in name.split(sep) {
+            node = node.children.entry(part).or_default();
+        }
+        node.namespace = true;
+    }
+    let mut out = String::new();
+    render(&root, sep, 0, &mut out);
+    out
+}
+
+fn cmd_rotate(
+    folder: &str,
+    namespace: &str,
+    key: &str,
+    length: usize,
+    charset: random::Charset,
+    print: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    if !store::is_valid_env_key(key) {
+        anyhow::bail!("invalid environment variable name: {key:?}");
+    }
+    let mut value = random::generate(length, charset)?;
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let mut notes = existing
+        .as_ref()
+        .and_then(|item| item.notes.clone())
+        .unwrap_or_default();
+    notes = store::update(&notes, key, &value);
+    let result = write_namespace(folder, namespace, &notes, existing.as_ref());
+    notes.zeroize();
+
+    // Only reveal the value once it is safely stored.
+    if result.is_ok() && print {
+        println!("{value}");
+    }
+    value.zeroize();
+    result
+}
+
+fn cmd_ensure(folder: &str, namespace: &str, assignments: &[String]) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    let mut wanted = parse_assignments(assignments)?;
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let mut notes = existing
+        .as_ref()
+        .and_then(|item| item.notes.clone())
+        .unwrap_or_default();
+    let current = store::parse(&notes);
+
+    let mut changed: Vec<&str> = Vec::new();
+    for (key, value) in &wanted {
+        if current.get(key) != Some(value) {
+            notes = store::update(&notes, key, value);
+            changed.push(key);
+        }
+    }
+
+    let result = if changed.is_empty() {
+        eprintln!("no changes");
+        Ok(())
+    } else {
+        write_namespace(folder, namespace, &notes, existing.as_ref()).map(|()| {
+            eprintln!(
+                "updated {} key(s) in namespace `{namespace}`: {}",
+                changed.len(),
+                changed.join(", ")
+            );
+        })
+    };
+    notes.zeroize();
+    for (_, v) in wanted.iter_mut() {
+        v.zeroize();
+    }
+    result
+}
+
+/// Output options for `list`.
+struct ListOptions {
+    show_value: bool,
+    null: bool,
+    filter: Option<String>,
+    json: bool,
+    count: bool,
+    porcelain: bool,
+    sort: SortKey,
+    reverse: bool,
+    /// `--tree`, with the separator to split names on.
+    tree: Option<String>,
+}
+
+fn cmd_list(folder: &str, namespace: Option<&str>, opts: &ListOptions) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    if let Some(ns) = namespace {
+        validate_identifier(ns, "namespace")?;
+    }
+    let sep = if opts.null { '\0' } else { '\n' };
+    match namespace {
+        None => {
+            let mut items = rbw::list_items(folder)?;
+            if let Some(pattern) = &opts.filter {
+                items.retain(|item| glob_match(pattern, &item.name));
+            }
+            if opts.count {
+                println!("{}", items.len());
+                return Ok(());
+            }
+            sort_items(&mut items, opts.sort);
+            if opts.reverse {
+                items.reverse();
+            }
+            if opts.json {
+                println!("{}", serde_json::to_string_pretty(&items)?);
+                return Ok(());
+            }
+            if opts.porcelain {
+                print!("{}", porcelain_namespaces(&items));
+                return Ok(());
+            }
+            if let Some(sep) = &opts.tree {
+                print!(
+                    "{}",
+                    namespace_tree(items.iter().map(|i| i.name.as_str()), sep)
+                );
+                return Ok(());
+            }
+            for item in items {
+                print!("{}{sep}", item.name);
+            }
+        }
+        Some(ns) if opts.porcelain => {
+            if opts.sort != SortKey::Name {
+                anyhow::bail!("keys can only be sorted by name");
+            }
+            let mut notes = rbw::get_notes(ns, folder)?.unwrap_or_default();
+            let mut parsed = read_parsed(ns, &notes)?;
+            notes.zeroize();
+            for v in parsed.pairs.values_mut() {
+                v.zeroize();
+            }
+            let out = porcelain_keys(&parsed);
+            if opts.reverse {
+                for line in out.lines().rev() {
+                    println!("{line}");
+                }
+            } else {
+                print!("{out}");
+            }
+        }
+        Some(ns) => {
+            let pairs = load_env_pairs(folder, ns)?;
+            if opts.count {
+                println!("{}", pairs.len());
+                return Ok(());
+            }
+            if pairs.is_empty() {
+                eprintln!(
+                    "WARNING: namespace `{ns}` not found or empty.\n\
+                     You can set variables via: bwenv set {ns} SOME_VAR"
+                );
+                return Ok(());
+            }
+            if opts.sort != SortKey::Name {
+                anyhow::bail!("keys can only be sorted by name");
+            }
+            let mut keys: Vec<&String> = pairs.keys().collect();
+            keys.sort();
+            if opts.reverse {
+                keys.reverse();
+            }
+            for key in keys {
+                if opts.show_value {
+                    print!("{}={}{sep}", key, pairs[key]);
+                } else {
+                    print!("{key}{sep}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cmd_cp_key(
+    folder: &str,
+    source: &str,
+    destination: &str,
+    key: &str,
+    move_key: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(source, "namespace")?;
+    validate_identifier(destination, "namespace")?;
+    if source == destination {
+        anyhow::bail!("source and destination are both `{source}`");
+    }
+    config::get()?.policy.check(destination, key)?;
+    rbw::ensure_writable()?;
+    rbw::unlock_for(&format!("copy `{key}` from `{source}` to `{destination}`"))?;
+    // Always lock in name order, so two opposite copies can't deadlock.
+    let (first, second) = if source < destination {
+        (source, destination)
+    } else {
+        (destination, source)
+    };
+    let _locks = (
+        lock::namespace(folder, first)?,
+        lock::namespace(folder, second)?,
+    );
+
+    let src = rbw::get_item(source, folder)?
+        .with_context(|| format!("namespace `{source}` not found in folder `{folder}`"))?;
+    let dst = rbw::get_item(destination, folder)?;
+    let src_notes = src.notes.as_deref().unwrap_or_default();
+    let dst_notes = dst
+        .as_ref()
+        .and_then(|item| item.notes.as_deref())
+        .unwrap_or_default();
+    let mut copied = copy_key(src_notes, dst_notes, key)
+        .with_context(|| format!("key `{key}` not found in namespace `{source}`"))?;
+    let result = write_namespace(folder, destination, &copied, dst.as_ref());
+    copied.zeroize();
+    result?;
+
+    if !move_key {
+        eprintln!("copied `{key}` from `{source}` to `{destination}`");
+        return Ok(());
+    }
+    // The destination is written first: a failure here leaves the key in
+    // both namespaces rather than in neither.
+    let mut remaining = store::remove(src_notes, key).expect("key was just copied");
+    let result = if store::parse(&remaining).is_empty() {
+        rbw::delete_item(source, folder).map(|()| {
+            eprintln!("namespace `{source}` is now empty and has been removed");
+        })
+    } else {
+        write_namespace(folder, source, &remaining, Some(&src))
+    };
+    remaining.zeroize();
+    result?;
+    eprintln!("moved `{key}` from `{source}` to `{destination}`");
+    Ok(())
+}
+
+/// `dst` with `key` set as it is in `src`: same value, same `!` base64
+/// encoding, same description.  `None` if `src` has no such key.
+fn copy_key(src: &str, dst: &str, key: &str) -> Option<String> {
+    let mut parsed = store::parse_checked(src, !NO_TRIM.load(Ordering::Relaxed));
+    let value = parsed.pairs.get(key)?;
+    let mut notes = if parsed.encoded.iter().any(|k| k == key) {
+        store::update_encoded(dst, key, value)
+    } else {
+        store::update(dst, key, value)
+    };
+    for v in parsed.pairs.values_mut() {
+        v.zeroize();
+    }
+    if let Some(Some(desc)) = store::description(src, key)
+        && let Some(described) = store::set_description(&notes, key, &desc)
+    {
+        notes.zeroize();
+        notes = described;
+    }
+    Some(notes)
+}
+
+fn cmd_unset(folder: &str, namespace: &str, vars: &[String]) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+
+    let mut notes = existing.notes.clone().unwrap_or_default();
+    for key in vars {
+        match store::remove(&notes, key) {
+            Some(updated) => notes = updated,
+            None => eprintln!("WARNING: key `{key}` not found in namespace `{namespace}`"),
+        }
+    }
+
+    write_namespace(folder, namespace, &notes, Some(&existing))?;
+
+    // If all keys have been removed, delete the entry entirely.  Comments
+    // (including the managed-by header) don't count as content.
+    if store::parse(&notes).is_empty() {
+        rbw::delete_item(namespace, folder)?;
+        eprintln!("namespace `{namespace}` is now empty and has been removed");
+    }
+
+    Ok(())
+}
+
+struct ImportOptions {
+    format: ImportFormat,
+    merge: bool,
+    dry_run: bool,
+    porcelain: bool,
+    check: bool,
+    /// `--only-changed`: leave the lines of unchanged keys as they are.
+    only_changed: bool,
+}
+
+/// Where `import` reads from.
+enum ImportSource {
+    Stdin,
+    File(std::path::PathBuf),
+    Url { url: String, headers: Vec<String> },
+}
+
+fn cmd_import(
+    folder: &str,
+    namespace: &str,
+    source: ImportSource,
+    opts: &ImportOptions,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let (label, bytes) = match source {
+        ImportSource::Stdin => {
+            let mut bytes = Vec::new();
+            std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)
+                .context("failed to read stdin")?;
+            ("stdin".to_string(), bytes)
+        }
+        ImportSource::File(path) => {
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            (path.display().to_string(), bytes)
+        }
+        ImportSource::Url { url, headers } => (
+            fetch::display_url(&url).to_string(),
+            fetch::get(&url, &headers)?,
+        ),
+    };
+    let mut content =
+        String::from_utf8(bytes).with_context(|| format!("`{label}` is not valid UTF-8"))?;
+    let parsed = parse_import(&label, &content, opts.format);
+    content.zeroize();
+    let diff = import_parsed(folder, namespace, parsed?, opts)?;
+
+    if opts.check {
+        check_result(namespace, &diff)
+    } else if diff.is_empty() {
+        eprintln!("namespace `{namespace}` is already up to date");
+        Ok(())
+    } else if opts.dry_run && opts.porcelain {
+        print!("{}", porcelain_diff(&diff));
+        Ok(())
+    } else if opts.dry_run {
+        for (sign, keys) in [
+            ("+", &diff.added),
+            ("~", &diff.changed),
+            ("-", &diff.removed),
+        ] {
+            for key in keys {
+                println!("{sign} {key}");
+            }
+        }
+        Ok(())
+    } else {
+        eprintln!(
+            "imported into `{namespace}`: {} added, {} changed, {} removed",
+            diff.added.len(),
+            diff.changed.len(),
+            diff.removed.len()
+        );
+        if opts.only_changed {
+            eprint!("{}", change_list(&diff));
+        }
+        Ok(())
+    }
+}
+
+/// Import `parsed` into `namespace` and return what changed.  With
+/// `--dry-run` or `--check` nothing is written.
+fn import_parsed(
+    folder: &str,
+    namespace: &str,
+    mut parsed: store::Parsed,
+    opts: &ImportOptions,
+) -> Result<store::EnvDiff> {
+    rbw::ensure_writable()?;
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let current = existing
+        .as_ref()
+        .and_then(|item| item.notes.as_deref())
+        .unwrap_or_default();
+    let mut notes = apply_import(current, &parsed, opts.merge, opts.only_changed);
+    for v in parsed.pairs.values_mut() {
+        v.zeroize();
+    }
+    let diff = store::diff(&store::parse(current), &store::parse(&notes));
+    let result = if opts.check || opts.dry_run || diff.is_empty() {
+        Ok(())
+    } else {
+        write_namespace(folder, namespace, &notes, existing.as_ref())
+    };
+    notes.zeroize();
+    result.map(|()| diff)
+}
+
+/// `import-all`: every namespace in the JSON object at `input` (`-` for
+/// stdin), one after the other, with a line per namespace.  All of the
+/// input is validated before anything is written; a namespace that then
+/// fails to import doesn't stop the others.
+fn cmd_import_all(folder: &str, input: &std::path::Path, opts: &ImportOptions) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    if opts.format != ImportFormat::Json {
+        anyhow::bail!("import-all reads one JSON object of namespaces (--format json)");
+    }
+    let (label, bytes) = if input.as_os_str() == "-" {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut bytes)
+            .context("failed to read stdin")?;
+        ("stdin".to_string(), bytes)
+    } else {
+        let bytes =
+            std::fs::read(input).with_context(|| format!("failed to read {}", input.display()))?;
+        (input.display().to_string(), bytes)
+    };
+    let mut content =
+        String::from_utf8(bytes).with_context(|| format!("`{label}` is not valid UTF-8"))?;
+    let namespaces = parse_import_all(&label, &content);
+    content.zeroize();
+    let namespaces = namespaces?;
+    if namespaces.is_empty() {
+        eprintln!("`{label}` holds no namespaces");
+        return Ok(());
+    }
+
+    let total = namespaces.len();
+    let (mut failed, mut outdated) = (Vec::new(), Vec::new());
+    for (namespace, parsed) in namespaces {
+        let diff = match import_parsed(folder, &namespace, parsed, opts) {
+            Ok(diff) => diff,
+            Err(e) => {
+                eprintln!("`{namespace}`: error: {e:#}");
+                failed.push(namespace);
+                continue;
+            }
+        };
+        if diff.is_empty() {
+            eprintln!("`{namespace}`: up to date");
+            continue;
+        }
+        if opts.dry_run {
+            println!("[{namespace}]");
+            for (sign, keys) in [
+                ("+", &diff.added),
+                ("~", &diff.changed),
+                ("-", &diff.removed),
+            ] {
+                for key in keys {
+                    println!("{sign} {key}");
+                }
+            }
+        }
+        let (add, change, remove) = (diff.added.len(), diff.changed.len(), diff.removed.len());
+        if opts.check || opts.dry_run {
+            eprintln!("`{namespace}`: {add} to add, {change} to change, {remove} to remove");
+        } else {
+            eprintln!("`{namespace}`: {add} added, {change} changed, {remove} removed");
+            if opts.only_changed {
+                eprint!("{}", change_list(&diff));
+            }
+        }
+        outdated.push(namespace);
+    }
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} of {total} namespace(s) failed: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    if opts.check && !outdated.is_empty() {
+        anyhow::bail!(
+            "{} of {total} namespace(s) out of date: {}",
+            outdated.len(),
+            outdated.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The namespaces in an `import-all` document, sorted by name: a JSON
+/// object whose values are each a flat object of strings.
+fn parse_import_all(label: &str, content: &str) -> Result<Vec<(String, store::Parsed)>> {
+    let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(content)
+        .with_context(|| format!("`{label}` is not a JSON object of namespaces"))?;
+    let mut namespaces = Vec::new();
+    for (namespace, value) in object {
+        validate_identifier(&namespace, "namespace")
+            .with_context(|| format!("`{label}`: bad namespace name"))?;
+        let inner_label = format!("{label}: {namespace}");
+        let serde_json::Value::Object(inner) = value else {
+            anyhow::bail!("`{inner_label}` must be an object of KEY: \"value\" pairs");
+        };
+        let parsed = json_pairs(&inner_label, inner)?;
+        check_import_keys(&inner_label, &parsed)?;
+        namespaces.push((namespace, parsed));
+    }
+    namespaces.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(namespaces)
+}
+
+/// Parse import content; every key must be a valid variable name.
+fn parse_import(label: &str, content: &str, format: ImportFormat) -> Result<store::Parsed> {
+    let parsed = match format {
+        ImportFormat::Dotenv => read_parsed(label, content)?,
+        ImportFormat::Json => {
+            let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(content)
+                .with_context(|| format!("`{label}` is not a JSON object"))?;
+            json_pairs(label, object)?
+        }
+    };
+    check_import_keys(label, &parsed)?;
+    Ok(parsed)
+}
+
+/// A flat JSON object of strings as parsed pairs.
+fn json_pairs(
+    label: &str,
+    object: serde_json::Map<String, serde_json::Value>,
+) -> Result<store::Parsed> {
+    let mut pairs = HashMap::new();
+    let mut non_strings = Vec::new();
+    for (key, value) in object {
+        match value {
+            serde_json::Value::String(s) => {
+                pairs.insert(key, s);
+            }
+            _ => non_strings.push(key),
+        }
+    }
+    if !non_strings.is_empty() {
+        non_strings.sort();
+        for v in pairs.values_mut() {
+            v.zeroize();
+        }
+        anyhow::bail!(
+            "`{label}`: value(s) must be strings for key(s): {}",
+            non_strings.join(", ")
+        );
+    }
+    Ok(store::Parsed {
+        pairs,
+        duplicates: Vec::new(),
+        exported: Vec::new(),
+        encoded: Vec::new(),
+        order: Vec::new(),
+    })
+}
+
+/// Every imported key must be a valid variable name.
+fn check_import_keys(label: &str, parsed: &store::Parsed) -> Result<()> {
+    let mut invalid: Vec<&String> = parsed
+        .pairs
+        .keys()
+        .filter(|k| !store::is_valid_env_key(k))
+        .collect();
+    if !invalid.is_empty() {
+        invalid.sort();
+        let keys: Vec<&str> = invalid.iter().map(|k| k.as_str()).collect();
+        anyhow::bail!("`{label}`: invalid variable name(s): {}", keys.join(", "));
+    }
+    Ok(())
+}
+
+/// `import --only-changed`: a `+ KEY`, `~ KEY` or `- KEY` line per key
+/// written.
+fn change_list(diff: &store::EnvDiff) -> String {
+    let mut out = String::new();
+    for (sign, keys) in [
+        ("+", &diff.added),
+        ("~", &diff.changed),
+        ("-", &diff.removed),
+    ] {
+        for key in keys {
+            out.push_str(&format!("  {sign} {key}\n"));
+        }
+    }
+    out
+}
+
+/// The namespace notes after importing `parsed` into `current`: keys are
+/// updated in place (new ones appended in sorted order) and, unless `merge`,
+/// keys missing from the input are removed.  With `only_changed`, keys that
+/// already have the imported value keep their line untouched.
+fn apply_import(current: &str, parsed: &store::Parsed, merge: bool, only_changed: bool) -> String {
+    let before = store::parse_checked(current, true);
+    let mut notes = current.to_string();
+    if !merge {
+        for key in before.pairs.keys() {
+            if !parsed.pairs.contains_key(key)
+                && let Some(updated) = store::remove(&notes, key)
+            {
+                notes = updated;
+            }
+        }
+    }
+    let mut keys: Vec<&String> = parsed.pairs.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &parsed.pairs[key];
+        if only_changed
+            && before.pairs.get(key) == Some(value)
+            && before.encoded.contains(key) == parsed.encoded.contains(key)
+        {
+            continue;
+        }
+        notes = if parsed.encoded.contains(key) {
+            store::update_encoded(&notes, key, value)
+        } else {
+            store::update(&notes, key, value)
+        };
+    }
+    notes
+}
+
+/// `pull`: the namespace as a dotenv file `push`/`import` read back
+/// unchanged (sorted, binary values kept as `KEY!=<base64>`).
+fn cmd_pull(folder: &str, namespace: &str, output: &std::path::Path) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let mut notes = rbw::get_notes(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let parsed = read_parsed(namespace, &notes);
+    notes.zeroize();
+    let mut parsed = parsed?;
+    let mut content = apply_import("", &parsed, false, false);
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    for v in parsed.pairs.values_mut() {
+        v.zeroize();
+    }
+    let result = atomic::write(output, content.as_bytes(), 0o600);
+    content.zeroize();
+    result?;
+    eprintln!(
+        "pulled {} key(s) from `{namespace}` into {}",
+        parsed.pairs.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn cmd_merge_namespaces(
+    folder: &str,
+    destination: &str,
+    sources: &[String],
+    fail_on_conflict: bool,
+    prune: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(destination, "namespace")?;
+    for ns in sources {
+        validate_identifier(ns, "namespace")?;
+        if ns == destination {
+            anyhow::bail!("`{destination}` is both the destination and a source");
+        }
+    }
+
+    rbw::ensure_writable()?;
+    rbw::unlock_for(&format!("merge namespaces into `{destination}`"))?;
+    // With --prune the sources are written too (deleted), so they are
+    // locked before they are read, all in name order like `cp-key`.
+    let locked = if prune {
+        lock_order(sources.iter().map(String::as_str).chain([destination]))
+    } else {
+        vec![destination]
+    };
+    let _locks = locked
+        .iter()
+        .map(|ns| lock::namespace(folder, ns))
+        .collect::<Result<Vec<_>>>()?;
+    let existing = rbw::get_item(destination, folder)?;
+    let current = existing
+        .as_ref()
+        .and_then(|item| item.env_content())
+        .unwrap_or_default();
+    let mut layers = vec![(destination.to_string(), read_parsed(destination, current)?)];
+    let requests: Vec<(String, String)> = sources
+        .iter()
+        .map(|ns| (ns.clone(), folder.to_string()))
+        .collect();
+    let mut read: Vec<String> = Vec::new();
+    for (ns, result) in sources.iter().zip(rbw::get_notes_many(&requests)) {
+        let notes =
+            result?.with_context(|| format!("namespace `{ns}` not found in folder `{folder}`"))?;
+        layers.push((ns.clone(), read_parsed(ns, &notes)?));
+        read.push(notes);
+    }
+
+    let (mut merged, conflicts) = merge_layers(layers);
+    for c in &conflicts {
+        eprintln!(
+            "conflict: `{}` differs between `{}` and `{}`; using `{}`",
+            c.key, c.previous, c.source, c.source
+        );
+    }
+    if fail_on_conflict && !conflicts.is_empty() {
+        anyhow::bail!(
+            "{} conflicting key(s); nothing written (--fail-on-conflict)",
+            conflicts.len()
+        );
+    }
+
+    let mut notes = apply_import(current, &merged, true, false);
+    for v in merged.pairs.values_mut() {
+        v.zeroize();
+    }
+    let result = if notes == current {
+        eprintln!("namespace `{destination}` already has every key");
+        Ok(())
+    } else {
+        write_namespace(folder, destination, &notes, existing.as_ref())
+    };
+    notes.zeroize();
+    result?;
+    eprintln!(
+        "merged {} namespace(s) into `{destination}` ({} key(s), {} conflict(s))",
+        sources.len(),
+        merged.pairs.len(),
+        conflicts.len()
+    );
+
+    let result = if prune {
+        prune_sources(folder, sources, &read)
+    } else {
+        Ok(())
+    };
+    for notes in &mut read {
+        notes.zeroize();
+    }
+    result
+}
+
+/// `merge-namespaces --prune`: delete each source, but only if it still
+/// holds what was merged (the read may have come from the cache, from
+/// before the lock).  Keeps going past failures and names them at the end.
+fn prune_sources(folder: &str, sources: &[String], read: &[String]) -> Result<()> {
+    let mut deleted = 0;
+    let mut failed: Vec<&str> = Vec::new();
+    for (ns, merged) in sources.iter().zip(read) {
+        let result = rbw::get_item(ns, folder).and_then(|item| {
+            if !unchanged_since_read(merged, item.as_ref()) {
+                anyhow::bail!("it changed after it was read, so it was not merged as it is now");
+            }
+            rbw::delete_item(ns, folder)
+        });
+        match result {
+            Ok(()) => {
+                eprintln!("deleted `{ns}`");
+                deleted += 1;
+            }
+            Err(e) => {
+                eprintln!("error: failed to delete `{ns}`: {e:#}");
+                failed.push(ns);
+            }
+        }
+    }
+    eprintln!(
+        "deleted {deleted} of {} source namespace(s) from folder `{folder}`",
+        sources.len()
+    );
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} source namespace(s) were merged but not deleted: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Whether `item` still holds the `notes` read earlier; a missing entry
+/// doesn't.
+fn unchanged_since_read(notes: &str, item: Option<&rbw::RbwItem>) -> bool {
+    item.and_then(|item| item.env_content())
+        .is_some_and(|current| current == notes)
+}
+
+/// `names` sorted and deduplicated: the order namespaces are locked in, so
+/// two commands locking overlapping sets can't deadlock, and a name given
+/// twice isn't locked twice.
+fn lock_order<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut names: Vec<&str> = names.into_iter().collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// A key whose value in `source` replaced a different one from `previous`.
+#[derive(Debug, PartialEq, Eq)]
+struct Conflict {
+    key: String,
+    previous: String,
+    source: String,
+}
+
+/// Layer parsed namespaces in order (later wins), noting every key whose
+/// value actually changes between layers.
+fn merge_layers(layers: Vec<(String, store::Parsed)>) -> (store::Parsed, Vec<Conflict>) {
+    let mut merged = store::Parsed {
+        pairs: HashMap::new(),
+        duplicates: Vec::new(),
+        exported: Vec::new(),
+        encoded: Vec::new(),
+        order: Vec::new(),
+    };
+    let mut origins: HashMap<String, String> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for (name, parsed) in layers {
+        let mut keys: Vec<String> = parsed.pairs.keys().cloned().collect();
+        keys.sort();
+        let mut pairs = parsed.pairs;
+        for key in keys {
+            let value = pairs.remove(&key).unwrap_or_default();
+            let encoded = parsed.encoded.contains(&key);
+            if let Some(prev) = merged.pairs.get(&key)
+                && (*prev != value || merged.encoded.contains(&key) != encoded)
+            {
+                conflicts.push(Conflict {
+                    key: key.clone(),
+                    previous: origins[&key].clone(),
+                    source: name.clone(),
+                });
+            }
+            merged.encoded.retain(|k| *k != key);
+            if encoded {
+                merged.encoded.push(key.clone());
+            }
+            origins.insert(key.clone(), name.clone());
+            merged.pairs.insert(key, value);
+        }
+    }
+    (merged, conflicts)
+}
+
+fn cmd_snapshot(
+    folder: &str,
+    namespace: &str,
+    only: &[String],
+    except: &[String],
+    include_all: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+
+    let vars =
+        env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+    let mut pairs = snapshot_pairs(vars, only, except, include_all);
+    if pairs.is_empty() {
+        anyhow::bail!("no environment variables matched; nothing to snapshot");
+    }
+
+    let _lock = lock::namespace(folder, namespace)?;
+    let existing = rbw::get_item(namespace, folder)?;
+    let mut notes = store::serialize(&pairs);
+    for v in pairs.values_mut() {
+        v.zeroize();
+    }
+    let result = write_namespace(folder, namespace, &notes, existing.as_ref());
+    notes.zeroize();
+    result?;
+
+    eprintln!(
+        "saved {} variable(s) to namespace `{namespace}`",
+        pairs.len()
+    );
+    Ok(())
+}
+
+/// The variables `snapshot` saves: valid names only, minus
+/// [`SNAPSHOT_DENYLIST`] unless `include_all`, then narrowed by `--only`
+/// and `--except`.
+fn snapshot_pairs(
+    vars: impl Iterator<Item = (String, String)>,
+    only: &[String],
+    except: &[String],
+    include_all: bool,
+) -> HashMap<String, String> {
+    vars.filter(|(k, _)| store::is_valid_env_key(k))
+        .filter(|(k, _)| include_all || !SNAPSHOT_DENYLIST.iter().any(|p| glob_match(p, k)))
+        .filter(|(k, _)| only.is_empty() || only.iter().any(|p| glob_match(p, k)))
+        .filter(|(k, _)| !except.iter().any(|p| glob_match(p, k)))
+        .collect()
+}
+
+/// Where `export` sends its output.
+enum ExportTarget {
+    Stdout,
+    Fd(i32),
+    File(std::path::PathBuf),
+}
+
+/// The sinks of one `export`, each with its format: `--fd`, every `-o`
+/// (`FORMAT:PATH` overriding `--format` for that file), and stdout when
+/// there is nothing else or with `--also-stdout`.
+fn export_targets(
+    fd: Option<i32>,
+    outputs: &[String],
+    also_stdout: bool,
+    format: ExportFormat,
+) -> Result<Vec<(ExportTarget, ExportFormat)>> {
+    let mut targets = Vec::new();
+    if let Some(fd) = fd {
+        targets.push((ExportTarget::Fd(fd), format));
+    }
+    for output in outputs {
+        let (path, file_format) = match output.split_once(':').and_then(|(prefix, path)| {
+            Some((
+                path,
+                <ExportFormat as clap::ValueEnum>::from_str(prefix, false).ok()?,
+            ))
+        }) {
+            Some((path, file_format)) => (path, file_format),
+            None => (output.as_str(), format),
+        };
+        if path.is_empty() {
+            anyhow::bail!("--output {output:?} names no file");
+        }
+        targets.push((ExportTarget::File(path.into()), file_format));
+    }
+    if targets.is_empty() || also_stdout {
+        targets.push((ExportTarget::Stdout, format));
+    }
+    Ok(targets)
+}
+
+struct ExportOptions {
+    infer_types: bool,
+    trailing_newline: bool,
+    decode: bool,
+    /// `--expand-from-env`; `Some(allow_missing)` when enabled.
+    expand: Option<bool>,
+    /// `--preserve-order`: keys in stored order instead of sorted.
+    preserve_order: bool,
+    fail_empty: bool,
+    /// `--keep-descriptions`: `# desc:` comments in shell output.
+    keep_descriptions: bool,
+}
+
+/// Options for `with-file`.
+struct WithFileOptions {
+    key: Option<String>,
+    format: ExportFormat,
+    var: String,
+    name: Option<String>,
+}
+
+/// `with-file`: write the namespace, or one key's value, to a
+/// [`tmpfile::SecretFile`], run `command` with `--as VAR` set to its path,
+/// and remove the file once the command exits, however it exits.
+fn cmd_with_file(
+    folder: &str,
+    namespace: &str,
+    command: &[String],
+    opts: &WithFileOptions,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    if !store::is_valid_env_key(&opts.var) {
+        anyhow::bail!("--as: invalid environment variable name: {:?}", opts.var);
+    }
+    let (cmd, args) = command.split_first().context("no command given")?;
+    if find_executable(cmd).is_none() {
+        anyhow::bail!("command not found: {cmd}");
+    }
+
+    let notes = rbw::get_notes(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let spec = resolve::Spec {
+        layers: vec![resolve::Layer::new(
+            namespace,
+            read_parsed(namespace, &notes)?,
+        )],
+        overlay: None,
+        expand: None,
+    };
+    let resolve::Resolved {
+        mut pairs, encoded, ..
+    } = resolve::resolve_environment(spec, |name| env::var(name).ok())?;
+    let content = match &opts.key {
+        Some(key) => match pairs.get(key) {
+            Some(value) if encoded.contains(key) => decode_value(key, value),
+            Some(value) => Ok(value.clone().into_bytes()),
+            None => Err(anyhow::anyhow!(
+                "key `{key}` not found in namespace `{namespace}`"
+            )),
+        },
+        None => render_export(
+            &pairs,
+            None,
+            &HashMap::new(),
+            |k: &String| encoded.contains(k),
+            opts.format,
+            &ExportOptions {
+                infer_types: false,
+                trailing_newline: true,
+                decode: false,
+                expand: None,
+                preserve_order: false,
+                fail_empty: false,
+                keep_descriptions: false,
+            },
+        ),
+    };
+    for value in pairs.values_mut() {
+        value.zeroize();
+    }
+    let mut content = content?;
+    let name = match (&opts.name, &opts.key, opts.format) {
+        (Some(name), _, _) => name.as_str(),
+        (None, Some(key), _) => key.as_str(),
+        (None, None, ExportFormat::Shell) => "env",
+        (None, None, ExportFormat::EnvJson) => "env.json",
+    };
+    let file = tmpfile::SecretFile::create(name, &content);
+    content.zeroize();
+    let file = file?;
+    if verbose() {
+        eprintln!("{}={}", opts.var, file.path().display());
+    }
+
+    let mut child = Command::new(cmd);
+    child.args(args).env(&opts.var, file.path());
+    let code = child::run(&mut child);
+    // Remove the file before exiting; `exit` would skip the drop.
+    drop(file);
+    let code = code?;
+    timings::report();
+    std::process::exit(code);
+}
+
+/// `--namespace-separator` if given, else the config's, else `/`.
+fn namespace_separator_for(cli: Option<String>) -> Result<String> {
+    let sep = match cli {
+        Some(sep) => sep,
+        None => config::get()?
+            .namespace_separator
+            .clone()
+            .unwrap_or_else(|| "/".to_string()),
+    };
+    if sep.is_empty() {
+        anyhow::bail!("the namespace separator must not be empty");
+    }
+    Ok(sep)
+}
+
+/// `--format` if given, else the config's rule for `namespace`, else shell.
+fn resolve_export_format(cli: Option<ExportFormat>, namespace: &str) -> Result<ExportFormat> {
+    if let Some(format) = cli {
+        return Ok(format);
+    }
+    match config::get()?.export_format(namespace) {
+        Some(name) => <ExportFormat as clap::ValueEnum>::from_str(name, false).map_err(|_| {
+            anyhow::anyhow!(
+                "config export_formats: unknown format `{name}` (use shell or env-json)"
+            )
+        }),
+        None => Ok(ExportFormat::Shell),
+    }
+}
+
+/// `export`: read the namespace once and render it to every target.
+fn cmd_export(
+    folder: &str,
+    namespace: &str,
+    targets: Vec<(ExportTarget, ExportFormat)>,
+    opts: &ExportOptions,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let uses = |format| targets.iter().any(|(_, f)| *f == format);
+    if uses(ExportFormat::EnvJson) && opts.decode {
+        anyhow::bail!("--decode only applies to --format shell");
+    }
+    if !uses(ExportFormat::EnvJson) && opts.infer_types {
+        anyhow::bail!("--infer-types requires --format env-json");
+    }
+    if uses(ExportFormat::EnvJson) && opts.keep_descriptions {
+        anyhow::bail!("--keep-descriptions only applies to --format shell");
+    }
+    // Check fds before touching the vault, so a bad fd never costs a prompt.
+    // (File targets are written in one go at the end instead.)
+    let mut sinks = Vec::new();
+    for (target, format) in targets {
+        let stream: Option<Box<dyn std::io::Write>> = match &target {
+            ExportTarget::Fd(fd) => Some(Box::new(open_output_fd(*fd)?)),
+            ExportTarget::File(_) => None,
+            ExportTarget::Stdout => Some(Box::new(std::io::stdout().lock())),
+        };
+        sinks.push((stream, target, format));
+    }
+
+    let notes = rbw::get_notes(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let mut parsed = read_parsed(namespace, &notes)?;
+    let order = std::mem::take(&mut parsed.order);
+    let descriptions = if opts.keep_descriptions {
+        store::descriptions(&notes)
+    } else {
+        HashMap::new()
+    };
+    let spec = resolve::Spec {
+        layers: vec![resolve::Layer {
+            label: namespace.to_string(),
+            prefix: None,
+            parsed,
+        }],
+        overlay: None,
+        expand: opts.expand,
+    };
+    let resolve::Resolved {
+        mut pairs, encoded, ..
+    } = resolve::resolve_environment(spec, |name| env::var(name).ok())?;
+    if opts.fail_empty && pairs.is_empty() {
+        anyhow::bail!("namespace `{namespace}` has no variables to export (--fail-empty)");
+    }
+    let is_encoded = |k: &String| encoded.contains(k);
+    let order = opts.preserve_order.then_some(order.as_slice());
+
+    let mut result = Ok(());
+    for (stream, target, format) in sinks {
+        let mut content =
+            match render_export(&pairs, order, &descriptions, is_encoded, format, opts) {
+                Ok(content) => content,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+        result = match (stream, &target) {
+            (Some(mut out), _) => out
+                .write_all(&content)
+                .and_then(|()| out.flush())
+                .context("failed to write exported variables"),
+            (None, ExportTarget::File(path)) => atomic::write(path, &content, 0o600),
+            (None, _) => Ok(()),
+        };
+        content.zeroize();
+        if result.is_err() {
+            break;
+        }
+    }
+    for value in pairs.values_mut() {
+        value.zeroize();
+    }
+    result
+}
+
+/// The bytes `export` writes for `pairs` in `format`, keys sorted or in
+/// `order` (see [`store::output_keys`]).  Shell output carries a key's
+/// entry in `descriptions` as a `# desc:` line above it.
+fn render_export(
+    pairs: &HashMap<String, String>,
+    order: Option<&[String]>,
+    descriptions: &HashMap<String, String>,
+    is_encoded: impl Fn(&String) -> bool,
+    format: ExportFormat,
+    opts: &ExportOptions,
+) -> Result<Vec<u8>> {
+    let mut content = match format {
+        ExportFormat::Shell if opts.decode || !descriptions.is_empty() => {
+            let mut content = Vec::new();
+            for key in store::output_keys(pairs, order) {
+                if let Some(text) = descriptions.get(key) {
+                    content.extend_from_slice(format!("# desc: {text}\n").as_bytes());
+                }
+                let mut value = if opts.decode && is_encoded(key) {
+                    match decode_value(key, &pairs[key]) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            content.zeroize();
+                            return Err(e);
+                        }
+                    }
+                } else {
+                    pairs[key].clone().into_bytes()
+                };
+                content.extend_from_slice(format!("export {key}=").as_bytes());
+                content.extend_from_slice(&store::shell_quote_bytes(&value));
+                content.push(b'\n');
+                value.zeroize();
+            }
+            content
+        }
+        ExportFormat::Shell => store::serialize_shell(pairs, order).into_bytes(),
+        ExportFormat::EnvJson => env_json(pairs, order, is_encoded, opts.infer_types).into_bytes(),
+    };
+    if !opts.trailing_newline {
+        strip_trailing_newline(&mut content);
+    }
+    Ok(content)
+}
+
+/// `export --no-trailing-newline`: drop the final `\n` of the output (only
+/// the output; what is stored is untouched).
+fn strip_trailing_newline(content: &mut Vec<u8>) {
+    if content.ends_with(b"\n") {
+        content.pop();
+    }
+}
+
+/// `export --format env-json`: a pretty-printed JSON object, keys sorted or
+/// in `order`.  Base64 values are always strings.
+///
+/// serde_json's map is always sorted, so the object is written by hand; the
+/// layout is the same as `to_string_pretty`'s.
+fn env_json(
+    pairs: &HashMap<String, String>,
+    order: Option<&[String]>,
+    is_encoded: impl Fn(&String) -> bool,
+    infer_types: bool,
+) -> String {
+    let keys = store::output_keys(pairs, order);
+    if keys.is_empty() {
+        return "{}\n".to_string();
+    }
+    let mut out = String::from("{\n");
+    for (i, k) in keys.iter().enumerate() {
+        let value = if infer_types && !is_encoded(k) {
+            infer_json(&pairs[*k])
+        } else {
+            None
+        }
+        .unwrap_or_else(|| serde_json::Value::String(pairs[*k].clone()));
+        let mut text = serde_json::to_string_pretty(&value).expect("a JSON value serializes");
+        out.push_str("  ");
+        out.push_str(&serde_json::to_string(k).expect("a string serializes"));
+        out.push_str(": ");
+        out.push_str(&text.replace('\n', "\n  "));
+        out.push_str(if i + 1 < keys.len() { ",\n" } else { "\n" });
+        text.zeroize();
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// `value` as a typed JSON value, or `None` to keep it a string.
+///
+/// A value is typed only when the whole of it is a JSON literal other than a
+/// string: `true`, `false`, `null`, a number, an array or an object.
+/// Surrounding whitespace, leading zeros (`007`) and quoted text keep it a
+/// string, as do integers outside the 64-bit range and numbers that don't fit
+/// a double, since those would come out changed.
+fn infer_json(value: &str) -> Option<serde_json::Value> {
+    if value.trim() != value || value.is_empty() {
+        return None;
+    }
+    let parsed: serde_json::Value = serde_json::from_str(value).ok()?;
+    match &parsed {
+        serde_json::Value::String(_) => None,
+        serde_json::Value::Number(n) => {
+            let integral = !value.contains(['.', 'e', 'E']);
+            let exact = if integral {
+                n.is_i64() || n.is_u64()
+            } else {
+                n.as_f64().is_some_and(f64::is_finite)
+            };
+            exact.then_some(parsed)
+        }
+        _ => Some(parsed),
+    }
+}
+
+/// Take ownership of inherited descriptor `fd`, after checking that it is open
+/// for writing.
+#[cfg(unix)]
+fn open_output_fd(fd: i32) -> Result<std::fs::File> {
+    use std::os::fd::FromRawFd as _;
+
+    if fd < 0 {
+        anyhow::bail!("invalid file descriptor {fd}");
+    }
+    // SAFETY: F_GETFL only queries the descriptor's flags.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        anyhow::bail!("file descriptor {fd} is not open");
+    }
+    if flags & libc::O_ACCMODE == libc::O_RDONLY {
+        anyhow::bail!("file descriptor {fd} is not open for writing");
+    }
+    // SAFETY: the descriptor is open and nothing else in bwenv uses it.
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+#[cfg(not(unix))]
+fn open_output_fd(_fd: i32) -> Result<std::fs::File> {
+    anyhow::bail!("--fd is only supported on Unix")
+}
+
+fn cmd_create(folder: &str, namespace: &str, from: Option<&str>, with_values: bool) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    rbw::ensure_writable()?;
+    if let Some(source) = from {
+        validate_identifier(source, "namespace")?;
+        if source == namespace {
+            anyhow::bail!("`{namespace}` cannot be created from itself");
+        }
+    }
+
+    rbw::unlock_for(&format!("create namespace `{namespace}`"))?;
+    let _lock = lock::namespace(folder, namespace)?;
+    if rbw::get_item(namespace, folder)?.is_some() {
+        anyhow::bail!("namespace `{namespace}` already exists in folder `{folder}`");
+    }
+    let mut notes = match from {
+        Some(source) => {
+            let mut notes = rbw::get_notes(source, folder)?
+                .with_context(|| format!("namespace `{source}` not found in folder `{folder}`"))?;
+            read_parsed(source, &notes)?;
+            if !with_values {
+                let blank = store::blank_values(&notes);
+                notes.zeroize();
+                notes = blank;
+            }
+            notes
+        }
+        None => String::new(),
+    };
+    let keys = store::parse(&notes).len();
+    let result = write_namespace(folder, namespace, &notes, None);
+    notes.zeroize();
+    result?;
+    match from {
+        Some(source) => eprintln!(
+            "created `{namespace}` with {keys} key(s) from `{source}`{}",
+            if with_values {
+                ""
+            } else {
+                " (values left empty)"
+            }
+        ),
+        None => eprintln!("created `{namespace}`"),
+    }
+    Ok(())
+}
+
+fn cmd_scaffold(folder: &str, namespace: &str, output: Option<&std::path::Path>) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let pairs = load_env_pairs(folder, namespace)?;
+
+    // Only key names leave this function; values are never written.
+    let mut keys: Vec<&String> = pairs.keys().collect();
+    keys.sort();
+    let content: String = keys.iter().map(|k| format!("{k}=\n")).collect();
+
+    match output {
+        Some(path) => {
+            atomic::write(path, content.as_bytes(), 0o644)?;
+            eprintln!("wrote {} key(s) to {}", keys.len(), path.display());
+        }
+        None => print!("{content}"),
+    }
+    Ok(())
+}
+
+fn cmd_describe(folder: &str, namespace: &str, key: &str, text: Option<&str>) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    if let Some(text) = text {
+        check_description(text)?;
+        rbw::ensure_writable()?;
+    }
+    let _lock = text
+        .map(|_| lock::namespace(folder, namespace))
+        .transpose()?;
+    let existing = rbw::get_item(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let notes = existing.env_content().unwrap_or_default();
+
+    match text {
+        None => {
+            let desc = store::description(notes, key)
+                .with_context(|| format!("key `{key}` not found in namespace `{namespace}`"))?;
+            if let Some(desc) = desc {
+                println!("{desc}");
+            }
+            Ok(())
+        }
+        Some(text) => {
+            let mut updated = store::set_description(notes, key, text)
+                .with_context(|| format!("key `{key}` not found in namespace `{namespace}`"))?;
+            let result = write_namespace(folder, namespace, &updated, Some(&existing));
+            updated.zeroize();
+            result
+        }
+    }
+}
+
+/// A description is the rest of one `# desc:` line: a line break in it
+/// would start new lines in the notes, which could define keys.
+fn check_description(text: &str) -> Result<()> {
+    if text.contains(['\n', '\r']) {
+        anyhow::bail!("descriptions are single lines; remove the line breaks");
+    }
+    Ok(())
+}
+
+/// `exists`: whether the namespace (and `key` in it) exists.  Errors such as
+/// a locked vault are still errors, not a "no".
+fn cmd_exists(folder: &str, namespace: &str, key: Option<&str>) -> Result<bool> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let Some(mut notes) = rbw::get_notes(namespace, folder)? else {
+        return Ok(false);
+    };
+    let found = match key {
+        Some(key) => {
+            let mut pairs = store::parse(&notes);
+            let found = pairs.contains_key(key);
+            for value in pairs.values_mut() {
+                value.zeroize();
+            }
+            found
+        }
+        None => true,
+    };
+    notes.zeroize();
+    Ok(found)
+}
+
+fn cmd_fingerprint(folder: &str, namespace: &str) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let mut notes = rbw::get_notes(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let parsed = read_parsed(namespace, &notes);
+    notes.zeroize();
+    let mut parsed = parsed?;
+    println!("{}", store::fingerprint(&parsed.pairs, &parsed.encoded));
+    for value in parsed.pairs.values_mut() {
+        value.zeroize();
+    }
+    Ok(())
+}
+
+fn cmd_info(folder: &str, namespace: &str) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    // Not cached: the revision date and history are the point of `info`.
+    let item = rbw::get_item(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let keys = read_pairs(namespace, item.env_content().unwrap_or_default())?;
+
+    println!("namespace: {namespace}");
+    println!("folder:    {folder}");
+    println!(
+        "type:      {}",
+        item.item_type.as_deref().unwrap_or("unknown")
+    );
+    println!("keys:      {}", keys.len());
+    match &item.revision_date {
+        Some(date) => println!("modified:  {date}"),
+        None => println!("modified:  unknown (not reported by the installed rbw)"),
+    }
+    // Bitwarden only keeps history for the password field, not the notes.
+    if let Some(last) = item.history.iter().map(|h| &h.last_used_date).max() {
+        println!(
+            "password history: {} entries, last changed {last}",
+            item.history.len()
+        );
+    }
+    Ok(())
+}
+
+fn cmd_log(folder: &str, namespace: &str) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let item = rbw::get_item(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    print!("{}", format_log(&item));
+    eprintln!(
+        "note: Bitwarden keeps no history of an entry's notes, only of its password, \
+         so earlier values of the keys can't be shown; `watch-ns` can record changes \
+         as they happen"
+    );
+    Ok(())
+}
+
+/// `log`: `<timestamp>  <event>` lines, newest first.  rbw reports the last
+/// modification and the dates the (unused) Login password was replaced.
+fn format_log(item: &rbw::RbwItem) -> String {
+    let mut events: Vec<(&str, &str)> = item
+        .history
+        .iter()
+        .map(|h| (h.last_used_date.as_str(), "password replaced"))
+        .collect();
+    if let Some(date) = &item.revision_date {
+        events.push((date, "last modified"));
+    }
+    // ISO 8601 timestamps in one zone sort chronologically as text.
+    events.sort_by(|a, b| b.0.cmp(a.0));
+    if events.is_empty() {
+        return "no revision dates reported by the installed rbw\n".to_string();
+    }
+    events
+        .iter()
+        .map(|(date, event)| format!("{date}  {event}\n"))
+        .collect()
+}
+
+fn cmd_verify(folder: &str, porcelain: bool) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    let mut names: Vec<String> = rbw::list_items(folder)?
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    names.sort();
+
+    let requests: Vec<(String, String)> = names
+        .iter()
+        .map(|ns| (ns.clone(), folder.to_string()))
+        .collect();
+    let results = rbw::get_notes_many(&requests);
+
+    // Keep going past bad entries; everything is reported at the end.
+    let strict = STRICT_PARSE.load(Ordering::Relaxed);
+    let mut failed = 0;
+    for (ns, result) in names.iter().zip(results) {
+        let issues = match result {
+            Ok(Some(mut notes)) => {
+                let issues = store::lint(&notes);
+                notes.zeroize();
+                issues
+            }
+            Ok(None) => {
+                failed += 1;
+                let message = "disappeared while verifying";
+                print!("{}", format_verify_failure(ns, message, porcelain));
+                continue;
+            }
+            Err(e) => {
+                failed += 1;
+                print!(
+                    "{}",
+                    format_verify_failure(ns, &format!("{e:#}"), porcelain)
+                );
+                continue;
+            }
+        };
+        let bad = issues.iter().any(|i| i.error || strict);
+        if bad {
+            failed += 1;
+        }
+        print!("{}", format_verify(ns, &issues, bad, porcelain));
+    }
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{failed} of {} namespace(s) in `{folder}` have problems",
+            names.len()
+        );
+    }
+    eprintln!(
+        "all {} namespace(s) in `{folder}` parse cleanly",
+        names.len()
+    );
+    Ok(())
+}
+
+/// One namespace's `verify` report.
+fn format_verify(ns: &str, issues: &[store::Issue], bad: bool, porcelain: bool) -> String {
+    let mut out = match (porcelain, bad) {
+        (true, true) => format!("fail\t{ns}\n"),
+        (true, false) => format!("ok\t{ns}\n"),
+        (false, true) => format!("FAIL  {ns}\n"),
+        (false, false) => format!("ok    {ns}\n"),
+    };
+    for issue in issues {
+        let level = if issue.error { "error" } else { "warning" };
+        out.push_str(&if porcelain {
+            format!("issue\t{ns}\t{}\t{level}\t{}\n", issue.line, issue.message)
+        } else {
+            format!("      line {}: {level}: {}\n", issue.line, issue.message)
+        });
+    }
+    out
+}
+
+/// A namespace `verify` could not read at all; reported as line 0 in
+/// porcelain output.
+fn format_verify_failure(ns: &str, message: &str, porcelain: bool) -> String {
+    if porcelain {
+        format!("fail\t{ns}\nissue\t{ns}\t0\terror\t{message}\n")
+    } else {
+        format!("FAIL  {ns}\n      {message}\n")
+    }
+}
+
+// ── Porcelain output ─────────────────────────────────────────────────────────
+//
+// `--porcelain` formats are a stable interface for scripts: one record per
+// line, tab-separated fields, no header and no colour.  Existing fields are
+// never reordered or removed; new ones may only be appended.  The tests pin
+// them byte for byte.
+
+/// `list --porcelain`: `<name>\t<type>\t<folder>` (folder empty if none).
+fn porcelain_namespaces(items: &[rbw::ListItem]) -> String {
+    items
+        .iter()
+        .map(|i| {
+            let folder = i.folder.as_deref().unwrap_or_default();
+            format!("{}\t{}\t{folder}\n", i.name, i.item_type)
+        })
+        .collect()
+}
+
+/// `list <namespace> --porcelain`: `<KEY>\t<text|base64>`, sorted by key.
+fn porcelain_keys(parsed: &store::Parsed) -> String {
+    let mut keys: Vec<&String> = parsed.pairs.keys().collect();
+    keys.sort();
+    keys.iter()
+        .map(|k| {
+            let kind = if parsed.encoded.contains(k) {
+                "base64"
+            } else {
+                "text"
+            };
+            format!("{k}\t{kind}\n")
+        })
+        .collect()
+}
+
+/// `import --dry-run --porcelain`: `<added|changed|removed>\t<KEY>`.
+fn porcelain_diff(diff: &store::EnvDiff) -> String {
+    let mut out = String::new();
+    for (kind, keys) in [
+        ("added", &diff.added),
+        ("changed", &diff.changed),
+        ("removed", &diff.removed),
+    ] {
+        for key in keys {
+            out.push_str(&format!("{kind}\t{key}\n"));
+        }
+    }
+    out
+}
+
+fn cmd_whoami() -> Result<()> {
+    let account = rbw::account()?;
+    let email = account
+        .email
+        .context("rbw has no account email configured (see `rbw config set email`)")?;
+    println!("email:  {email}");
+    println!(
+        "server: {}",
+        account
+            .base_url
+            .as_deref()
+            .unwrap_or("https://api.bitwarden.com (default)")
+    );
+    Ok(())
+}
+
+fn cmd_watch_ns(
+    folder: &str,
+    namespace: &str,
+    interval: std::time::Duration,
+    show_values: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    spinner::disable();
+    rbw::unlock_for(&format!("watch namespace `{namespace}`"))?;
+
+    // Not cached: every poll has to see the server's latest state.
+    let fetch = || -> Result<HashMap<String, String>> {
+        let item = rbw::get_item(namespace, folder)?;
+        Ok(store::parse(
+            item.as_ref()
+                .and_then(|i| i.env_content())
+                .unwrap_or_default(),
+        ))
+    };
+    let mut current = fetch()?;
+    eprintln!(
+        "Watching `{namespace}` ({} key(s)) every {}s; press Ctrl-C to stop",
+        current.len(),
+        interval.as_secs()
+    );
+    loop {
+        std::thread::sleep(interval);
+        if let Err(e) = rbw::sync() {
+            eprintln!("WARNING: {e:#}");
+            continue;
+        }
+        let next = fetch()?;
+        let diff = store::diff(&current, &next);
+        if !diff.is_empty() {
+            print!(
+                "{}",
+                format_env_diff(
+                    &utc_timestamp(std::time::SystemTime::now()),
+                    &diff,
+                    &current,
+                    &next,
+                    show_values
+                )
+            );
+            std::io::Write::flush(&mut std::io::stdout())?;
+        }
+        current = next;
+    }
+}
+
+/// Each side of `compare` is a namespace and, with `--left-profile` or
+/// `--right-profile`, the rbw profile to read it from.
+fn cmd_compare(
+    folder: &str,
+    (left, left_profile): (&str, Option<&str>),
+    (right, right_profile): (&str, Option<&str>),
+    show_values: bool,
+    json: bool,
+) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(left, "namespace")?;
+    validate_identifier(right, "namespace")?;
+    let fetch = |requests: &[(String, String)]| -> Result<Vec<HashMap<String, String>>> {
+        rbw::unlock_for(&format!("compare `{left}` and `{right}`"))?;
+        let mut sides = Vec::new();
+        for ((ns, _), result) in requests.iter().zip(rbw::get_notes_many(requests)) {
+            let mut notes = result?
+                .with_context(|| format!("namespace `{ns}` not found in folder `{folder}`"))?;
+            let parsed = read_parsed(ns, &notes);
+            notes.zeroize();
+            sides.push(parsed?.pairs);
+        }
+        Ok(sides)
+    };
+    let request = |ns: &str| (ns.to_string(), folder.to_string());
+    let mut sides = if left_profile == right_profile {
+        rbw::with_profile(left_profile, || fetch(&[request(left), request(right)]))?
+    } else {
+        // Two accounts: one after the other, each unlocked on its own.
+        let mut sides = rbw::with_profile(left_profile, || fetch(&[request(left)]))?;
+        sides.extend(rbw::with_profile(right_profile, || {
+            fetch(&[request(right)])
+        })?);
+        sides
+    };
+    let (mut old, mut new) = (sides.remove(0), sides.remove(0));
+    let label = |ns: &str, profile: Option<&str>| match profile {
+        Some(profile) => format!("{ns}@{profile}"),
+        None => ns.to_string(),
+    };
+    let (left, right) = (label(left, left_profile), label(right, right_profile));
+
+    let diff = store::diff(&old, &new);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&compare_json(
+                &left,
+                &right,
+                &diff,
+                (&old, &new),
+                show_values
+            ))?
+        );
+    } else if diff.is_empty() {
+        eprintln!("`{left}` and `{right}` have the same keys and values");
+    } else {
+        print!(
+            "{}",
+            format_env_diff(
+                &format!("{left} -> {right}"),
+                &diff,
+                &old,
+                &new,
+                show_values
+            )
+        );
+    }
+    for v in old.values_mut().chain(new.values_mut()) {
+        v.zeroize();
+    }
+    Ok(())
+}
+
+/// `compare --json`: `{left, right, added, removed, changed}` key lists
+/// (sides read from another profile are labelled `namespace@profile`),
+/// plus `values` (`{KEY: {left, right}}`, `null` where absent) with
+/// `--show-values`.
+fn compare_json(
+    left: &str,
+    right: &str,
+    diff: &store::EnvDiff,
+    (old, new): (&HashMap<String, String>, &HashMap<String, String>),
+    show_values: bool,
+) -> serde_json::Value {
+    let mut out = serde_json::json!({
+        "left": left,
+        "right": right,
+        "added": diff.added,
+        "removed": diff.removed,
+        "changed": diff.changed,
+    });
+    if show_values {
+        let values: serde_json::Map<String, serde_json::Value> = diff
+            .added
+            .iter()
+            .chain(&diff.removed)
+            .chain(&diff.changed)
+            .map(|k| {
+                let side = serde_json::json!({ "left": old.get(k), "right": new.get(k) });
+                (k.clone(), side)
+            })
             .collect();
-        if namespaces.is_empty() {
-            anyhow::bail!("namespace must not be empty");
+        out["values"] = values.into();
+    }
+    out
+}
+
+fn format_env_diff(
+    when: &str,
+    diff: &store::EnvDiff,
+    old: &HashMap<String, String>,
+    new: &HashMap<String, String>,
+    show_values: bool,
+) -> String {
+    let shown = |value: &str| {
+        if show_values {
+            value.to_string()
+        } else {
+            mask::mask(value)
+        }
+    };
+    let mut out = format!("[{when}]\n");
+    for key in &diff.added {
+        out.push_str(&format!("  + {key}={}\n", shown(&new[key])));
+    }
+    for key in &diff.changed {
+        out.push_str(&format!(
+            "  ~ {key}={} -> {}\n",
+            shown(&old[key]),
+            shown(&new[key])
+        ));
+    }
+    for key in &diff.removed {
+        out.push_str(&format!("  - {key}\n"));
+    }
+    out
+}
+
+/// `time` as an ISO 8601 UTC timestamp, e.g. `2024-05-01T10:00:00Z`.
+fn utc_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Public Bitwarden web vault, used when neither the config nor rbw names a
+/// server.
+const DEFAULT_VAULT_URL: &str = "https://vault.bitwarden.com";
+
+fn cmd_open(folder: &str, namespace: &str, print: bool) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    validate_identifier(namespace, "namespace")?;
+    let item = rbw::list_items(folder)?
+        .into_iter()
+        .find(|i| i.name == namespace)
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+    let id = item
+        .id
+        .context("the installed rbw does not report item IDs (needs rbw 1.13.0+)")?;
+
+    let base = match &config::get()?.vault_url {
+        Some(url) => url.clone(),
+        None => rbw::account()
+            .ok()
+            .and_then(|a| a.base_url)
+            .unwrap_or_else(|| DEFAULT_VAULT_URL.to_string()),
+    };
+    let url = vault_item_url(&base, &id);
+    if !print && launch_browser(&url) {
+        eprintln!("Opened {url}");
+    } else {
+        println!("{url}");
+    }
+    Ok(())
+}
+
+fn vault_item_url(base: &str, id: &str) -> String {
+    format!("{}/#/vault?itemId={id}", base.trim_end_matches('/'))
+}
+
+/// Hand `url` to the platform's opener; `false` if there is none or it failed.
+fn launch_browser(url: &str) -> bool {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(opener)
+        .arg(url)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+fn cmd_delete_all(folder: &str, filter: Option<&str>, yes: bool, strict: bool) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    let mut items = rbw::list_items(folder)?;
+    if let Some(pattern) = filter {
+        items.retain(|i| glob_match(pattern, &i.name));
+    }
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let count = items.len();
+    if count == 0 {
+        eprintln!("no matching namespaces in folder `{folder}`; nothing to delete");
+        return Ok(());
+    }
+
+    eprintln!("WARNING: this will PERMANENTLY DELETE {count} namespace(s) from folder `{folder}`:");
+    for item in &items {
+        eprintln!("  {} ({})", item.name, item.item_type);
+    }
+    if !yes {
+        confirm_deletion(count)?;
+    }
+
+    let mut deleted = 0;
+    let mut failed: Vec<&str> = Vec::new();
+    for item in &items {
+        match rbw::delete_item(&item.name, folder) {
+            Ok(()) => deleted += 1,
+            Err(e) if strict => {
+                return Err(e.context(format!(
+                    "failed to delete `{}` ({deleted} of {count} deleted before stopping)",
+                    item.name
+                )));
+            }
+            Err(e) => {
+                eprintln!("error: failed to delete `{}`: {e:#}", item.name);
+                failed.push(&item.name);
+            }
+        }
+    }
+
+    eprintln!("deleted {deleted} of {count} namespace(s) from folder `{folder}`");
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} namespace(s) could not be deleted: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Ask for the number of namespaces about to be deleted, as a deliberate
+/// confirmation; anything else aborts.
+fn confirm_deletion(count: usize) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("refusing to delete without confirmation; pass --yes to proceed");
+    }
+    eprint!("Type the number of namespaces to delete ({count}) to confirm: ");
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("failed to read confirmation")?;
+    if answer.trim() != count.to_string() {
+        anyhow::bail!("aborted; nothing was deleted");
+    }
+    Ok(())
+}
+
+/// `clean`: delete the namespaces in `folder` that parse to no keys.
+///
+/// Namespaces with content bwenv can't read (raw `set-raw` content, garbled
+/// lines) are skipped rather than treated as empty, as are non-note entry
+/// types and Logins that still hold a password.  Each candidate is checked
+/// again under its lock just before deletion, so one that gained keys in the
+/// meantime survives.
+fn cmd_clean(folder: &str, dry_run: bool, yes: bool) -> Result<()> {
+    validate_identifier(folder, "folder")?;
+    let mut items = rbw::list_items(folder)?;
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+    items.retain(|item| {
+        let supported = matches!(item.item_type.as_str(), "Login" | "Note");
+        if !supported {
+            eprintln!(
+                "skipping `{}`: a {} entry, not a namespace",
+                item.name, item.item_type
+            );
+        }
+        supported
+    });
+    let requests: Vec<(String, String)> = items
+        .iter()
+        .map(|item| (item.name.clone(), folder.to_string()))
+        .collect();
+    let results = rbw::get_notes_many(&requests);
+
+    let mut empty: Vec<&str> = Vec::new();
+    let mut failed: Vec<&str> = Vec::new();
+    for (item, result) in items.iter().zip(results) {
+        match result {
+            Ok(Some(mut notes)) => {
+                match holds_no_keys(&notes) {
+                    Some(true) => match rbw::get_item(&item.name, folder) {
+                        Ok(Some(full)) => match keep_reason(&full) {
+                            Some(reason) => eprintln!("skipping `{}`: {reason}", item.name),
+                            None => empty.push(&item.name),
+                        },
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("error: failed to read `{}`: {e:#}", item.name);
+                            failed.push(&item.name);
+                        }
+                    },
+                    Some(false) => {}
+                    None => eprintln!(
+                        "skipping `{}`: has content that isn't KEY=VALUE lines (see `bwenv verify`)",
+                        item.name
+                    ),
+                }
+                notes.zeroize();
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("error: failed to read `{}`: {e:#}", item.name);
+                failed.push(&item.name);
+            }
+        }
+    }
+
+    let count = empty.len();
+    if count == 0 {
+        eprintln!("no empty namespaces in folder `{folder}`");
+    } else {
+        eprintln!("{count} empty namespace(s) in folder `{folder}`:");
+        for name in &empty {
+            eprintln!("  {name}");
+        }
+    }
+    if count > 0 && !dry_run {
+        if !yes {
+            confirm_deletion(count)?;
+        }
+        let mut deleted = 0;
+        for name in &empty {
+            match delete_if_empty(folder, name) {
+                Ok(None) => deleted += 1,
+                Ok(Some(reason)) => eprintln!("kept `{name}`: {reason}"),
+                Err(e) => {
+                    eprintln!("error: failed to delete `{name}`: {e:#}");
+                    failed.push(name);
+                }
+            }
+        }
+        eprintln!("deleted {deleted} of {count} empty namespace(s) from folder `{folder}`");
+    }
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} namespace(s) could not be cleaned: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// `Some(true)` for notes with no keys (only blank lines and comments),
+/// `Some(false)` when there are keys, `None` for content that doesn't parse.
+fn holds_no_keys(notes: &str) -> Option<bool> {
+    if store::lint(notes).iter().any(|issue| issue.error) {
+        return None;
+    }
+    let mut pairs = store::parse(notes);
+    let empty = pairs.is_empty();
+    for value in pairs.values_mut() {
+        value.zeroize();
+    }
+    Some(empty)
+}
+
+/// Why `clean` must not delete `item`, if it mustn't.
+fn keep_reason(item: &rbw::RbwItem) -> Option<&'static str> {
+    if holds_no_keys(item.env_content().unwrap_or_default()) != Some(true) {
+        Some("it has content now")
+    } else if item.password().is_some_and(|p| !p.is_empty()) {
+        Some("it is a Login that holds a password")
+    } else {
+        None
+    }
+}
+
+/// Delete `namespace` if, re-read under its lock, there is still no reason
+/// to keep it; otherwise return the reason.
+fn delete_if_empty(folder: &str, namespace: &str) -> Result<Option<&'static str>> {
+    let _lock = lock::namespace(folder, namespace)?;
+    let Some(item) = rbw::get_item(namespace, folder)? else {
+        return Ok(Some("it no longer exists"));
+    };
+    let reason = keep_reason(&item);
+    if reason.is_none() {
+        rbw::delete_item(namespace, folder)?;
+    }
+    Ok(reason)
+}
+
+// ── Helpers ────────────────────────────────────────────────────────────────────
+
+/// Reject values that could be misinterpreted as `rbw` flags or that contain
+/// characters unsafe to pass as CLI arguments.
+///
+/// Rules:
+/// - Must not be empty.
+/// - Must not start with `-` (would be parsed as a flag by rbw).
+/// - Must not contain a null byte (undefined behaviour in argv).
+fn validate_identifier(value: &str, label: &str) -> Result<()> {
+    if value.is_empty() {
+        anyhow::bail!("{label} must not be empty");
+    }
+    if value.starts_with('-') {
+        anyhow::bail!("{label} must not start with '-': {value:?}");
+    }
+    if value.contains('\0') {
+        anyhow::bail!("{label} must not contain null bytes");
+    }
+    if value.contains(',') {
+        anyhow::bail!("{label} must not contain commas: {value:?}");
+    }
+    Ok(())
+}
+
+/// [`validate_identifier`] for an exec-mode namespace list entry: the
+/// namespace part of `PREFIX:namespace`, which is what reaches rbw.
+fn validate_namespace_entry(entry: &str) -> Result<()> {
+    validate_identifier(resolve::split_prefix(entry).1, "namespace")
+}
+
+/// Sort listed entries by `key`, breaking ties by name.
+fn sort_items(items: &mut [rbw::ListItem], key: SortKey) {
+    items.sort_by(|a, b| {
+        let primary = match key {
+            SortKey::Name => std::cmp::Ordering::Equal,
+            SortKey::Type => a.item_type.cmp(&b.item_type),
+            SortKey::Folder => a.folder.cmp(&b.folder),
+        };
+        primary.then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+/// Match `name` against a shell-style glob: `*` matches any run of
+/// characters, `?` matches exactly one, everything else matches literally.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Position of the last `*` seen in the pattern and the name index it was
+    // tried against, so we can backtrack and let it swallow one more char.
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Locate `cmd` the way `execvp` would: paths containing a separator are
+/// checked directly, bare names are searched for on `PATH`.
+fn find_executable(cmd: &str) -> Option<std::path::PathBuf> {
+    use std::path::{Path, PathBuf};
+
+    fn is_executable(path: &Path) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            path.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        }
+        #[cfg(not(unix))]
+        {
+            path.is_file()
+        }
+    }
+
+    // On Windows `foo` may resolve to `foo.exe`, `foo.cmd`, …
+    let candidates = |base: PathBuf| -> Vec<PathBuf> {
+        let mut out = vec![base.clone()];
+        if cfg!(windows) {
+            let exts = env::var("PATHEXT").unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".into());
+            out.extend(exts.split(';').map(|ext| {
+                let mut p = base.clone().into_os_string();
+                p.push(ext);
+                PathBuf::from(p)
+            }));
+        }
+        out
+    };
+
+    if cmd.contains(std::path::MAIN_SEPARATOR) || cmd.contains('/') {
+        return candidates(PathBuf::from(cmd))
+            .into_iter()
+            .find(|p| is_executable(p));
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .flat_map(|dir| candidates(dir.join(cmd)))
+        .find(|p| is_executable(p))
+}
+
+/// Parse `KEY=VALUE` command-line assignments, validating each key.
+/// Errors never include the value.
+fn parse_assignments(args: &[String]) -> Result<Vec<(String, String)>> {
+    args.iter()
+        .map(|arg| {
+            let (key, value) = arg
+                .split_once('=')
+                .with_context(|| format!("expected KEY=VALUE, got `{arg}`"))?;
+            if !store::is_valid_env_key(key) {
+                anyhow::bail!("invalid environment variable name: {key:?}");
+            }
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Split a comma-separated namespace argument, dropping empty segments.
+fn split_namespaces(arg: &str) -> Result<Vec<String>> {
+    let namespaces: Vec<String> = arg
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if namespaces.is_empty() {
+        anyhow::bail!("namespace must not be empty");
+    }
+    Ok(namespaces)
+}
+
+/// Whether `--verbose` was given.
+fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Resolve the folder: `--folder`/`-f` > `BWENV_FOLDER` > config `folder` >
+/// default, normalized so nested paths (`parent/child`) reach rbw in the
+/// form it reports them.  (Commands that take a folder argument, such as
+/// `verify`, use that instead.)
+fn resolve_folder(cli_folder: Option<&str>) -> Result<String> {
+    let env_folder = env::var(FOLDER_ENV).ok();
+    Ok(pick_folder(
+        cli_folder,
+        env_folder.as_deref(),
+        config::get()?.folder.as_deref(),
+    ))
+}
+
+fn pick_folder(cli: Option<&str>, env: Option<&str>, config: Option<&str>) -> String {
+    let folder = cli.or(env).or(config).unwrap_or(DEFAULT_FOLDER);
+    rbw::normalize_folder(folder)
+}
+
+/// The namespace given on the command line, or (with `--from-git` or
+/// `--select`, one of which clap requires when it is missing) the one for
+/// this git checkout or one picked from a menu.
+fn namespace_or_pick(namespace: Option<String>, folder: &str, from_git: bool) -> Result<String> {
+    match namespace {
+        Some(ns) => Ok(ns),
+        None if from_git => git_namespace(),
+        None => pick::namespace(folder),
+    }
+}
+
+/// `--from-git`: the namespace for the current checkout.
+fn git_namespace() -> Result<String> {
+    let template = config::get()?.git_namespace.as_deref();
+    let namespace = git::namespace(template.unwrap_or(git::DEFAULT_TEMPLATE))?;
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprintln!("--from-git: using namespace `{namespace}`");
+    }
+    Ok(namespace)
+}
+
+fn resolve_profile(cli_profile: Option<&str>) -> Result<Option<String>> {
+    let profile = cli_profile
+        .map(str::to_string)
+        .or_else(|| env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()));
+    if let Some(p) = &profile
+        && (p.is_empty() || p.contains('/'))
+    {
+        anyhow::bail!("invalid profile `{p}`: must be non-empty and contain no `/`");
+    }
+    Ok(profile)
+}
+
+/// Parse notes (or a local env file) for reading, surfacing duplicate keys
+/// and `export` prefixes: an error under `--strict-parse`, a warning under
+/// `--verbose`, otherwise silently tolerated.  `source` names the namespace or
+/// file in messages.
+fn read_pairs(source: &str, notes: &str) -> Result<HashMap<String, String>> {
+    read_parsed(source, notes).map(|parsed| parsed.pairs)
+}
+
+/// [`read_pairs`], keeping the rest of what the parser reported (e.g. which
+/// values are base64-encoded).
+fn read_parsed(source: &str, notes: &str) -> Result<store::Parsed> {
+    let parsed = store::parse_checked(notes, !NO_TRIM.load(Ordering::Relaxed));
+    if !parsed.exported.is_empty() {
+        let keys = parsed.exported.join(", ");
+        if STRICT_PARSE.load(Ordering::Relaxed) {
+            anyhow::bail!("`export` prefix on key(s) in `{source}`: {keys}");
+        }
+        if verbose() {
+            eprintln!("note: ignoring `export` prefix on key(s) in `{source}`: {keys}");
+        }
+    }
+    if !parsed.duplicates.is_empty() {
+        let keys = parsed.duplicates.join(", ");
+        if STRICT_PARSE.load(Ordering::Relaxed) {
+            anyhow::bail!("duplicate key(s) in `{source}`: {keys}");
+        }
+        if verbose() {
+            eprintln!("warning: duplicate key(s) in `{source}`: {keys}; using the last value");
+        }
+    }
+    Ok(parsed)
+}
+
+/// Decode a `KEY!=<base64>` value into the raw bytes an env var can hold.
+fn decode_value(key: &str, value: &str) -> Result<Vec<u8>> {
+    let bytes = base64::decode(value).with_context(|| format!("cannot decode `{key}`"))?;
+    if bytes.contains(&0) {
+        anyhow::bail!(
+            "decoded `{key}` contains a NUL byte, which an environment variable cannot hold"
+        );
+    }
+    Ok(bytes)
+}
+
+/// Turn decoded bytes into an env-var value.
+fn os_value(key: &str, bytes: Vec<u8>) -> Result<std::ffi::OsString> {
+    #[cfg(unix)]
+    {
+        let _ = key;
+        use std::os::unix::ffi::OsStringExt as _;
+        Ok(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(bytes)
+            .map(Into::into)
+            .map_err(|_| anyhow::anyhow!("decoded `{key}` is not valid UTF-8"))
+    }
+}
+
+/// Resolve the batch concurrency limit: CLI flag > env var > default.
+fn resolve_max_concurrency(cli_value: Option<u32>) -> Result<usize> {
+    if let Some(n) = cli_value {
+        return Ok(n as usize);
+    }
+    match env::var(MAX_CONCURRENCY_ENV) {
+        Ok(raw) => match raw.trim().parse::<usize>() {
+            Ok(n) if n >= 1 => Ok(n),
+            _ => anyhow::bail!("{MAX_CONCURRENCY_ENV} must be an integer >= 1, got {raw:?}"),
+        },
+        Err(_) => Ok(rbw::DEFAULT_MAX_CONCURRENCY),
+    }
+}
+
+/// Load env pairs for a namespace from the notes-field KEY=VALUE lines.
+/// Read-only, so it may be served from the local cache.
+fn load_env_pairs(folder: &str, namespace: &str) -> Result<HashMap<String, String>> {
+    let notes = rbw::get_notes(namespace, folder)?
+        .with_context(|| format!("namespace `{namespace}` not found in folder `{folder}`"))?;
+
+    read_pairs(namespace, &notes)
+}
+
+/// Write a namespace note: create it when `existing` is `None`, otherwise edit
+/// the existing entry in place (preserving its non-notes data).
+///
+/// With `BWENV_MARK_MANAGED=1` the notes get a `# managed-by: bwenv` header,
+/// and editing an entry that lacks one prints a warning first, since it may
+/// be an ordinary Bitwarden note rather than a bwenv namespace.
+///
+/// Every key that is added or changed is checked against the configured
+/// key policy (and, with `--strict-keys`, the naming convention) first; keys
+/// already stored unchanged are not re-checked.
+fn write_namespace(
+    folder: &str,
+    namespace: &str,
+    notes: &str,
+    existing: Option<&rbw::RbwItem>,
+) -> Result<()> {
+    check_written_keys(namespace, notes, existing)?;
+    if !mark_managed() {
+        return match existing {
+            None => rbw::create_item(namespace, folder, notes),
+            Some(item) => rbw::edit_item(namespace, folder, notes, item),
+        };
+    }
+
+    if let Some(item) = existing {
+        let current = item.notes.as_deref().unwrap_or_default();
+        if !current.trim().is_empty() && !store::is_managed(current) {
+            eprintln!(
+                "WARNING: namespace `{namespace}` has no `{}` header; \
+                 it may not be a bwenv namespace",
+                store::MANAGED_HEADER
+            );
+        }
+    }
+    let mut notes = store::with_managed_header(notes);
+    let result = match existing {
+        None => rbw::create_item(namespace, folder, &notes),
+        Some(item) => rbw::edit_item(namespace, folder, &notes, item),
+    };
+    notes.zeroize();
+    result
+}
+
+fn check_written_keys(namespace: &str, notes: &str, existing: Option<&rbw::RbwItem>) -> Result<()> {
+    let policy = &config::get()?.policy;
+    let strict_keys = strict_keys()?;
+    if policy.allow.is_empty() && policy.deny.is_empty() && !strict_keys {
+        return Ok(());
+    }
+    let before = store::parse(
+        existing
+            .and_then(|i| i.notes.as_deref())
+            .unwrap_or_default(),
+    );
+    let after = store::parse(notes);
+    let mut keys: Vec<&String> = after
+        .iter()
+        .filter(|&(key, value)| before.get(key) != Some(value))
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort();
+    if strict_keys {
+        check_key_convention(keys.iter().map(|key| key.as_str()))?;
+    }
+    keys.into_iter()
+        .try_for_each(|key| policy.check(namespace, key))
+}
+
+/// Whether `--strict-keys` (or config `strict_keys`) is in effect.
+fn strict_keys() -> Result<bool> {
+    Ok(STRICT_KEYS.load(Ordering::Relaxed) || config::get()?.strict_keys)
+}
+
+/// `--strict-keys`: fail naming every key that isn't UPPERCASE_WITH_UNDERSCORES.
+fn check_key_convention<'a>(keys: impl Iterator<Item = &'a str>) -> Result<()> {
+    let offending: Vec<&str> = keys
+        .filter(|key| !store::is_conventional_key(key))
+        .collect();
+    if !offending.is_empty() {
+        anyhow::bail!(
+            "--strict-keys: key(s) not UPPERCASE_WITH_UNDERSCORES: {}",
+            offending.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Whether `BWENV_MARK_MANAGED` asks for the managed-by header.
+fn mark_managed() -> bool {
+    env::var(MARK_MANAGED_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true" | "yes"))
+}
+
+// ── Entry point ────────────────────────────────────────────────────────────────
+
+fn main() {
+    let result = run();
+    timings::report();
+    if let Err(e) = result {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(host) = &cli.remote {
+        let code = remote::run(host, env::args_os().skip(1).collect())?;
+        std::process::exit(code);
+    }
+    VERBOSE.store(cli.verbose, Ordering::Relaxed);
+    STRICT_PARSE.store(cli.strict_parse, Ordering::Relaxed);
+    STRICT_KEYS.store(cli.strict_keys, Ordering::Relaxed);
+    NO_TRIM.store(cli.no_trim, Ordering::Relaxed);
+    store::set_keep_cr(cli.keep_cr);
+    mask::set(cli.mask_char, cli.mask_show)?;
+    if let Some(profile) = resolve_profile(cli.profile.as_deref())? {
+        rbw::set_profile(&profile);
+    }
+    if cli.timings {
+        timings::enable();
+    }
+    if cli.trace_rbw {
+        timings::enable_trace();
+        // Spinner frames would interleave with the trace lines.
+        spinner::disable();
+    }
+    rbw::set_no_unlock(cli.no_unlock);
+    rbw::set_timeouts(cli.timeout.unwrap_or(0), cli.timeout_unlock);
+    rbw::set_sync_mode(cli.offline, cli.require_sync);
+    rbw::set_include_trashed(cli.include_trashed);
+    rbw::set_create_folder(cli.create_folder);
+    if let Some(command) = &cli.value_filter {
+        filter::set(command);
+    }
+    if let Some(field) = cli.field.as_deref().or(config::get()?.field.as_deref()) {
+        rbw::set_source_field(field);
+    }
+    rbw::set_max_concurrency(resolve_max_concurrency(cli.max_concurrency)?);
+    let folder = resolve_folder(cli.folder.as_deref())?;
+
+    let opts = ExecOptions {
+        clean_env: cli.clean_env,
+        keep: cli.keep,
+        env_file: cli.env_file,
+        redact: cli.redact,
+        decode: cli.decode,
+        expand: cli.expand_from_env.then_some(cli.allow_missing),
+        fail_empty: cli.fail_empty,
+    };
+
+    if cli.sync || cli.require_sync {
+        rbw::sync()?;
+    }
+
+    if let Some(command) = cli.command {
+        match command {
+            Commands::Unlock => rbw::unlock(),
+
+            Commands::Shell {
+                namespace,
+                select: _,
+                from_git,
+                lock,
+            } => {
+                let namespace = namespace_or_pick(namespace, &folder, from_git)?;
+                cmd_shell(&folder, &split_namespaces(&namespace)?, lock, &opts)
+            }
+
+            Commands::Env {
+                mut namespaces,
+                select,
+                from_git,
+                env_file,
+                show_values,
+            } => {
+                if select || from_git {
+                    namespaces.push(namespace_or_pick(None, &folder, from_git)?);
+                }
+                let mut split = Vec::new();
+                for arg in &namespaces {
+                    split.extend(split_namespaces(arg)?);
+                }
+                cmd_env(
+                    &folder,
+                    &split,
+                    env_file.as_deref().or(opts.env_file.as_deref()),
+                    opts.expand,
+                    show_values,
+                )
+            }
+
+            Commands::Set {
+                namespace,
+                vars,
+                stdin,
+                noecho: _,
+                encode,
+                append,
+                sep,
+                check,
+                if_missing,
+                error_if_exists,
+                value_file,
+                trim_file,
+            } => match encode {
+                Some(path) => cmd_set_encoded(&folder, &namespace, &vars, &path),
+                None => cmd_set(
+                    &folder,
+                    &namespace,
+                    &vars,
+                    &SetOptions {
+                        from_stdin: stdin,
+                        append,
+                        sep,
+                        check,
+                        if_missing,
+                        error_if_exists,
+                        value_file,
+                        trim_file,
+                    },
+                ),
+            },
+
+            Commands::SetRaw {
+                namespace,
+                stdin: _,
+                input,
+            } => cmd_set_raw(&folder, &namespace, input.as_deref()),
+
+            Commands::CpKey {
+                source,
+                destination,
+                key,
+                move_key,
+            } => cmd_cp_key(&folder, &source, &destination, &key, move_key),
+
+            Commands::Rotate {
+                namespace,
+                key,
+                length,
+                charset,
+                print,
+            } => cmd_rotate(&folder, &namespace, &key, length as usize, charset, print),
+
+            Commands::Ensure {
+                namespace,
+                assignments,
+            } => cmd_ensure(&folder, &namespace, &assignments),
+
+            Commands::List { all: true, .. } => cmd_list_all(),
+
+            Commands::List {
+                namespace,
+                select,
+                from_git,
+                show_value,
+                all: false,
+                null,
+                sort,
+                reverse,
+                filter,
+                json,
+                count,
+                porcelain,
+                tree,
+                namespace_separator,
+            } => cmd_list(
+                &folder,
+                (if select || from_git {
+                    Some(namespace_or_pick(None, &folder, from_git)?)
+                } else {
+                    namespace
+                })
+                .as_deref(),
+                &ListOptions {
+                    show_value,
+                    null,
+                    filter,
+                    json,
+                    count,
+                    porcelain,
+                    sort,
+                    reverse,
+                    tree: if tree {
+                        Some(namespace_separator_for(namespace_separator)?)
+                    } else {
+                        None
+                    },
+                },
+            ),
+
+            Commands::Unset { namespace, vars } => cmd_unset(&folder, &namespace, &vars),
+
+            Commands::Import {
+                namespace,
+                input,
+                url,
+                headers,
+                format,
+                merge,
+                dry_run,
+                porcelain,
+                check,
+                only_changed,
+            } => {
+                let source = match (url, input) {
+                    (Some(url), _) => ImportSource::Url { url, headers },
+                    (None, Some(path)) if path.as_os_str() != "-" => ImportSource::File(path),
+                    (None, _) => ImportSource::Stdin,
+                };
+                cmd_import(
+                    &folder,
+                    &namespace,
+                    source,
+                    &ImportOptions {
+                        format,
+                        merge,
+                        dry_run,
+                        porcelain,
+                        check,
+                        only_changed,
+                    },
+                )
+            }
+
+            Commands::ImportAll {
+                input,
+                format,
+                merge,
+                dry_run,
+                check,
+                only_changed,
+            } => cmd_import_all(
+                &folder,
+                &input,
+                &ImportOptions {
+                    format,
+                    merge,
+                    dry_run,
+                    porcelain: false,
+                    check,
+                    only_changed,
+                },
+            ),
+
+            Commands::Push {
+                namespace,
+                input,
+                merge,
+                dry_run,
+                check,
+            } => cmd_import(
+                &folder,
+                &namespace,
+                ImportSource::File(input),
+                &ImportOptions {
+                    format: ImportFormat::Dotenv,
+                    merge,
+                    dry_run,
+                    porcelain: false,
+                    check,
+                    only_changed: false,
+                },
+            ),
+
+            Commands::Pull { namespace, output } => cmd_pull(&folder, &namespace, &output),
+
+            Commands::MergeNamespaces {
+                destination,
+                sources,
+                fail_on_conflict,
+                prune,
+            } => cmd_merge_namespaces(&folder, &destination, &sources, fail_on_conflict, prune),
+
+            Commands::Snapshot {
+                namespace,
+                only,
+                except,
+                include_all,
+            } => cmd_snapshot(&folder, &namespace, &only, &except, include_all),
+
+            Commands::Export {
+                namespace,
+                fd,
+                output_file,
+                decode,
+                expand_from_env,
+                allow_missing,
+                format,
+                infer_types,
+                no_trailing_newline,
+                also_stdout,
+                fail_empty,
+                preserve_order,
+                sorted: _,
+                keep_descriptions,
+            } => cmd_export(
+                &folder,
+                &namespace,
+                export_targets(
+                    fd,
+                    &output_file,
+                    also_stdout,
+                    resolve_export_format(format, &namespace)?,
+                )?,
+                &ExportOptions {
+                    infer_types,
+                    trailing_newline: !no_trailing_newline,
+                    decode,
+                    expand: expand_from_env.then_some(allow_missing),
+                    preserve_order,
+                    fail_empty,
+                    keep_descriptions,
+                },
+            ),
+
+            Commands::Create {
+                namespace,
+                from_namespace,
+                with_values,
+            } => cmd_create(&folder, &namespace, from_namespace.as_deref(), with_values),
+
+            Commands::Scaffold { namespace, output } => {
+                cmd_scaffold(&folder, &namespace, output.as_deref())
+            }
+
+            Commands::Describe {
+                namespace,
+                key,
+                text,
+            } => cmd_describe(&folder, &namespace, &key, text.as_deref()),
+
+            Commands::Info {
+                namespace,
+                select: _,
+                from_git,
+            } => cmd_info(&folder, &namespace_or_pick(namespace, &folder, from_git)?),
+
+            Commands::Fingerprint { namespace } => cmd_fingerprint(&folder, &namespace),
+
+            Commands::Log { namespace } => cmd_log(&folder, &namespace),
+
+            Commands::WithFile {
+                namespace,
+                key,
+                format,
+                var,
+                name,
+                command,
+            } => cmd_with_file(
+                &folder,
+                &namespace,
+                &command,
+                &WithFileOptions {
+                    key,
+                    format,
+                    var,
+                    name,
+                },
+            ),
+
+            Commands::ExecEach {
+                target,
+                filter,
+                jobs,
+                command,
+            } => cmd_exec_each(
+                &rbw::normalize_folder(&target),
+                filter.as_deref(),
+                jobs as usize,
+                &command,
+                &opts,
+            ),
+
+            Commands::Verify { target, porcelain } => {
+                cmd_verify(&rbw::normalize_folder(&target), porcelain)
+            }
+
+            Commands::Exists { namespace, key } => {
+                if !cmd_exists(&folder, &namespace, key.as_deref())? {
+                    timings::report();
+                    std::process::exit(1);
+                }
+                Ok(())
+            }
+
+            Commands::Whoami => cmd_whoami(),
+
+            Commands::Open {
+                namespace,
+                select: _,
+                from_git,
+                print,
+            } => cmd_open(
+                &folder,
+                &namespace_or_pick(namespace, &folder, from_git)?,
+                print,
+            ),
+
+            Commands::Compare {
+                left,
+                right,
+                left_profile,
+                right_profile,
+                show_values,
+                json,
+            } => {
+                let right = right.unwrap_or_else(|| left.clone());
+                cmd_compare(
+                    &folder,
+                    (&left, left_profile.as_deref()),
+                    (&right, right_profile.as_deref()),
+                    show_values,
+                    json,
+                )
+            }
+
+            Commands::WatchNs {
+                namespace,
+                interval,
+                show_values,
+            } => cmd_watch_ns(
+                &folder,
+                &namespace,
+                std::time::Duration::from_secs(interval),
+                show_values,
+            ),
+
+            Commands::Completions { shell } => {
+                print!("{}", complete::script(shell));
+                Ok(())
+            }
+
+            Commands::Complete { words } => complete::run(&folder, &words),
+
+            Commands::DeleteAll {
+                target,
+                filter,
+                yes,
+                strict,
+            } => cmd_delete_all(
+                &rbw::normalize_folder(&target),
+                filter.as_deref(),
+                yes,
+                strict,
+            ),
+
+            Commands::Clean {
+                target,
+                dry_run,
+                yes,
+            } => cmd_clean(&rbw::normalize_folder(&target), dry_run, yes),
         }
-        cmd_exec(&folder, &namespaces, &command, &cli.exec_args)
+    } else if cli.use_stored_cmd {
+        let namespaces = match (cli.from_git, cli.namespace) {
+            (true, None) => split_namespaces(&git_namespace()?)?,
+            (false, Some(namespace_arg)) => split_namespaces(&namespace_arg)?,
+            (true, Some(_)) => anyhow::bail!("--use-stored-cmd with --from-git takes no arguments"),
+            (false, None) => anyhow::bail!("--use-stored-cmd needs a namespace"),
+        };
+        cmd_exec_stored(&folder, &namespaces, &opts)
+    } else if cli.from_git
+        && let Some(command) = cli.namespace
+    {
+        // `bwenv --from-git <prog> [args]`: the first positional is the program.
+        let args: Vec<String> = cli.exec_command.into_iter().chain(cli.exec_args).collect();
+        let namespaces = split_namespaces(&git_namespace()?)?;
+        cmd_exec(&folder, &namespaces, &command, &args, &opts)
+    } else if let (Some(namespace_arg), Some(command)) = (cli.namespace, cli.exec_command) {
+        let namespaces = split_namespaces(&namespace_arg)?;
+        cmd_exec(&folder, &namespaces, &command, &cli.exec_args, &opts)
     } else {
         Cli::command().print_help().ok();
         std::process::exit(2);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_executable_searches_path() {
+        assert!(find_executable("sh").is_some());
+        assert!(find_executable("/bin/sh").is_some());
+        assert!(find_executable("bwenv-definitely-not-a-command").is_none());
+        assert!(find_executable("/etc/hostname-not-executable").is_none());
+    }
+
+    #[test]
+    fn prefixed_namespace_entries_validate_the_namespace() {
+        assert!(validate_namespace_entry("db:postgres").is_ok());
+        assert!(validate_namespace_entry(":app:prod").is_ok());
+        assert!(validate_namespace_entry("db:--raw").is_err());
+        assert!(validate_namespace_entry(":-x").is_err());
+        assert!(validate_namespace_entry("-x").is_err());
+    }
+
+    #[test]
+    fn descriptions_must_be_single_lines() {
+        assert!(check_description("database password").is_ok());
+        assert!(check_description("x\nEVIL=1").is_err());
+        assert!(check_description("x\rEVIL=1").is_err());
+        assert!(check_description("x\r\n").is_err());
+    }
+
+    #[test]
+    fn prune_only_deletes_sources_as_they_were_merged() {
+        let item = |notes: &str| -> rbw::RbwItem {
+            serde_json::from_value(serde_json::json!({"type": "Note", "notes": notes})).unwrap()
+        };
+        assert!(unchanged_since_read("A=1", Some(&item("A=1"))));
+        assert!(!unchanged_since_read("A=1", Some(&item("A=1\nB=2"))));
+        assert!(!unchanged_since_read("A=1", None));
+        assert_eq!(
+            lock_order(["src2", "dest", "src1", "src2"]),
+            ["dest", "src1", "src2"]
+        );
+    }
+
+    #[test]
+    fn parse_assignments_splits_on_first_equals() {
+        let pairs = parse_assignments(&["A=1".into(), "URL=a=b".into(), "E=".into()]).unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                ("A".into(), "1".into()),
+                ("URL".into(), "a=b".into()),
+                ("E".into(), String::new())
+            ]
+        );
+        assert!(parse_assignments(&["NOVALUE".into()]).is_err());
+        assert!(parse_assignments(&["1BAD=x".into()]).is_err());
+    }
+
+    #[test]
+    fn resolved_env_lists_masked_values_with_origin() {
+        let resolved = resolve::Resolved {
+            pairs: HashMap::from([
+                ("B".to_string(), "2".to_string()),
+                ("A".to_string(), "1".to_string()),
+                ("BIN".to_string(), "AA==".to_string()),
+            ]),
+            origins: HashMap::from([
+                ("A".to_string(), "base".to_string()),
+                ("B".to_string(), ".env.local".to_string()),
+                ("BIN".to_string(), "dev".to_string()),
+            ]),
+            encoded: ["BIN".to_string()].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_resolved(&resolved, false),
+            "A=***  # base\nB=***  # .env.local\nBIN=***  # dev, base64\n"
+        );
+        assert!(format_resolved(&resolved, true).starts_with("A=1  # base\n"));
+    }
+
+    #[test]
+    fn import_json_requires_flat_strings_and_valid_names() {
+        let parsed = parse_import("in", r#"{"A": "1", "B": "x y"}"#, ImportFormat::Json).unwrap();
+        assert_eq!(parsed.pairs["B"], "x y");
+        let err = parse_import("in", r#"{"A": 1, "B": {}}"#, ImportFormat::Json).unwrap_err();
+        assert!(err.to_string().contains("key(s): A, B"), "{err}");
+        assert!(parse_import("in", "[]", ImportFormat::Json).is_err());
+        assert!(parse_import("in", r#"{"1X": "v"}"#, ImportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn import_all_wants_an_object_of_flat_objects() {
+        let all = parse_import_all("all", r#"{"prod": {"A": "1"}, "dev": {}}"#).unwrap();
+        let names: Vec<&str> = all.iter().map(|(ns, _)| ns.as_str()).collect();
+        assert_eq!(names, ["dev", "prod"]);
+        assert_eq!(all[1].1.pairs["A"], "1");
+        let err = parse_import_all("all", r#"{"dev": {"A": 1}}"#).unwrap_err();
+        assert!(err.to_string().contains("`all: dev`"), "{err}");
+        assert!(parse_import_all("all", r#"{"dev": "A=1"}"#).is_err());
+        assert!(parse_import_all("all", r#"{"dev": {"bad key": "v"}}"#).is_err());
+        assert!(parse_import_all("all", r#"{"-x": {}}"#).is_err());
+    }
+
+    #[test]
+    fn tree_nests_names_by_separator() {
+        let names = ["prod/db", "app", "app/web/prod", "app/api", "solo"];
+        assert_eq!(
+            namespace_tree(names, "/"),
+            "app\napp/\n  api\n  web/\n    prod\nprod/\n  db\nsolo\n"
+        );
+        assert_eq!(
+            namespace_tree(["app.prod", "app.dev", "app/x"], "."),
+            "app.\n  dev\n  prod\napp/x\n"
+        );
+    }
+
+    #[test]
+    fn snapshot_skips_session_variables_unless_include_all() {
+        let vars = || {
+            [
+                ("PATH", "/bin"),
+                ("LC_ALL", "C"),
+                ("SSH_AUTH_SOCK", "/tmp/s"),
+                ("API_URL", "https://x"),
+                ("API_KEY", "k"),
+                ("bad-name", "v"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into_iter()
+        };
+        let keys = |pairs: HashMap<String, String>| {
+            let mut keys: Vec<String> = pairs.into_keys().collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(
+            keys(snapshot_pairs(vars(), &[], &[], false)),
+            ["API_KEY", "API_URL"]
+        );
+        assert_eq!(
+            keys(snapshot_pairs(vars(), &[], &[], true)),
+            ["API_KEY", "API_URL", "LC_ALL", "PATH", "SSH_AUTH_SOCK"]
+        );
+        let only = ["API_*".to_string(), "PATH".to_string()];
+        let except = ["*_KEY".to_string()];
+        assert_eq!(
+            keys(snapshot_pairs(vars(), &only, &except, false)),
+            ["API_URL"]
+        );
+        assert_eq!(
+            keys(snapshot_pairs(vars(), &only, &except, true)),
+            ["API_URL", "PATH"]
+        );
+    }
+
+    #[test]
+    fn import_replaces_or_merges_in_place() {
+        let current = "# db\nDB=old\nKEEP=1";
+        let parsed = parse_import("in", "NEW=2\nDB=new", ImportFormat::Dotenv).unwrap();
+        assert_eq!(
+            apply_import(current, &parsed, false, false),
+            "# db\nDB=new\nNEW=2"
+        );
+        assert_eq!(
+            apply_import(current, &parsed, true, false),
+            "# db\nDB=new\nKEEP=1\nNEW=2"
+        );
+    }
+
+    #[test]
+    fn only_changed_import_leaves_other_lines_alone() {
+        let current = "export A=1\nB=\"x\"\nC=old\nB=x";
+        let parsed = parse_import("in", "A=1\nB=x\nC=new", ImportFormat::Dotenv).unwrap();
+        assert_eq!(
+            apply_import(current, &parsed, false, false),
+            "A=1\nB=x\nC=new"
+        );
+        assert_eq!(
+            apply_import(current, &parsed, false, true),
+            "export A=1\nB=\"x\"\nC=new\nB=x"
+        );
+        let binary = parse_import("in", "A!=MQ==", ImportFormat::Dotenv).unwrap();
+        assert_eq!(apply_import("A=MQ==", &binary, false, true), "A!=MQ==");
+    }
+
+    #[test]
+    fn pulled_file_pushes_back_unchanged() {
+        let notes = "# desc: x\nB= padded \nA!=AAE=\nexport C=3";
+        let pulled = apply_import("", &store::parse_checked(notes, true), false, false);
+        assert_eq!(pulled, "A!=AAE=\nB=padded\nC=3");
+        let pushed = parse_import(".env", &pulled, ImportFormat::Dotenv).unwrap();
+        assert_eq!(apply_import(&pulled, &pushed, false, false), pulled);
+    }
+
+    #[test]
+    fn merge_layers_reports_real_conflicts() {
+        let layer = |name: &str, notes: &str| (name.to_string(), store::parse_checked(notes, true));
+        let (merged, conflicts) = merge_layers(vec![
+            layer("dst", "A=0"),
+            layer("one", "A=0\nB=1\nC=1"),
+            layer("two", "B=2\nC=1\nD!=AA=="),
+        ]);
+        assert_eq!(merged.pairs["B"], "2");
+        assert_eq!(merged.pairs.len(), 4);
+        assert_eq!(merged.encoded, ["D"]);
+        assert_eq!(
+            conflicts,
+            [Conflict {
+                key: "B".into(),
+                previous: "one".into(),
+                source: "two".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn porcelain_formats_are_stable() {
+        let item = |name: &str, folder: Option<&str>| rbw::ListItem {
+            id: Some("id".into()),
+            name: name.into(),
+            folder: folder.map(Into::into),
+            item_type: "Note".into(),
+            deleted_date: None,
+        };
+        assert_eq!(
+            porcelain_namespaces(&[item("dev api", Some("bwenv")), item("x", None)]),
+            "dev api\tNote\tbwenv\nx\tNote\t\n"
+        );
+
+        let parsed = store::parse_checked("B=2\nA!=AA==\n", true);
+        assert_eq!(porcelain_keys(&parsed), "A\tbase64\nB\ttext\n");
+
+        let diff = store::EnvDiff {
+            added: vec!["N".into()],
+            removed: vec!["R".into()],
+            changed: vec!["C".into()],
+        };
+        assert_eq!(porcelain_diff(&diff), "added\tN\nchanged\tC\nremoved\tR\n");
+
+        let issues = store::lint("A=1\nnot a line\n");
+        assert_eq!(
+            format_verify("dev", &issues, true, true),
+            format!("fail\tdev\nissue\tdev\t2\terror\t{}\n", issues[0].message)
+        );
+        assert_eq!(format_verify("ok", &[], false, true), "ok\tok\n");
+        assert_eq!(
+            format_verify_failure("gone", "not found", true),
+            "fail\tgone\nissue\tgone\t0\terror\tnot found\n"
+        );
+    }
+
+    #[test]
+    fn utc_timestamps() {
+        let at = |secs| utc_timestamp(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_714_557_600), "2024-05-01T10:00:00Z");
+    }
+
+    #[test]
+    fn env_diff_masks_values_by_default() {
+        let old = store::parse("A=1\nB=2");
+        let new = store::parse("B=3\nC=4");
+        let diff = store::diff(&old, &new);
+        assert_eq!(
+            format_env_diff("T", &diff, &old, &new, false),
+            "[T]\n  + C=***\n  ~ B=*** -> ***\n  - A\n"
+        );
+        assert!(format_env_diff("T", &diff, &old, &new, true).contains("~ B=2 -> 3"));
+    }
+
+    #[test]
+    fn compare_json_lists_keys_and_optionally_values() {
+        let old = store::parse("A=1\nB=2");
+        let new = store::parse("B=3\nC=4");
+        let diff = store::diff(&old, &new);
+        let masked = compare_json("staging", "prod", &diff, (&old, &new), false);
+        assert_eq!(
+            masked,
+            serde_json::json!({
+                "left": "staging", "right": "prod",
+                "added": ["C"], "removed": ["A"], "changed": ["B"],
+            })
+        );
+        let shown = compare_json("staging", "prod", &diff, (&old, &new), true);
+        assert_eq!(
+            shown["values"],
+            serde_json::json!({
+                "A": {"left": "1", "right": null},
+                "B": {"left": "2", "right": "3"},
+                "C": {"left": null, "right": "4"},
+            })
+        );
+    }
+
+    #[test]
+    fn folder_precedence() {
+        let pick = pick_folder;
+        assert_eq!(pick(Some("cli"), Some("env"), Some("cfg")), "cli");
+        assert_eq!(pick(None, Some("env"), Some("cfg")), "env");
+        assert_eq!(pick(None, None, Some("/cfg//sub/")), "cfg/sub");
+        assert_eq!(pick(None, None, None), DEFAULT_FOLDER);
+        let cli = Cli::try_parse_from(["bwenv", "list", "-f", "myapp"]).unwrap();
+        assert_eq!(cli.folder.as_deref(), Some("myapp"));
+    }
+
+    #[test]
+    fn stored_command_is_neither_injected_nor_counted() {
+        let resolved = resolve::Resolved {
+            pairs: [("A", "1"), (STORED_CMD_KEY, "make deploy")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            ..Default::default()
+        };
+        let opts = ExecOptions {
+            clean_env: true,
+            keep: Vec::new(),
+            env_file: None,
+            redact: false,
+            decode: false,
+            expand: None,
+            fail_empty: true,
+        };
+        let (command, _) = build_command("sh", &[], resolved, &opts).unwrap();
+        let names: Vec<_> = command.get_envs().map(|(k, _)| k.to_owned()).collect();
+        assert!(names.contains(&"A".into()));
+        assert!(!names.contains(&STORED_CMD_KEY.into()));
+        let only_cmd = resolve::Resolved {
+            pairs: [(STORED_CMD_KEY.to_string(), "true".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(build_command("sh", &[], only_cmd, &opts).is_err());
+        assert!(Cli::try_parse_from(["bwenv", "--use-stored-cmd", "ns", "echo"]).is_err());
+    }
+
+    #[test]
+    fn copy_key_carries_encoding_and_description() {
+        let src = "# desc: signing key\nSIGN!=AAE=\nTOKEN=abc";
+        assert_eq!(
+            copy_key(src, "A=1", "SIGN").unwrap(),
+            "A=1\n# desc: signing key\nSIGN!=AAE="
+        );
+        assert_eq!(copy_key(src, "TOKEN=old", "TOKEN").unwrap(), "TOKEN=abc");
+        assert_eq!(copy_key(src, "", "MISSING"), None);
+    }
+
+    #[test]
+    fn clean_only_counts_readable_notes_without_keys_as_empty() {
+        assert_eq!(holds_no_keys(""), Some(true));
+        assert_eq!(holds_no_keys(" # managed-by: bwenv\n\n# note"), Some(true));
+        assert_eq!(holds_no_keys("A=1"), Some(false));
+        assert_eq!(holds_no_keys("-----BEGIN CERTIFICATE-----"), None);
+        assert_eq!(holds_no_keys("{\"json\": true}"), None);
+    }
+
+    #[test]
+    fn value_file_drops_final_line_ending() {
+        let dir = std::env::temp_dir().join(format!("bwenv-value-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("token");
+        for (content, trim, expected) in [
+            ("tok\n", false, Some("tok")),
+            ("tok\r\n", false, Some("tok")),
+            (" tok \n", false, Some(" tok ")),
+            (" tok \n\n", true, Some("tok")),
+            ("tok\n\n", false, None),
+            ("a\nb", true, None),
+        ] {
+            std::fs::write(&file, content).unwrap();
+            assert_eq!(
+                read_value_file(&file, trim).ok().as_deref(),
+                expected,
+                "{content:?}"
+            );
+        }
+        assert!(read_value_file(&dir.join("missing"), false).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_targets_pick_format_per_file() {
+        let outputs = ["a.env".to_string(), "env-json:b.json".to_string()];
+        let targets = export_targets(None, &outputs, true, ExportFormat::Shell).unwrap();
+        let shown: Vec<(String, ExportFormat)> = targets
+            .iter()
+            .map(|(target, format)| {
+                let name = match target {
+                    ExportTarget::Stdout => "-".to_string(),
+                    ExportTarget::Fd(fd) => fd.to_string(),
+                    ExportTarget::File(path) => path.display().to_string(),
+                };
+                (name, *format)
+            })
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ("a.env".to_string(), ExportFormat::Shell),
+                ("b.json".to_string(), ExportFormat::EnvJson),
+                ("-".to_string(), ExportFormat::Shell),
+            ]
+        );
+        // Only a known format name counts as a prefix.
+        let odd = ["c:\\out.env".to_string()];
+        let targets = export_targets(None, &odd, false, ExportFormat::EnvJson).unwrap();
+        assert!(
+            matches!(&targets[..], [(ExportTarget::File(p), ExportFormat::EnvJson)]
+            if p.as_os_str() == "c:\\out.env")
+        );
+        assert!(export_targets(None, &["shell:".to_string()], false, ExportFormat::Shell).is_err());
+    }
+
+    #[test]
+    fn trailing_newline_is_optional() {
+        let pairs = store::parse("B=2\nA=1");
+        let with = store::serialize_shell(&pairs, None).into_bytes();
+        assert_eq!(with, b"export A='1'\nexport B='2'\n");
+        let mut without = with.clone();
+        strip_trailing_newline(&mut without);
+        assert_eq!(without, b"export A='1'\nexport B='2'");
+        let mut json = env_json(&pairs, None, |_| false, false).into_bytes();
+        strip_trailing_newline(&mut json);
+        assert!(json.ends_with(b"}"));
+        let mut empty = Vec::new();
+        strip_trailing_newline(&mut empty);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn env_json_infers_types_only_when_asked() {
+        let pairs = store::parse(
+            "PORT=8080\nDEBUG=true\nZIP=007\nBIG=123456789012345678901234\n\
+             RATIO=0.5\nNAME=api\nLIST=[1,2]\nPAD=\" 1\"\nNIL=null\nQ=\"\"x\"\"\nBIN!=AAE=",
+        );
+        let typed: serde_json::Value =
+            serde_json::from_str(&env_json(&pairs, None, |k| k == "BIN", true)).unwrap();
+        assert_eq!(
+            typed,
+            serde_json::json!({
+                "PORT": 8080, "DEBUG": true, "ZIP": "007",
+                "BIG": "123456789012345678901234", "RATIO": 0.5, "NAME": "api",
+                "LIST": [1, 2], "PAD": " 1", "NIL": null, "Q": "\"x\"", "BIN": "AAE=",
+            })
+        );
+        let plain: serde_json::Value =
+            serde_json::from_str(&env_json(&pairs, None, |k| k == "BIN", false)).unwrap();
+        assert_eq!(plain["PORT"], "8080");
+        assert_eq!(infer_json("1e999"), None);
+    }
+
+    #[test]
+    fn env_json_keeps_the_pretty_layout_in_either_order() {
+        let parsed = store::parse_checked("Z=[1,2]\nA=x\n", true);
+        let expect =
+            |object: serde_json::Value| serde_json::to_string_pretty(&object).unwrap() + "\n";
+        assert_eq!(
+            env_json(&parsed.pairs, None, |_| false, true),
+            expect(serde_json::json!({"A": "x", "Z": [1, 2]}))
+        );
+        let ordered = env_json(&parsed.pairs, Some(&parsed.order), |_| false, true);
+        assert_eq!(
+            ordered,
+            "{\n  \"Z\": [\n    1,\n    2\n  ],\n  \"A\": \"x\"\n}\n"
+        );
+        assert_eq!(env_json(&HashMap::new(), None, |_| false, false), "{}\n");
+    }
+
+    #[test]
+    fn shell_export_keeps_descriptions_when_asked() {
+        let notes = "# desc: api token\nTOKEN=a b\nPLAIN=1";
+        let parsed = store::parse_checked(notes, true);
+        let opts = ExportOptions {
+            infer_types: false,
+            trailing_newline: true,
+            decode: false,
+            expand: None,
+            preserve_order: false,
+            fail_empty: false,
+            keep_descriptions: true,
+        };
+        let render = |descriptions: &HashMap<String, String>| {
+            let out = render_export(
+                &parsed.pairs,
+                None,
+                descriptions,
+                |_| false,
+                ExportFormat::Shell,
+                &opts,
+            );
+            String::from_utf8(out.unwrap()).unwrap()
+        };
+        assert_eq!(
+            render(&store::descriptions(notes)),
+            "export PLAIN='1'\n# desc: api token\nexport TOKEN='a b'\n"
+        );
+        assert_eq!(
+            render(&HashMap::new()),
+            "export PLAIN='1'\nexport TOKEN='a b'\n"
+        );
+    }
+
+    #[test]
+    fn log_lists_revisions_newest_first() {
+        let item: rbw::RbwItem = serde_json::from_str(
+            r#"{"revisionDate":"2024-05-01T10:00:00Z","history":[
+                {"last_used_date":"2024-01-02T00:00:00Z"},
+                {"last_used_date":"2024-03-04T00:00:00Z"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            format_log(&item),
+            "2024-05-01T10:00:00Z  last modified\n\
+             2024-03-04T00:00:00Z  password replaced\n\
+             2024-01-02T00:00:00Z  password replaced\n"
+        );
+        let bare: rbw::RbwItem = serde_json::from_str("{}").unwrap();
+        assert!(format_log(&bare).starts_with("no revision dates"));
+    }
+
+    #[test]
+    fn vault_item_url_joins_base() {
+        assert_eq!(
+            vault_item_url("https://bw.example.com/", "abc"),
+            "https://bw.example.com/#/vault?itemId=abc"
+        );
+        assert_eq!(
+            vault_item_url(DEFAULT_VAULT_URL, "x"),
+            "https://vault.bitwarden.com/#/vault?itemId=x"
+        );
+    }
+
+    #[test]
+    fn sort_items_by_type_then_name() {
+        let item = |name: &str, item_type: &str| rbw::ListItem {
+            id: None,
+            name: name.into(),
+            folder: Some("f".into()),
+            item_type: item_type.into(),
+            deleted_date: None,
+        };
+        let mut items = vec![item("b", "Note"), item("c", "Login"), item("a", "Note")];
+        sort_items(&mut items, SortKey::Type);
+        let names: Vec<&str> = items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["c", "a", "b"]);
+        sort_items(&mut items, SortKey::Name);
+        let names: Vec<&str> = items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn glob_literal_and_wildcards() {
+        assert!(glob_match("prod/db", "prod/db"));
+        assert!(!glob_match("prod/db", "prod/dbx"));
+        assert!(glob_match("svc-*", "svc-api"));
+        assert!(glob_match("svc-*", "svc-"));
+        assert!(!glob_match("svc-*", "api-svc"));
+        assert!(glob_match("*/db", "staging/db"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn glob_backtracks_across_stars() {
+        assert!(glob_match("*a*b", "xxaxxb"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*a*b", "xxaxx"));
+    }
+}
@@ -0,0 +1,56 @@
+//! How masked values look in `env`, `compare` and `watch-ns` output
+//! (`--mask-char`, `--mask-show`).
+//!
+//! A masked value is three mask characters, whatever its length, so the
+//! mask says nothing about how long the secret is.  With `--mask-show N`
+//! the first and last `N` characters are shown around it, but never more
+//! than a quarter of the value on each side: at least half of every value
+//! stays hidden, and values shorter than 4 characters are fully masked.
+//! `--redact` output filtering always writes `***` and is not affected.
+
+use anyhow::{Result, bail};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+static CHAR: AtomicU32 = AtomicU32::new('*' as u32);
+static SHOW: AtomicUsize = AtomicUsize::new(0);
+
+/// Use `ch` for masks and reveal up to `show` characters at each end.
+pub fn set(ch: char, show: usize) -> Result<()> {
+    if ch.is_whitespace() || ch.is_control() {
+        bail!("--mask-char must be a visible character, not {ch:?}");
+    }
+    CHAR.store(ch as u32, Ordering::Relaxed);
+    SHOW.store(show, Ordering::Relaxed);
+    Ok(())
+}
+
+/// `value` as masked output shows it.
+pub fn mask(value: &str) -> String {
+    let ch = char::from_u32(CHAR.load(Ordering::Relaxed)).unwrap_or('*');
+    masked(value, ch, SHOW.load(Ordering::Relaxed))
+}
+
+fn masked(value: &str, ch: char, show: usize) -> String {
+    let len = value.chars().count();
+    let reveal = show.min(len / 4);
+    let head: String = value.chars().take(reveal).collect();
+    let tail: String = value.chars().skip(len - reveal).collect();
+    format!("{head}{}{tail}", String::from(ch).repeat(3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reveals_at_most_a_quarter_at_each_end() {
+        assert_eq!(masked("hunter2hunter2", '*', 0), "***");
+        assert_eq!(masked("hunter2hunter2", '#', 2), "hu###r2");
+        assert_eq!(masked("abcdefgh", '*', 9), "ab***gh");
+        assert_eq!(masked("abcdefg", '*', 9), "a***g");
+        assert_eq!(masked("abc", '*', 9), "***");
+        assert_eq!(masked("", '*', 9), "***");
+        assert_eq!(masked("ééééé", '•', 1), "é•••é");
+        assert!(set('\n', 0).is_err());
+    }
+}
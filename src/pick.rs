@@ -0,0 +1,127 @@
+//! `--select`: choose a namespace interactively.
+//!
+//! A plain numbered menu on stderr, read a line at a time from stdin: enter
+//! a number to pick that entry, or some letters to narrow the list to names
+//! containing them in order (`stg` matches `staging/api`).  A filter that
+//! leaves a single name picks it.  Only offered when stdin and stdout are
+//! terminals, so scripts keep getting a plain "missing namespace" error.
+
+use crate::rbw;
+use anyhow::{Context, Result, bail};
+use std::io::{BufRead as _, IsTerminal as _, Write as _};
+
+/// Menu entries shown at once; a longer list asks for a filter first.
+const MAX_SHOWN: usize = 30;
+
+/// Let the user pick one of the namespaces in `folder`.
+pub fn namespace(folder: &str) -> Result<String> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        bail!("--select needs a terminal; name the namespace instead");
+    }
+    let mut names: Vec<String> = rbw::list_items(folder)?
+        .into_iter()
+        .map(|i| i.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        bail!("folder `{folder}` has no namespaces to select from");
+    }
+
+    let stdin = std::io::stdin();
+    let mut shown: Vec<&str> = names.iter().map(String::as_str).collect();
+    loop {
+        let mut err = std::io::stderr().lock();
+        if shown.len() > MAX_SHOWN {
+            write!(err, "{} namespaces; type part of a name: ", shown.len())?;
+        } else {
+            for (i, name) in shown.iter().enumerate() {
+                writeln!(err, "{:>3}) {name}", i + 1)?;
+            }
+            write!(err, "namespace (number or filter, empty to cancel): ")?;
+        }
+        err.flush()?;
+        drop(err);
+
+        let mut line = String::new();
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .context("failed to read selection")?;
+        match choose(&shown, line.trim()) {
+            Choice::Picked(name) => return Ok(name.to_string()),
+            Choice::Cancel => bail!("no namespace selected"),
+            Choice::Narrowed(list) => shown = list,
+            Choice::NoMatch => eprintln!("no namespace matches `{}`", line.trim()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Choice<'a> {
+    Picked(&'a str),
+    Narrowed(Vec<&'a str>),
+    NoMatch,
+    Cancel,
+}
+
+/// Interpret one line of input against the names currently shown: numbers
+/// refer to the menu as printed, anything else narrows it.
+fn choose<'a>(shown: &[&'a str], input: &str) -> Choice<'a> {
+    if input.is_empty() {
+        return Choice::Cancel;
+    }
+    if let Ok(n) = input.parse::<usize>()
+        && (1..=shown.len()).contains(&n)
+        && shown.len() <= MAX_SHOWN
+    {
+        return Choice::Picked(shown[n - 1]);
+    }
+    let matches: Vec<&str> = shown
+        .iter()
+        .copied()
+        .filter(|name| fuzzy_match(input, name))
+        .collect();
+    match matches.as_slice() {
+        [] => Choice::NoMatch,
+        [only] => Choice::Picked(only),
+        _ => Choice::Narrowed(matches),
+    }
+}
+
+/// Whether the characters of `query` appear in `name` in order
+/// (case-insensitively).
+fn fuzzy_match(query: &str, name: &str) -> bool {
+    let mut chars = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|q| chars.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_matches_in_order() {
+        assert!(fuzzy_match("stg", "staging/api"));
+        assert!(fuzzy_match("API", "staging/api"));
+        assert!(!fuzzy_match("gts", "staging"));
+        assert!(fuzzy_match("", "anything"));
+    }
+
+    #[test]
+    fn choose_by_number_or_filter() {
+        let shown = ["dev", "prod", "prod-eu"];
+        assert_eq!(choose(&shown, "2"), Choice::Picked("prod"));
+        assert_eq!(choose(&shown, "dv"), Choice::Picked("dev"));
+        assert_eq!(
+            choose(&shown, "prd"),
+            Choice::Narrowed(vec!["prod", "prod-eu"])
+        );
+        assert_eq!(choose(&shown, "9"), Choice::NoMatch);
+        assert_eq!(choose(&shown, "xyz"), Choice::NoMatch);
+        assert_eq!(choose(&shown, ""), Choice::Cancel);
+    }
+}
@@ -0,0 +1,82 @@
+//! Random secret generation for `rotate`, from the operating system's CSPRNG.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Charset {
+    /// A-Z, a-z, 0-9
+    Alnum,
+    /// 0-9, a-f
+    Hex,
+    /// A-Z, a-z, 0-9, `+`, `/`
+    Base64,
+}
+
+impl Charset {
+    fn chars(self) -> &'static [u8] {
+        match self {
+            Charset::Alnum => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+            Charset::Hex => b"0123456789abcdef",
+            Charset::Base64 => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+        }
+    }
+}
+
+/// A `length`-character random string drawn uniformly from `charset`.
+pub fn generate(length: usize, charset: Charset) -> Result<String> {
+    let chars = charset.chars();
+    // Bytes at or above the largest multiple of the charset size are redrawn,
+    // so every character is equally likely.
+    let limit = 256 - 256 % chars.len();
+    let mut out = String::with_capacity(length);
+    let mut buf = [0u8; 64];
+    while out.len() < length {
+        fill(&mut buf)?;
+        for &b in &buf {
+            if out.len() == length {
+                break;
+            }
+            if usize::from(b) < limit {
+                out.push(chars[usize::from(b) % chars.len()] as char);
+            }
+        }
+    }
+    zeroize::Zeroize::zeroize(&mut buf);
+    Ok(out)
+}
+
+#[cfg(unix)]
+fn fill(buf: &mut [u8]) -> Result<()> {
+    use std::io::Read as _;
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(buf))
+        .context("failed to read random bytes from /dev/urandom")
+}
+
+#[cfg(not(unix))]
+fn fill(_buf: &mut [u8]) -> Result<()> {
+    anyhow::bail!("random value generation is only supported on Unix")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_requested_length_and_charset() {
+        for (charset, len) in [
+            (Charset::Alnum, 32),
+            (Charset::Hex, 7),
+            (Charset::Base64, 100),
+        ] {
+            let value = generate(len, charset).unwrap();
+            assert_eq!(value.len(), len);
+            assert!(value.bytes().all(|b| charset.chars().contains(&b)));
+        }
+        assert_ne!(
+            generate(32, Charset::Alnum).unwrap(),
+            generate(32, Charset::Alnum).unwrap()
+        );
+    }
+}
@@ -8,20 +8,82 @@
 //! terminal, `rbw::edit::edit()` reads the entire stdin rather than launching
 //! an editor.  This avoids any temp-file / EDITOR tricks.
 
+use crate::{cache, spinner, timings};
 use anyhow::{Context, Result, bail};
-use serde::Deserialize;
-use spinners::{Spinner, Spinners, Stream};
+use serde::{Deserialize, Serialize};
 use std::io::Write as _;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use zeroize::Zeroize as _;
+
+/// rbw profile chosen with `--profile`/`BWENV_PROFILE`, if any; swapped for
+/// a while by [`with_profile`].
+static PROFILE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Custom field holding the KEY=VALUE content instead of the notes (`--field`).
+static SOURCE_FIELD: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// When set, entries in the Bitwarden trash are listed and fetched like any
+/// other.
+static INCLUDE_TRASHED: AtomicBool = AtomicBool::new(false);
+
+/// `--create-folder`: creating a namespace may also create its folder.
+static CREATE_FOLDER: AtomicBool = AtomicBool::new(false);
+
+/// When set, a locked vault is an error instead of triggering `rbw unlock`.
+static NO_UNLOCK: AtomicBool = AtomicBool::new(false);
+
+/// `--offline`: [`sync`] does nothing.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// `--require-sync`: an unreachable server is an error in [`sync`] rather
+/// than a warning.
+static REQUIRE_SYNC: AtomicBool = AtomicBool::new(false);
+
+/// Attempts [`sync`] makes when the server can't be reached, and the pause
+/// after each failed one.
+const SYNC_ATTEMPTS: u32 = 2;
+const SYNC_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// `--timeout`: seconds any rbw call other than `rbw unlock` may take
+/// before it is killed; 0 for no limit.
+static COMMAND_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// `--timeout-unlock`: seconds `rbw unlock` may wait for the master
+/// password; 0 for no limit.  Much longer than a data call, since a person
+/// is typing.
+pub const DEFAULT_UNLOCK_TIMEOUT: u64 = 120;
+static UNLOCK_TIMEOUT: AtomicU64 = AtomicU64::new(DEFAULT_UNLOCK_TIMEOUT);
+
+/// How often a call with a time limit checks whether rbw has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Upper bound on simultaneous rbw subprocesses in batch operations.  Too
+/// many at once can overwhelm rbw-agent.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 4;
+static MAX_CONCURRENCY: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_CONCURRENCY);
 
 // ── JSON shapes returned by `rbw list --raw` and `rbw get --raw` ─────────────
 
-#[derive(Debug, Deserialize)]
+/// Also the shape of `bwenv list --json` (which has no secret data).
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ListItem {
+    /// Bitwarden item ID (only reported by `rbw list --raw`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub name: String,
     pub folder: Option<String>,
     #[serde(rename = "type")]
     pub item_type: String,
+    /// Set when the entry is in the Bitwarden trash (if rbw reports it).
+    #[serde(
+        default,
+        rename = "deletedDate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deleted_date: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,110 +92,716 @@ pub struct RbwItem {
     #[serde(rename = "type")]
     pub item_type: Option<String>,
     pub notes: Option<String>,
+    /// Type-specific payload; only the Login password matters to us.
+    pub data: Option<RbwData>,
+    /// Last modification time, if the installed rbw reports it.
+    #[serde(rename = "revisionDate")]
+    pub revision_date: Option<String>,
+    /// Password history (timestamps only; old passwords are not kept).
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+    /// Set when the entry is in the Bitwarden trash (if rbw reports it).
+    #[serde(default, rename = "deletedDate")]
+    pub deleted_date: Option<String>,
+    /// Custom fields, for namespaces kept in a field (`--field`).
+    #[serde(default)]
+    pub fields: Vec<RbwField>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RbwField {
+    pub name: Option<String>,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryEntry {
+    pub last_used_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RbwData {
+    pub password: Option<String>,
+}
+
+impl RbwItem {
+    pub fn is_secure_note(&self) -> bool {
+        self.item_type.as_deref() == Some("Note")
+    }
+
+    /// Fail for entry types whose editor buffer bwenv cannot frame.
+    ///
+    /// Only Login and SecureNote entries have the password-then-notes buffer
+    /// [`editor_buffer`] writes; piping it into a Card or Identity would
+    /// mangle the entry, so writes to those are refused.  An item without a
+    /// type (older rbw) is treated as a Login.
+    pub fn ensure_env_storage(&self) -> Result<()> {
+        match self.item_type.as_deref() {
+            None | Some("Login" | "Note") => Ok(()),
+            Some(other) => bail!(
+                "unsupported entry type for env storage: `{other}` \
+                 (only Login and Secure Note entries can hold a namespace)"
+            ),
+        }
+    }
+
+    pub fn password(&self) -> Option<&str> {
+        self.data.as_ref()?.password.as_deref()
+    }
+
+    /// The namespace content: the notes, or the `--field` custom field.
+    pub fn env_content(&self) -> Option<&str> {
+        self.content_of(source_field())
+    }
+
+    fn content_of(&self, field: Option<&str>) -> Option<&str> {
+        let content = match field {
+            None => self.notes.as_deref(),
+            Some(field) => self
+                .fields
+                .iter()
+                .find(|f| f.name.as_deref() == Some(field))?
+                .value
+                .as_deref(),
+        };
+        content.map(strip_framing)
+    }
 }
 
 // ── Public API ────────────────────────────────────────────────────────────────
 
-/// List namespace names: all items in `folder`, regardless of type.
-pub fn list_namespaces(folder: &str) -> Result<Vec<String>> {
-    ensure_unlocked()?;
+/// List namespaces: the raw entries in `folder`, regardless of type.
+/// Trashed entries are left out unless `--include-trashed` is in effect.
+pub fn list_items(folder: &str) -> Result<Vec<ListItem>> {
+    list_where(|i| in_folder(i, Some(folder)))
+}
 
-    let mut sp = Spinner::with_stream(
-        Spinners::Dots,
-        "Fetching namespaces…".into(),
-        Stream::Stderr,
-    );
-    let mut cmd = Command::new("rbw");
-    cmd.args(["list", "--raw"]);
-    set_rbw_tty(&mut cmd);
-    let output = cmd.output().context("failed to run `rbw list`")?;
-    sp.stop_with_newline();
+/// Whether `item` lives directly in `folder`, where `None` means "in no
+/// folder" — distinct from a folder that happens to have an empty name.
+/// Nested Bitwarden folders are plain names with `/` separators
+/// (`parent/child`), and rbw reports the full name, so this is an exact
+/// match on normalized paths: an item in `parent/child` is not in `parent`.
+fn in_folder(item: &ListItem, folder: Option<&str>) -> bool {
+    match (item.folder.as_deref(), folder) {
+        (None, None) => true,
+        (Some(actual), Some(wanted)) => normalize_folder(actual) == normalize_folder(wanted),
+        _ => false,
+    }
+}
+
+/// Canonical form of a folder path: empty components from leading, trailing
+/// or doubled `/` are dropped (`/a//b/` → `a/b`).  Components are otherwise
+/// kept verbatim, since Bitwarden folder names may contain spaces.
+pub fn normalize_folder(folder: &str) -> String {
+    folder
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// List every entry in the vault with a single `rbw list` call.
+pub fn list_all() -> Result<Vec<ListItem>> {
+    list_where(|_| true)
+}
+
+/// Run `rbw list` and keep the entries `keep` accepts.  The output is parsed
+/// as it streams from rbw, one entry at a time, so only the kept entries are
+/// ever held in memory — even for vaults with thousands of items.
+fn list_where(keep: impl Fn(&ListItem) -> bool) -> Result<Vec<ListItem>> {
+    ensure_unlocked(Some("list namespaces"))?;
+
+    let include_trashed = INCLUDE_TRASHED.load(Ordering::Relaxed);
+    let keep = |i: &ListItem| (include_trashed || i.deleted_date.is_none()) && keep(i);
+    let raw = capabilities()?.list_raw;
+    let sp = spinner::start("Fetching namespaces…");
+    let mut cmd = rbw_command();
+    if raw {
+        cmd.args(["list", "--raw"]);
+    } else {
+        cmd.args(["list", "--fields", "name,folder"]);
+    }
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let (parsed, output) = timings::timed(&mut cmd, |cmd| -> Result<_> {
+        let mut child = cmd.spawn().context("failed to run `rbw list`")?;
+        let stdout = child.stdout.take().context("failed to open rbw stdout")?;
+        let mut stderr = child.stderr.take().context("failed to open rbw stderr")?;
+        // Drain stderr alongside so rbw never blocks on a full pipe.
+        let stderr_reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = std::io::Read::read_to_end(&mut stderr, &mut buf);
+            buf
+        });
+
+        // Waiting happens alongside too, so a hung rbw is killed (ending
+        // stdout) on `--timeout` even while parsing blocks on a read.
+        let timeout = limit(&COMMAND_TIMEOUT);
+        let waiter = std::thread::spawn(move || wait_within(child, timeout, "--timeout"));
+
+        // `stdout` is dropped once parsing stops, so rbw can't block writing
+        // to a reader that has gone away.
+        let parsed = if raw {
+            read_list_raw(std::io::BufReader::new(stdout), &keep)
+        } else {
+            read_list_fields(std::io::BufReader::new(stdout), &keep)
+        };
+        let status = waiter
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            .context("failed to wait for `rbw list`")?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+        Ok((
+            parsed,
+            std::process::Output {
+                status,
+                stdout: Vec::new(),
+                stderr,
+            },
+        ))
+    })?;
+    sp.stop();
 
     check_status("rbw list", &output)?;
+    parsed
+}
+
+/// Stream-parse the JSON array printed by `rbw list --raw`.
+fn read_list_raw(
+    reader: impl std::io::Read,
+    keep: &dyn Fn(&ListItem) -> bool,
+) -> Result<Vec<ListItem>> {
+    struct Filtered<'a>(&'a dyn Fn(&ListItem) -> bool);
 
-    let items: Vec<ListItem> = serde_json::from_slice(&output.stdout)
+    impl<'de> serde::de::Visitor<'de> for Filtered<'_> {
+        type Value = Vec<ListItem>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a list of vault entries")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> std::result::Result<Self::Value, A::Error> {
+            let mut items = Vec::new();
+            while let Some(item) = seq.next_element::<ListItem>()? {
+                if (self.0)(&item) {
+                    items.push(item);
+                }
+            }
+            Ok(items)
+        }
+    }
+
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let items = serde::Deserializer::deserialize_seq(&mut de, Filtered(keep))
+        .and_then(|items| de.end().map(|()| items))
         .context("failed to parse `rbw list --raw` output")?;
+    Ok(items)
+}
 
-    let names = items
-        .into_iter()
-        .filter(|i| i.folder.as_deref().unwrap_or("") == folder)
-        .map(|i| i.name)
-        .collect();
+/// Stream-parse `rbw list --fields name,folder` output line by line.
+fn read_list_fields(
+    reader: impl std::io::BufRead,
+    keep: &dyn Fn(&ListItem) -> bool,
+) -> Result<Vec<ListItem>> {
+    let mut items = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("failed to read `rbw list` output")?;
+        if let Some(item) = parse_list_fields_line(&line)
+            && keep(&item)
+        {
+            items.push(item);
+        }
+    }
+    Ok(items)
+}
 
-    Ok(names)
+/// Parse one tab-separated `rbw list --fields name,folder` line (rbw without
+/// `--raw`).  The entry type is not available that way.
+fn parse_list_fields_line(line: &str) -> Option<ListItem> {
+    if line.is_empty() {
+        return None;
+    }
+    let (name, folder) = line.split_once('\t').unwrap_or((line, ""));
+    Some(ListItem {
+        id: None,
+        name: name.to_string(),
+        // Tab-separated output can't tell "no folder" from an empty-named
+        // one; treat an empty column as no folder.
+        folder: (!folder.is_empty()).then(|| folder.to_string()),
+        item_type: "unknown".to_string(),
+        deleted_date: None,
+    })
 }
 
 /// Fetch a single item's notes.
 /// Returns `None` if the item does not exist in the given folder.
 pub fn get_item(name: &str, folder: &str) -> Result<Option<RbwItem>> {
-    ensure_unlocked()?;
+    ensure_unlocked(Some(&format!("read namespace `{name}`")))?;
 
-    let mut sp = Spinner::with_stream(Spinners::Dots, format!("Fetching {name}…"), Stream::Stderr);
+    let sp = spinner::start(format!("Fetching {name}…"));
     let result = get_item_raw(name, folder);
-    sp.stop_with_newline();
+    sp.stop();
+    result
+}
+
+/// Fetch just an item's notes, for read-only callers.
+/// Returns `None` if the item does not exist in the given folder.
+///
+/// Served from the local cache when `BWENV_CACHE_TTL` is set and the entry
+/// is fresh; otherwise asks rbw with `rbw get --raw`, or with the lighter
+/// `--field notes` read under `--include-trashed` (see [`notes_via_field`]).
+pub fn get_notes(name: &str, folder: &str) -> Result<Option<String>> {
+    ensure_unlocked(Some(&format!("read namespace `{name}`")))?;
+    // The cache holds notes only; custom-field reads always go to rbw.
+    let cacheable = source_field().is_none();
+    if cacheable && let Some(notes) = cache::get(name, folder) {
+        return Ok(Some(notes));
+    }
+
+    let sp = spinner::start(format!("Fetching {name}…"));
+    let result = get_notes_raw(name, folder);
+    sp.stop();
+    if cacheable && let Ok(Some(notes)) = &result {
+        cache::put(name, folder, notes);
+    }
     result
 }
 
 /// Core `rbw get` subprocess call with no spinner and no unlock check.
 fn get_item_raw(name: &str, folder: &str) -> Result<Option<RbwItem>> {
-    let mut cmd = Command::new("rbw");
+    let mut cmd = rbw_command();
     cmd.args(["get", "--raw", "--folder", folder, name]);
-    set_rbw_tty(&mut cmd);
-    let output = cmd.output().context("failed to run `rbw get`")?;
+    let output = timings::timed(&mut cmd, output).context("failed to run `rbw get`")?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        if stderr.contains("no entry found")
-            || stderr.contains("no items found")
-            || stderr.contains("Entry not found")
-        {
+        if is_not_found(&stderr) {
             return Ok(None);
         }
+        if let Some(candidates) = ambiguous_candidates(&stderr) {
+            return Err(ambiguity_error(name, folder, &candidates));
+        }
         bail!("`rbw get` failed ({}): {}", output.status, stderr.trim());
     }
 
     let item: RbwItem =
         serde_json::from_slice(&output.stdout).context("failed to parse `rbw get --raw` output")?;
 
+    // A name that only matches a trashed entry is as good as missing.
+    if item.deleted_date.is_some() && !INCLUDE_TRASHED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
     Ok(Some(item))
 }
 
-/// Fetch multiple items in parallel (one thread per namespace).
+/// Whether the installed rbw accepts `rbw get --field notes`.  Unknown until
+/// the first attempt (only made under `--include-trashed`); an rbw that
+/// rejects the flag is remembered for the rest of the process so the
+/// fallback costs one extra call at most.
+static FIELD_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`get_notes_raw`] may use `rbw get --field`.
+///
+/// The `--field` read saves parsing the JSON document, but its plain-text
+/// output doesn't say whether the entry is in the trash, and telling that
+/// some other way (an `rbw list` per read) would cost more than the JSON.
+/// So it is only used when trashed entries are wanted anyway
+/// (`--include-trashed`); by default every read goes through `--raw`, which
+/// reports `deletedDate`.
+fn notes_via_field(include_trashed: bool, field_unsupported: bool) -> bool {
+    include_trashed && !field_unsupported
+}
+
+/// Notes-only `rbw get`.  By default this is `--raw`, so trashed entries
+/// can be skipped; under `--include-trashed` it is `--field notes`, which
+/// prints the notes as plain text and skips building and parsing the JSON
+/// document, with `--raw` as the fallback for an rbw that does not know the
+/// flag (see [`notes_via_field`]).  With `--field`, the custom field is
+/// fetched the same way.
+fn get_notes_raw(name: &str, folder: &str) -> Result<Option<String>> {
+    let from_raw =
+        |item: Option<RbwItem>| item.map(|i| i.env_content().unwrap_or_default().to_string());
+    if !notes_via_field(
+        INCLUDE_TRASHED.load(Ordering::Relaxed),
+        FIELD_UNSUPPORTED.load(Ordering::Relaxed),
+    ) {
+        return Ok(from_raw(get_item_raw(name, folder)?));
+    }
+
+    let field = source_field().unwrap_or("notes");
+    let mut cmd = rbw_command();
+    cmd.args(["get", "--folder", folder, "--field", field, name]);
+    let output = timings::timed(&mut cmd, output).context("failed to run `rbw get`")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if is_not_found(&stderr) {
+            return Ok(None);
+        }
+        if is_unknown_flag(&stderr) {
+            FIELD_UNSUPPORTED.store(true, Ordering::Relaxed);
+            return Ok(from_raw(get_item_raw(name, folder)?));
+        }
+        if let Some(candidates) = ambiguous_candidates(&stderr) {
+            return Err(ambiguity_error(name, folder, &candidates));
+        }
+        bail!("`rbw get` failed ({}): {}", output.status, stderr.trim());
+    }
+
+    let mut notes =
+        String::from_utf8(output.stdout).context("`rbw get --field` output is not UTF-8")?;
+    // rbw terminates the printed value with a newline.
+    if notes.ends_with('\n') {
+        notes.pop();
+    }
+    let framing = notes.len() - strip_framing(&notes).len();
+    notes.drain(..framing);
+    Ok(Some(notes))
+}
+
+/// Fetch multiple items' notes in parallel (at most `max_concurrency()` rbw
+/// processes at a time), serving fresh entries from the local cache.
 /// The vault must already be unlocked before calling this.
 /// A single spinner covers the whole batch; individual fetches are silent.
 /// Results are returned in the same order as `requests`.
-pub fn get_items(
+pub fn get_notes_many(
     requests: &[(String, String)], // (name, folder) pairs
-) -> Vec<Result<Option<RbwItem>>> {
-    use std::thread;
-
-    let handles: Vec<_> = requests
+) -> Vec<Result<Option<String>>> {
+    let cacheable = source_field().is_none();
+    let mut results: Vec<Option<Result<Option<String>>>> = requests
         .iter()
         .map(|(name, folder)| {
-            let name = name.clone();
-            let folder = folder.clone();
-            thread::spawn(move || get_item_raw(&name, &folder))
+            cacheable
+                .then(|| cache::get(name, folder))
+                .flatten()
+                .map(|notes| Ok(Some(notes)))
         })
         .collect();
+    let misses: Vec<&(String, String)> = requests
+        .iter()
+        .zip(&results)
+        .filter(|(_, hit)| hit.is_none())
+        .map(|(req, _)| req)
+        .collect();
 
-    let names: Vec<&str> = requests.iter().map(|(n, _)| n.as_str()).collect();
-    let label = format!("Fetching {}…", names.join(", "));
-    let mut sp = Spinner::with_stream(Spinners::Dots, label, Stream::Stderr);
-    let results = handles
-        .into_iter()
-        .map(|h| {
-            h.join()
-                .unwrap_or_else(|_| bail!("rbw fetch thread panicked"))
+    if !misses.is_empty() {
+        let names: Vec<&str> = misses.iter().map(|(n, _)| n.as_str()).collect();
+        let label = format!("Fetching {}…", names.join(", "));
+        let sp = spinner::start(label);
+        let mut fetched = parallel_map(&misses, |(name, folder)| {
+            let result = get_notes_raw(name, folder);
+            if cacheable && let Ok(Some(notes)) = &result {
+                cache::put(name, folder, notes);
+            }
+            result
         })
-        .collect();
-    sp.stop_with_newline();
+        .into_iter();
+        sp.stop();
+        for slot in results.iter_mut().filter(|slot| slot.is_none()) {
+            *slot = fetched.next();
+        }
+    }
+
     results
+        .into_iter()
+        .map(|slot| slot.unwrap_or_else(|| Err(anyhow::anyhow!("missing fetch result"))))
+        .collect()
 }
 
 /// Ensure the vault is unlocked. Public so callers can unlock once before
 /// issuing parallel fetches.
 pub fn unlock() -> Result<()> {
-    ensure_unlocked()
+    ensure_unlocked(None)
+}
+
+/// [`unlock`], saying on stderr what bwenv needs the vault for (`reason`,
+/// e.g. "read namespace `prod`") if it has to prompt.
+pub fn unlock_for(reason: &str) -> Result<()> {
+    ensure_unlocked(Some(reason))
+}
+
+/// Set the time limits, in seconds (0 for none), for data calls and for
+/// `rbw unlock`.
+pub fn set_timeouts(command: u64, unlock: u64) {
+    COMMAND_TIMEOUT.store(command, Ordering::Relaxed);
+    UNLOCK_TIMEOUT.store(unlock, Ordering::Relaxed);
+}
+
+fn limit(secs: &AtomicU64) -> Option<Duration> {
+    match secs.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// `Command::output` for an rbw data call, killed after `--timeout`.
+fn output(cmd: &mut Command) -> std::io::Result<Output> {
+    let Some(timeout) = limit(&COMMAND_TIMEOUT) else {
+        return cmd.output();
+    };
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    collect_output(child, Some(timeout))
+}
+
+/// Read `child`'s piped stdout and stderr (each empty if not piped) and
+/// wait for it, up to `timeout`.
+fn collect_output(mut child: Child, timeout: Option<Duration>) -> std::io::Result<Output> {
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    // On a timeout the readers are left behind: something rbw started may
+    // still hold the pipes open.
+    let status = wait_within(child, timeout, "--timeout")?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain<R: std::io::Read + Send + 'static>(pipe: Option<R>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Wait for `child`, killing it if it is still running after `timeout`
+/// (set with `flag`, which the error names).
+fn wait_within(
+    mut child: Child,
+    timeout: Option<Duration>,
+    flag: &str,
+) -> std::io::Result<ExitStatus> {
+    let Some(timeout) = timeout else {
+        return child.wait();
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("stopped after {}s ({flag})", timeout.as_secs()),
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Set the batch concurrency limit (values below 1 are treated as 1).
+pub fn set_max_concurrency(n: usize) {
+    MAX_CONCURRENCY.store(n.max(1), Ordering::Relaxed);
+}
+
+/// Let [`create_item`] create a folder that doesn't exist yet
+/// (`--create-folder`).
+pub fn set_create_folder(create: bool) {
+    CREATE_FOLDER.store(create, Ordering::Relaxed);
+}
+
+/// Treat trashed entries as live (`--include-trashed`).
+pub fn set_include_trashed(include: bool) {
+    INCLUDE_TRASHED.store(include, Ordering::Relaxed);
+}
+
+/// Run every rbw command under the given rbw profile (`RBW_PROFILE`).
+pub fn set_profile(profile: &str) {
+    *PROFILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(profile.to_string());
+}
+
+/// Run `f` with every rbw call (and the cache) under `profile` instead, for
+/// commands that read from two accounts (`compare --left-profile`).  `None`
+/// keeps the profile in effect.  Calls inside `f` must not race with calls
+/// outside it.
+pub fn with_profile<T>(profile: Option<&str>, f: impl FnOnce() -> T) -> T {
+    let Some(profile) = profile else {
+        return f();
+    };
+    let previous = PROFILE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .replace(profile.to_string());
+    let result = f();
+    *PROFILE.lock().unwrap_or_else(|e| e.into_inner()) = previous;
+    result
+}
+
+/// The profile set with `--profile` (or [`with_profile`]), if any.
+fn selected_profile() -> Option<String> {
+    PROFILE.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The rbw profile in effect: `--profile`, or an inherited `RBW_PROFILE`.
+pub fn profile() -> Option<String> {
+    selected_profile()
+        .or_else(|| std::env::var("RBW_PROFILE").ok())
+        .filter(|p| !p.is_empty())
+}
+
+/// Read namespaces from the custom field `field` instead of the notes
+/// (`notes` itself keeps the default).
+pub fn set_source_field(field: &str) {
+    if field != "notes" {
+        SOURCE_FIELD.set(field.to_string()).ok();
+    }
+}
+
+/// The custom field set with [`set_source_field`], `None` for the notes.
+pub fn source_field() -> Option<&'static str> {
+    SOURCE_FIELD.get().map(String::as_str)
+}
+
+/// rbw can only write the password and notes, so writes fail when the
+/// namespaces live in a custom field.
+pub fn ensure_writable() -> Result<()> {
+    check_writable(source_field())
+}
+
+fn check_writable(field: Option<&str>) -> Result<()> {
+    if let Some(field) = field {
+        bail!(
+            "namespaces are read from the custom field `{field}` (--field), \
+             which rbw cannot write; edit it in Bitwarden instead"
+        );
+    }
+    Ok(())
+}
+
+/// `--offline` / `--require-sync` behaviour of [`sync`].
+pub fn set_sync_mode(offline: bool, require: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+    REQUIRE_SYNC.store(require, Ordering::Relaxed);
+}
+
+/// Make `ensure_unlocked()` fail rather than prompt when the vault is locked,
+/// for non-interactive runs after an explicit `bwenv unlock`.
+pub fn set_no_unlock(no_unlock: bool) {
+    NO_UNLOCK.store(no_unlock, Ordering::Relaxed);
+}
+
+/// The Bitwarden account rbw is configured for (the parts of rbw's
+/// `config.json` bwenv cares about).
+#[derive(Debug, Default, Deserialize)]
+pub struct Account {
+    pub email: Option<String>,
+    pub base_url: Option<String>,
+}
+
+/// Read rbw's account settings, preferring `rbw config show` and falling back
+/// to rbw's config file when that command fails.  Needs no unlock.
+pub fn account() -> Result<Account> {
+    let mut cmd = rbw_command();
+    cmd.args(["config", "show"]);
+    if let Ok(output) = timings::timed(&mut cmd, output)
+        && output.status.success()
+    {
+        return serde_json::from_slice(&output.stdout)
+            .context("failed to parse `rbw config show` output");
+    }
+
+    let path = config_path().context("cannot locate rbw's config file (HOME is not set)")?;
+    let data = std::fs::read(&path)
+        .with_context(|| format!("failed to read rbw config `{}`", path.display()))?;
+    serde_json::from_slice(&data)
+        .with_context(|| format!("failed to parse rbw config `{}`", path.display()))
+}
+
+/// Where rbw keeps its config on Linux: `$XDG_CONFIG_HOME/rbw/config.json`,
+/// or `rbw-<profile>/` under a profile.
+fn config_path() -> Option<std::path::PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => std::path::PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    let dir = match profile() {
+        Some(profile) => format!("rbw-{profile}"),
+        None => "rbw".to_string(),
+    };
+    Some(base.join(dir).join("config.json"))
+}
+
+/// Lock the vault (`rbw lock`) and drop the local cache with it.
+pub fn lock() -> Result<()> {
+    cache::clear();
+    let mut cmd = rbw_command();
+    cmd.args(["lock"]);
+    let output = timings::timed(&mut cmd, output).context("failed to run `rbw lock`")?;
+    check_status("rbw lock", &output)
+}
+
+/// Pull the latest vault contents from the server (`rbw sync`).
+///
+/// A network failure is retried, then — unless `--require-sync` — reported
+/// as a warning, leaving rbw's local copy of the vault to work from.  Any
+/// other failure is an error.  With `--offline` nothing is attempted.
+pub fn sync() -> Result<()> {
+    if OFFLINE.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    ensure_unlocked(Some("sync the vault"))?;
+    for attempt in 1..=SYNC_ATTEMPTS {
+        let sp = spinner::start("Syncing with the server…");
+        let mut cmd = rbw_command();
+        cmd.args(["sync"]);
+        let output = timings::timed(&mut cmd, output);
+        sp.stop();
+        let output = output.context("failed to run `rbw sync`")?;
+        if output.status.success() {
+            cache::clear();
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !is_offline(&stderr) {
+            bail!(
+                "`rbw sync` failed unexpectedly ({}): {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        if attempt < SYNC_ATTEMPTS {
+            std::thread::sleep(SYNC_RETRY_DELAY);
+            continue;
+        }
+        let reason = stderr.trim();
+        if REQUIRE_SYNC.load(Ordering::Relaxed) {
+            bail!("cannot reach the Bitwarden server, and --require-sync is set: {reason}");
+        }
+        eprintln!("WARNING: offline, using the local copy of the vault ({reason})");
+    }
+    Ok(())
+}
+
+/// Whether rbw's stderr is a network failure rather than, say, an
+/// authentication problem.
+fn is_offline(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    [
+        "error sending request",
+        "dns error",
+        "failed to lookup address",
+        "connection refused",
+        "network is unreachable",
+        "no route to host",
+        "timed out",
+        "connect error",
+    ]
+    .iter()
+    .any(|pattern| stderr.contains(pattern))
 }
 
 /// Create a new entry (Login type) with `notes_content` in the given folder.
@@ -142,64 +810,228 @@ pub fn unlock() -> Result<()> {
 /// rbw reads the editor content directly from stdin.  Format: first line =
 /// password (empty), rest = notes.
 pub fn create_item(name: &str, folder: &str, notes_content: &str) -> Result<()> {
-    // Prepend empty line so rbw's parse_editor treats it as an empty password.
-    let stdin_content = format!("\n{notes_content}\n");
+    ensure_writable()?;
+    ensure_folder(folder)?;
+    // Empty first line so rbw's parse_editor treats it as an empty password.
+    let stdin_content = editor_buffer(notes_content, None, false);
+    cache::invalidate(name, folder);
     pipe_to_rbw(&["add", "--folder", folder, name], &stdin_content)
 }
 
+/// Check that `folder` exists before a new entry goes into it.  `rbw add`
+/// creates a missing folder on its own, so that only happens with
+/// `--create-folder`; without it a typo'd `--folder` would quietly start a
+/// new folder.  rbw can't list folders, so a folder counts as existing when
+/// some entry is in it: a folder with no entries at all is reported as
+/// missing too.  The default folder is never checked (see
+/// [`folder_must_exist`]).
+fn ensure_folder(folder: &str) -> Result<()> {
+    if !folder_must_exist(folder) || list_all()?.iter().any(|item| in_folder(item, Some(folder))) {
+        return Ok(());
+    }
+    if !CREATE_FOLDER.load(Ordering::Relaxed) {
+        bail!(
+            "folder `{folder}` does not exist (no entries are in it); check the name, \
+             or pass --create-folder to create it along with this namespace"
+        );
+    }
+    eprintln!("creating folder `{folder}`");
+    Ok(())
+}
+
+/// Whether a new entry in `folder` needs the folder to exist already.  The
+/// default folder is exempt: it can't be a typo, and a first `bwenv set`
+/// in a fresh vault has to be able to start it.
+fn folder_must_exist(folder: &str) -> bool {
+    normalize_folder(folder) != crate::DEFAULT_FOLDER
+}
+
 /// Edit an existing entry, replacing its notes with `notes_content`.
 ///
-/// For Login entries (created by `create_item`): pipe `\n<content>` so the
-/// first line (password) stays empty.
-/// For SecureNote entries: rbw internally prepends `\n`
-/// before parsing, so pipe the content directly.
-pub fn edit_item(
-    name: &str,
-    folder: &str,
-    notes_content: &str,
-    is_secure_note: bool,
-) -> Result<()> {
-    let stdin_content = if is_secure_note {
-        format!("{notes_content}\n")
-    } else {
-        format!("\n{notes_content}\n")
-    };
-    pipe_to_rbw(&["edit", "--folder", folder, name], &stdin_content)
+/// rbw only takes the password and notes from the editor buffer; username,
+/// URIs, TOTP and custom fields are carried over from the stored entry.  The
+/// password is the buffer's first line, so for Login entries we must echo the
+/// current one back or `rbw edit` would clear it.
+pub fn edit_item(name: &str, folder: &str, notes_content: &str, existing: &RbwItem) -> Result<()> {
+    ensure_writable()?;
+    existing
+        .ensure_env_storage()
+        .with_context(|| format!("refusing to write `{name}`"))?;
+    let mut stdin_content = editor_buffer(
+        notes_content,
+        existing.password(),
+        existing.is_secure_note(),
+    );
+    cache::invalidate(name, folder);
+    let result = pipe_to_rbw(&["edit", "--folder", folder, name], &stdin_content);
+    stdin_content.zeroize();
+    result
 }
 
 /// Delete an entry by name and folder.
 pub fn delete_item(name: &str, folder: &str) -> Result<()> {
-    ensure_unlocked()?;
+    ensure_unlocked(Some(&format!("delete namespace `{name}`")))?;
+    cache::invalidate(name, folder);
 
-    let mut sp = Spinner::with_stream(
-        Spinners::Dots,
-        "Deleting from Bitwarden…".into(),
-        Stream::Stderr,
-    );
-    let mut cmd = Command::new("rbw");
+    let sp = spinner::start("Deleting from Bitwarden…");
+    let mut cmd = rbw_command();
     cmd.args(["remove", "--folder", folder, name]);
-    set_rbw_tty(&mut cmd);
-    let output = cmd.output().context("failed to run `rbw remove`")?;
-    sp.stop_with_newline();
+    let output = timings::timed(&mut cmd, output).context("failed to run `rbw remove`")?;
+    sp.stop();
+    if !output.status.success()
+        && let Some(candidates) = ambiguous_candidates(&String::from_utf8_lossy(&output.stderr))
+    {
+        return Err(ambiguity_error(name, folder, &candidates));
+    }
     check_status("rbw remove", &output)
 }
 
 // ── Helpers ───────────────────────────────────────────────────────────────────
 
+/// Map `f` over `items` on a pool of at most `max_concurrency()` threads,
+/// returning results in input order.  A panicking worker yields an error for
+/// the item it was handling.
+fn parallel_map<T, R, F>(items: &[T], f: F) -> Vec<Result<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R> + Sync,
+{
+    let workers = MAX_CONCURRENCY.load(Ordering::Relaxed).min(items.len());
+    let next = AtomicUsize::new(0);
+    let slots: Mutex<Vec<Option<Result<R>>>> =
+        Mutex::new(std::iter::repeat_with(|| None).take(items.len()).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else { break };
+                    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(item)))
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("rbw worker thread panicked")));
+                    slots.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    slots
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|slot| slot.unwrap_or_else(|| Err(anyhow::anyhow!("rbw worker did not run"))))
+        .collect()
+}
+
+// ── rbw version gating ───────────────────────────────────────────────────────
+
+/// A parsed `rbw --version`: (major, minor, patch).
+pub type Version = (u32, u32, u32);
+
+/// Oldest rbw bwenv supports: it needs `rbw get --raw` (entry type and Login
+/// password for edits) and `rbw get --folder`.
+const MIN_RBW_VERSION: Version = (1, 5, 0);
+/// First rbw with `rbw list --raw`; older ones get `rbw list --fields`.
+const LIST_RAW_SINCE: Version = (1, 13, 0);
+
+/// Optional rbw features the wrappers check before using newer flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub list_raw: bool,
+}
+
+impl Capabilities {
+    /// Capabilities of a given rbw version; an unknown version is assumed to
+    /// be current rather than locking the user out.
+    fn for_version(version: Option<Version>) -> Self {
+        let at_least = |since: Version| version.is_none_or(|v| v >= since);
+        Self {
+            list_raw: at_least(LIST_RAW_SINCE),
+        }
+    }
+}
+
+/// The installed rbw's version, from `rbw --version` (run once per process).
+/// `None` if it could not be determined.
+pub fn rbw_version() -> Option<Version> {
+    static VERSION: std::sync::OnceLock<Option<Version>> = std::sync::OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let mut cmd = rbw_command();
+        cmd.arg("--version");
+        let output = timings::timed(&mut cmd, output).ok()?;
+        parse_version(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+/// Capabilities of the installed rbw.  Errors if it is older than bwenv
+/// supports.
+pub fn capabilities() -> Result<Capabilities> {
+    let version = rbw_version();
+    if let Some(v) = version
+        && v < MIN_RBW_VERSION
+    {
+        let (a, b, c) = v;
+        let (x, y, z) = MIN_RBW_VERSION;
+        bail!("rbw {a}.{b}.{c} is too old; bwenv needs rbw {x}.{y}.{z} or newer");
+    }
+    Ok(Capabilities::for_version(version))
+}
+
+/// Parse `rbw 1.13.2` (or `1.13.2-dev`, …) into a version triple.
+fn parse_version(text: &str) -> Option<Version> {
+    let word = text
+        .split_whitespace()
+        .find(|w| w.starts_with(|c: char| c.is_ascii_digit()))?;
+    let mut parts = word.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+// ── Unlock ───────────────────────────────────────────────────────────────────
+
 /// Ensure the rbw vault is unlocked before running commands.  This triggers
 /// `rbw unlock` (and pinentry) up front, so that subsequent rbw commands don't
 /// need to prompt — avoiding TTY conflicts with piped stdin/stdout.
-fn ensure_unlocked() -> Result<()> {
-    let mut cmd = Command::new("rbw");
+///
+/// Also the first point every command passes through, so it is where an
+/// unsupported rbw version is rejected.
+///
+/// rbw offers no way to change the pinentry prompt, so when it is about to
+/// appear bwenv says on stderr why the vault is needed (`reason`), and under
+/// which rbw profile.
+fn ensure_unlocked(reason: Option<&str>) -> Result<()> {
+    capabilities()?;
+    let mut cmd = rbw_command();
     cmd.args(["unlocked"]);
-    set_rbw_tty(&mut cmd);
-    let output = cmd.output().context("failed to run `rbw unlocked`")?;
+    let output = timings::timed(&mut cmd, output).context("failed to run `rbw unlocked`")?;
     if !output.status.success() {
+        // Cached secrets must not outlive the unlocked session.
+        cache::clear();
+        if NO_UNLOCK.load(Ordering::Relaxed) {
+            let needed = reason
+                .map(|r| format!(" (needed to {r})"))
+                .unwrap_or_default();
+            bail!("vault is locked{needed} and --no-unlock was given; run `bwenv unlock` first");
+        }
+        if let Some(reason) = reason {
+            eprintln!("{}", unlock_notice(reason, profile().as_deref()));
+        }
         // Not unlocked — run `rbw unlock` which will invoke pinentry.
-        let mut cmd = Command::new("rbw");
+        let mut cmd = rbw_command();
         cmd.args(["unlock"]);
-        set_rbw_tty(&mut cmd);
-        let status = cmd.status().context("failed to run `rbw unlock`")?;
+        let timeout = limit(&UNLOCK_TIMEOUT);
+        let status = match timings::timed(&mut cmd, |cmd| {
+            wait_within(cmd.spawn()?, timeout, "--timeout-unlock")
+        }) {
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => bail!(
+                "gave up waiting for the master password: `rbw unlock` {e}; \
+                 raise --timeout-unlock (0 for no limit) if you need longer"
+            ),
+            result => result.context("failed to run `rbw unlock`")?,
+        };
         if !status.success() {
             bail!("`rbw unlock` failed ({})", status);
         }
@@ -207,74 +1039,691 @@ fn ensure_unlocked() -> Result<()> {
     Ok(())
 }
 
+/// The line printed before pinentry asks for the master password.
+fn unlock_notice(reason: &str, profile: Option<&str>) -> String {
+    match profile {
+        Some(profile) => {
+            format!("bwenv needs to {reason}; unlocking the vault (rbw profile `{profile}`)")
+        }
+        None => format!("bwenv needs to {reason}; unlocking the vault"),
+    }
+}
+
+/// Start an `rbw` invocation with bwenv's profile and TTY applied.  Every rbw
+/// subprocess must be built through this.
+fn rbw_command() -> Command {
+    let mut cmd = Command::new("rbw");
+    set_rbw_profile(&mut cmd, selected_profile().as_deref());
+    set_rbw_tty(&mut cmd);
+    cmd
+}
+
+/// Select the rbw profile via `RBW_PROFILE`, which makes rbw use its own config,
+/// data and agent (`rbw-<profile>` directories).  Without `--profile` the
+/// variable is inherited unchanged.
+fn set_rbw_profile(cmd: &mut Command, profile: Option<&str>) {
+    if let Some(profile) = profile {
+        cmd.env("RBW_PROFILE", profile);
+    }
+}
+
 /// Pass the real TTY device path (e.g. `/dev/pts/3`) so that the rbw-agent
 /// daemon — which has no controlling terminal — can tell pinentry which TTY to
 /// use.  `/dev/tty` would only work inside the current process tree; the agent
 /// needs an absolute device path.
+///
+/// Without a usable terminal, an inherited `RBW_TTY` that no longer names a
+/// terminal device (a `/dev/pts/N` gone after a tmux reattach) is removed
+/// rather than handed to pinentry, which would fail with a confusing error.
 fn set_rbw_tty(cmd: &mut Command) {
-    if let Some(tty) = real_tty_path() {
-        cmd.env("RBW_TTY", tty);
+    static TTY: std::sync::OnceLock<Option<std::ffi::OsString>> = std::sync::OnceLock::new();
+    let tty = TTY.get_or_init(|| {
+        let tty = real_tty_path();
+        if crate::verbose() {
+            match &tty {
+                Some(tty) => eprintln!("RBW_TTY: using {}", tty.to_string_lossy()),
+                None => eprintln!("RBW_TTY: no usable terminal found"),
+            }
+        }
+        tty
+    });
+    match tty {
+        Some(tty) => {
+            cmd.env("RBW_TTY", tty);
+        }
+        None => {
+            if let Some(inherited) = std::env::var_os("RBW_TTY")
+                && !is_char_device(std::path::Path::new(&inherited))
+            {
+                cmd.env_remove("RBW_TTY");
+            }
+        }
     }
 }
 
-/// Resolve the real TTY device path from stderr (fd 2).
-/// Falls back to `/dev/tty` if the real path cannot be determined.
+/// Resolve the real TTY device path from stderr (fd 2), then stdin.  The
+/// path must still exist as a character device: a terminal that has gone
+/// away still shows up in `/proc` (as `/dev/pts/N (deleted)`).  Falls back
+/// to `/dev/tty` if the process has a controlling terminal.
+#[cfg(unix)]
 fn real_tty_path() -> Option<std::ffi::OsString> {
     // Try stderr first (bwenv may have stdout piped), then stdin.
-    for fd in ["2", "0"] {
-        let link = format!("/proc/self/fd/{fd}");
-        if let Ok(path) = std::fs::read_link(&link) {
-            if path.to_string_lossy().starts_with("/dev/") {
-                return Some(path.into_os_string());
-            }
+    for fd in [2, 0] {
+        // SAFETY: isatty only queries the descriptor.
+        if unsafe { libc::isatty(fd) } != 1 {
+            continue;
+        }
+        if let Ok(path) = std::fs::read_link(format!("/proc/self/fd/{fd}"))
+            && path.to_string_lossy().starts_with("/dev/")
+            && is_char_device(&path)
+        {
+            return Some(path.into_os_string());
         }
     }
-    // Last resort: /dev/tty (works if caller has a ctty).
-    if std::path::Path::new("/dev/tty").exists() {
+    // Last resort: /dev/tty, which only opens with a controlling terminal.
+    if std::fs::File::open("/dev/tty").is_ok() {
         return Some("/dev/tty".into());
     }
     None
 }
 
+/// No TTY paths to hand rbw outside Unix.
+#[cfg(not(unix))]
+fn real_tty_path() -> Option<std::ffi::OsString> {
+    None
+}
+
+#[cfg(unix)]
+fn is_char_device(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::FileTypeExt as _;
+    std::fs::metadata(path).is_ok_and(|m| m.file_type().is_char_device())
+}
+
+#[cfg(not(unix))]
+fn is_char_device(path: &std::path::Path) -> bool {
+    path.exists()
+}
+
+/// Fail if `content` would not survive the round trip through rbw's editor
+/// buffer byte for byte (`set-raw`): rbw drops lines starting with `#` (bwenv
+/// indents them for KEY=VALUE notes), leading blank lines are dropped on
+/// read, the buffer is rebuilt line by line with `\n` endings, and trailing
+/// newlines are not kept.
+pub fn check_verbatim(content: &str) -> Result<()> {
+    if content.contains('\0') {
+        bail!("content contains a NUL byte, which Bitwarden notes cannot hold");
+    }
+    if content.contains('\r') {
+        bail!(
+            "content contains a carriage return (CRLF line endings?), which would not \
+             be kept; convert it to LF or store it base64-encoded (`set --encode`)"
+        );
+    }
+    if content.ends_with('\n') {
+        bail!("content ends with a blank line, which would not be kept");
+    }
+    if let Some(n) = content.lines().position(|l| l.starts_with('#')) {
+        bail!(
+            "line {} starts with `#`, which rbw would drop; indent it or store the \
+             content base64-encoded (`set --encode`)",
+            n + 1
+        );
+    }
+    if strip_framing(content).len() != content.len() {
+        bail!("content starts with a blank line, which would not be kept");
+    }
+    Ok(())
+}
+
+/// `content` without leading blank lines.
+///
+/// The first line of the editor buffer is the password.  Should it ever end
+/// up in the notes instead (an entry edited by hand, or an rbw that frames
+/// the buffer differently), the notes start with a stray blank line; it
+/// carries no data, so it is dropped on read and never written back.
+fn strip_framing(content: &str) -> &str {
+    let mut rest = content;
+    while let Some((line, tail)) = rest.split_once('\n')
+        && line.trim().is_empty()
+    {
+        rest = tail;
+    }
+    rest
+}
+
+/// Build the editor buffer rbw expects on stdin.
+///
+/// Login entries: first line = password, rest = notes.
+/// SecureNote entries: rbw internally prepends `\n` before parsing, so the
+/// notes are piped directly.
+///
+/// rbw discards buffer lines that begin with `#` (its help text), so comment
+/// lines are indented by one space to survive; `store::parse` trims them.
+fn editor_buffer(notes_content: &str, password: Option<&str>, is_secure_note: bool) -> String {
+    let mut buf = String::new();
+    if !is_secure_note {
+        buf.push_str(password.unwrap_or(""));
+        buf.push('\n');
+    }
+    for line in strip_framing(notes_content).lines() {
+        if line.starts_with('#') {
+            buf.push(' ');
+        }
+        buf.push_str(line);
+        buf.push('\n');
+    }
+    buf
+}
+
 /// Run an rbw command with the given args, piping `stdin_content` to its stdin.
 /// rbw's `edit::edit()` detects a non-TTY stdin and reads from it directly.
+///
+/// stderr is captured rather than inherited so a failure can report what rbw
+/// said, the same way `check_status` does.  Prompting is not affected: the
+/// vault is unlocked by `ensure_unlocked()` before rbw is spawned here.
 fn pipe_to_rbw(args: &[&str], stdin_content: &str) -> Result<()> {
-    ensure_unlocked()?;
+    let name = args.last().copied().unwrap_or_default();
+    ensure_unlocked(Some(&format!("save namespace `{name}`")))?;
 
-    let mut cmd = Command::new("rbw");
+    let mut cmd = rbw_command();
     cmd.args(args)
         .stdin(Stdio::piped())
         .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
-    set_rbw_tty(&mut cmd);
+        .stderr(Stdio::piped());
 
-    let mut sp = Spinner::with_stream(
-        Spinners::Dots,
-        "Saving to Bitwarden…".into(),
-        Stream::Stderr,
-    );
-    let mut child = cmd.spawn().context("failed to spawn rbw")?;
+    let sp = spinner::start("Saving to Bitwarden…");
+    let stdin_len = Some(stdin_content.len());
+    let output =
+        timings::timed_piped(&mut cmd, stdin_len, |cmd| -> Result<std::process::Output> {
+            let mut child = cmd.spawn().context("failed to spawn rbw")?;
 
-    child
-        .stdin
-        .take()
-        .context("failed to open rbw stdin")?
-        .write_all(stdin_content.as_bytes())
-        .context("failed to write to rbw stdin")?;
+            // The stdin handle is dropped at the end of this statement, closing
+            // the pipe so rbw sees EOF.
+            child
+                .stdin
+                .take()
+                .context("failed to open rbw stdin")?
+                .write_all(stdin_content.as_bytes())
+                .context("failed to write to rbw stdin")?;
 
-    let status = child.wait().context("failed to wait for rbw")?;
-    sp.stop_with_newline();
-    if !status.success() {
-        bail!("rbw exited with status {}", status);
+            collect_output(child, limit(&COMMAND_TIMEOUT)).context("failed to wait for rbw")
+        })?;
+    sp.stop();
+    check_status(&format!("rbw {}", args[0]), &output)?;
+    if crate::verbose() && !output.stderr.is_empty() {
+        eprint!("{}", String::from_utf8_lossy(&output.stderr));
     }
     Ok(())
 }
 
+/// Whether rbw's stderr says the requested entry does not exist.
+/// Update this list if rbw changes its error messages.
+fn is_not_found(stderr: &str) -> bool {
+    stderr.contains("no entry found")
+        || stderr.contains("no items found")
+        || stderr.contains("Entry not found")
+}
+
+/// The entries rbw listed when a name matched more than one of them
+/// (`multiple entries found: me@api, api`).  Update this if rbw changes the
+/// message.
+fn ambiguous_candidates(stderr: &str) -> Option<Vec<String>> {
+    let line = stderr
+        .lines()
+        .find(|l| l.to_ascii_lowercase().contains("multiple entries found"))?;
+    let list = line.split_once("found").map_or("", |(_, rest)| rest);
+    let list = list.trim_start_matches(':').trim();
+    Some(
+        list.split(", ")
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// The error for a namespace name that matches several vault entries: the
+/// candidates, and what to do about it.
+fn ambiguity_error(name: &str, folder: &str, candidates: &[String]) -> anyhow::Error {
+    let mut msg = format!("`{name}` matches more than one entry in folder `{folder}`");
+    if candidates.is_empty() {
+        msg.push('.');
+    } else {
+        msg.push(':');
+        for candidate in candidates {
+            msg.push_str(&format!("\n  {candidate}"));
+        }
+    }
+    msg.push_str(
+        "\nrename or delete the extra entries in Bitwarden so the name is unique \
+         (`bwenv list --json` shows their IDs)",
+    );
+    anyhow::anyhow!(msg)
+}
+
+/// Whether rbw's stderr is its argument parser rejecting an unknown flag.
+fn is_unknown_flag(stderr: &str) -> bool {
+    stderr.contains("unexpected argument")
+        || stderr.contains("wasn't expected")
+        || stderr.contains("unrecognized")
+}
+
 /// Convert a failed `Command` output into an error message.
 fn check_status(cmd: &str, output: &std::process::Output) -> Result<()> {
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
+        if let Some(candidates) = ambiguous_candidates(&stderr) {
+            bail!(
+                "`{cmd}` found more than one entry with that name ({}); rename or delete \
+                 the extra entries in Bitwarden so the name is unique",
+                candidates.join(", ")
+            );
+        }
         bail!("`{}` failed ({}): {}", cmd, output.status, stderr.trim());
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn calls_with_a_time_limit_are_killed_once_it_runs_out() {
+        let limit = Some(Duration::from_millis(100));
+        let started = Instant::now();
+        let child = Command::new("sleep").arg("5").spawn().unwrap();
+        let err = wait_within(child, limit, "--timeout").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = collect_output(cmd.spawn().unwrap(), limit).unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            (&output.stdout[..], &output.stderr[..]),
+            (&b"out\n"[..], &b"err\n"[..])
+        );
+    }
+
+    #[test]
+    fn unlock_notice_says_why_and_for_which_profile() {
+        assert_eq!(
+            unlock_notice("read namespace `prod`", None),
+            "bwenv needs to read namespace `prod`; unlocking the vault"
+        );
+        assert!(unlock_notice("sync the vault", Some("work")).ends_with("(rbw profile `work`)"));
+    }
+
+    #[test]
+    fn ambiguity_lists_the_candidates() {
+        let stderr =
+            "rbw get: couldn't find entry for 'api': multiple entries found: me@api, api\n";
+        assert_eq!(
+            ambiguous_candidates(stderr),
+            Some(vec!["me@api".to_string(), "api".to_string()])
+        );
+        assert_eq!(ambiguous_candidates("no entry found\n"), None);
+        let err = ambiguity_error("api", "bwenv", &["me@api".into(), "api".into()]).to_string();
+        assert!(err.starts_with(
+            "`api` matches more than one entry in folder `bwenv`:\n  me@api\n  api\n"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_tty_paths_are_not_char_devices() {
+        assert!(is_char_device(std::path::Path::new("/dev/null")));
+        assert!(!is_char_device(std::path::Path::new("/dev/pts/999999")));
+        assert!(!is_char_device(&std::env::temp_dir()));
+    }
+
+    #[test]
+    fn parallel_map_preserves_order_and_bounds_workers() {
+        set_max_concurrency(2);
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<u32> = (0..10).collect();
+        let out = parallel_map(&items, |&n| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(n * 2)
+        });
+        set_max_concurrency(DEFAULT_MAX_CONCURRENCY);
+        let out: Vec<u32> = out.into_iter().map(Result::unwrap).collect();
+        assert_eq!(out, (0..10).map(|n| n * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn profile_sets_rbw_profile() {
+        let env_of = |cmd: &Command| {
+            cmd.get_envs()
+                .find(|(k, _)| *k == "RBW_PROFILE")
+                .map(|(_, v)| v.map(|v| v.to_os_string()))
+        };
+
+        let mut cmd = Command::new("rbw");
+        set_rbw_profile(&mut cmd, Some("work"));
+        assert_eq!(env_of(&cmd), Some(Some("work".into())));
+
+        let mut cmd = Command::new("rbw");
+        set_rbw_profile(&mut cmd, None);
+        assert_eq!(env_of(&cmd), None);
+    }
+
+    #[test]
+    fn parse_version_variants() {
+        assert_eq!(parse_version("rbw 1.13.2\n"), Some((1, 13, 2)));
+        assert_eq!(parse_version("rbw 1.10.0-dev"), Some((1, 10, 0)));
+        assert_eq!(parse_version("rbw 2.0"), Some((2, 0, 0)));
+        assert_eq!(parse_version("rbw"), None);
+    }
+
+    #[test]
+    fn custom_field_namespaces_are_read_only() {
+        assert!(check_writable(None).is_ok());
+        let err = check_writable(Some("env")).unwrap_err().to_string();
+        assert!(err.contains("custom field `env`"));
+    }
+
+    #[test]
+    fn only_non_default_folders_must_exist() {
+        assert!(!folder_must_exist("bwenv"));
+        assert!(!folder_must_exist("/bwenv/"));
+        assert!(folder_must_exist("work"));
+        assert!(folder_must_exist("bwenv/child"));
+    }
+
+    #[test]
+    fn field_read_is_only_used_when_trashed_entries_are_wanted() {
+        assert!(!notes_via_field(false, false));
+        assert!(notes_via_field(true, false));
+        assert!(!notes_via_field(true, true));
+    }
+
+    #[test]
+    fn capabilities_by_version() {
+        let caps = Capabilities::for_version(Some((1, 12, 0)));
+        assert!(!caps.list_raw);
+        assert!(Capabilities::for_version(Some((1, 13, 0))).list_raw);
+        assert_eq!(
+            Capabilities::for_version(None),
+            Capabilities { list_raw: true }
+        );
+    }
+
+    #[test]
+    fn parse_list_fields_output() {
+        let items = read_list_fields("prod/db\tbwenv\nloose\t\n".as_bytes(), &|_| true).unwrap();
+        assert_eq!(items[0].name, "prod/db");
+        assert_eq!(items[0].folder.as_deref(), Some("bwenv"));
+        assert_eq!(items[1].folder, None);
+    }
+
+    #[test]
+    fn item_revision_and_history_are_optional() {
+        let item: RbwItem = serde_json::from_str(r#"{"type":"Login","notes":null}"#).unwrap();
+        assert!(item.revision_date.is_none());
+        assert!(item.history.is_empty());
+        assert!(item.deleted_date.is_none());
+
+        let item: RbwItem = serde_json::from_str(
+            r#"{"revisionDate":"2024-05-01T10:00:00Z",
+                "history":[{"last_used_date":"2024-04-01T00:00:00Z","password":"old"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(item.revision_date.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(item.history[0].last_used_date, "2024-04-01T00:00:00Z");
+    }
+
+    #[test]
+    fn nested_folders_match_exactly() {
+        let items: Vec<ListItem> = serde_json::from_str(
+            r#"[{"name":"a","folder":"parent","type":"Note"},
+                {"name":"b","folder":"parent/child","type":"Note"},
+                {"name":"c","folder":"parent/child/leaf","type":"Note"},
+                {"name":"d","folder":null,"type":"Note"}]"#,
+        )
+        .unwrap();
+        let names = |folder: Option<&str>| -> Vec<&str> {
+            items
+                .iter()
+                .filter(|i| in_folder(i, folder))
+                .map(|i| i.name.as_str())
+                .collect()
+        };
+        assert_eq!(names(Some("parent")), ["a"]);
+        assert_eq!(names(Some("parent/child")), ["b"]);
+        assert_eq!(names(Some("/parent//child/")), ["b"]);
+        assert_eq!(names(Some("parent/child/leaf")), ["c"]);
+        assert_eq!(names(None), ["d"]);
+    }
+
+    #[test]
+    fn no_folder_is_not_the_empty_folder() {
+        let items: Vec<ListItem> = serde_json::from_str(
+            r#"[{"name":"none","folder":null,"type":"Note"},
+                {"name":"empty","folder":"","type":"Note"},
+                {"name":"other","folder":"bwenv","type":"Note"}]"#,
+        )
+        .unwrap();
+        let names = |folder: Option<&str>| -> Vec<&str> {
+            items
+                .iter()
+                .filter(|i| in_folder(i, folder))
+                .map(|i| i.name.as_str())
+                .collect()
+        };
+        assert_eq!(names(None), ["none"]);
+        assert_eq!(names(Some("")), ["empty"]);
+    }
+
+    #[test]
+    fn normalize_folder_paths() {
+        assert_eq!(normalize_folder("a/b"), "a/b");
+        assert_eq!(normalize_folder("/a//b/"), "a/b");
+        assert_eq!(
+            normalize_folder("Work Stuff/API keys"),
+            "Work Stuff/API keys"
+        );
+        assert_eq!(normalize_folder("bwenv"), "bwenv");
+    }
+
+    #[test]
+    fn read_list_raw_filters_while_streaming() {
+        let json = r#"[{"name":"a","folder":"bwenv","type":"Login","user":"x"},
+                       {"name":"b","folder":"other","type":"Note"},
+                       {"name":"c","folder":"bwenv","type":"Note"}]"#;
+        let items = read_list_raw(json.as_bytes(), &|i| in_folder(i, Some("bwenv"))).unwrap();
+        let names: Vec<&str> = items.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["a", "c"]);
+
+        assert!(read_list_raw(r#"[{"name":"a"}"#.as_bytes(), &|_| true).is_err());
+        assert!(read_list_raw(r#"[] trailing"#.as_bytes(), &|_| true).is_err());
+    }
+
+    #[test]
+    fn list_item_reports_trash() {
+        let items: Vec<ListItem> = serde_json::from_str(
+            r#"[{"name":"a","folder":"bwenv","type":"Login"},
+                {"name":"b","folder":"bwenv","type":"Note","deletedDate":"2024-06-01T00:00:00Z"}]"#,
+        )
+        .unwrap();
+        assert!(items[0].deleted_date.is_none());
+        assert_eq!(
+            items[1].deleted_date.as_deref(),
+            Some("2024-06-01T00:00:00Z")
+        );
+    }
+
+    #[test]
+    fn content_comes_from_notes_or_custom_field() {
+        let item: RbwItem = serde_json::from_str(
+            r#"{"notes":"A=1","fields":[{"name":"other","value":"x"},{"name":"env","value":"B=2"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(item.content_of(None), Some("A=1"));
+        assert_eq!(item.content_of(Some("env")), Some("B=2"));
+        assert_eq!(item.content_of(Some("missing")), None);
+    }
+
+    #[test]
+    fn list_item_json_shape() {
+        let item = ListItem {
+            id: None,
+            name: "dev".into(),
+            folder: Some("bwenv".into()),
+            item_type: "Note".into(),
+            deleted_date: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&item).unwrap(),
+            r#"{"name":"dev","folder":"bwenv","type":"Note"}"#
+        );
+    }
+
+    #[test]
+    fn offline_sync_errors_are_recognized() {
+        assert!(is_offline(
+            "rbw sync: failed to sync database: error sending request for url \
+             (https://api.bitwarden.com/sync): dns error: failed to lookup address"
+        ));
+        assert!(is_offline(
+            "tcp connect error: Connection refused (os error 111)"
+        ));
+        assert!(!is_offline(
+            "failed to sync database: API error: Invalid token"
+        ));
+        assert!(!is_offline("rbw-agent: failed to unlock"));
+    }
+
+    #[test]
+    fn editor_buffer_keeps_login_password() {
+        let item: RbwItem = serde_json::from_str(
+            r#"{"type":"Login","notes":"A=1","data":{"username":"me","password":"hunter2"}}"#,
+        )
+        .unwrap();
+        let buf = editor_buffer("A=2", item.password(), item.is_secure_note());
+        assert_eq!(buf.lines().next(), Some("hunter2"));
+        assert_eq!(buf, "hunter2\nA=2\n");
+    }
+
+    #[test]
+    fn editor_buffer_login_without_password() {
+        let item: RbwItem =
+            serde_json::from_str(r#"{"type":"Login","notes":"A=1","data":{"password":null}}"#)
+                .unwrap();
+        assert_eq!(
+            editor_buffer("A=1", item.password(), item.is_secure_note()),
+            "\nA=1\n"
+        );
+    }
+
+    #[test]
+    fn editor_buffer_protects_comment_lines() {
+        assert_eq!(
+            editor_buffer("# desc: token\nA=1", None, false),
+            "\n # desc: token\nA=1\n"
+        );
+    }
+
+    /// What rbw stores as the notes for an editor buffer: the first line
+    /// is the password (SecureNotes get a `\n` prepended first).  With
+    /// `leaky`, the notes keep the framing newline.
+    fn stored_notes(buf: &str, is_secure_note: bool, leaky: bool) -> String {
+        let buf = if is_secure_note {
+            format!("\n{buf}")
+        } else {
+            buf.to_string()
+        };
+        let (_password, notes) = buf.split_once('\n').unwrap();
+        let notes = notes.trim_end_matches('\n');
+        if leaky {
+            format!("\n{notes}")
+        } else {
+            notes.to_string()
+        }
+    }
+
+    #[test]
+    fn create_edit_get_round_trip_has_no_blank_first_line() {
+        let content = "# desc: token\nA=1\nB=two words";
+        for leaky in [false, true] {
+            // create → get
+            let created = stored_notes(&editor_buffer(content, None, false), false, leaky);
+            let item = RbwItem {
+                notes: Some(created),
+                ..serde_json::from_str(r#"{"type":"Login","data":{"password":null}}"#).unwrap()
+            };
+            let notes = item.content_of(None).unwrap();
+            assert_eq!(notes, " # desc: token\nA=1\nB=two words");
+            let parsed = crate::store::parse(notes);
+            let mut keys: Vec<&String> = parsed.keys().collect();
+            keys.sort();
+            assert_eq!(keys, ["A", "B"]);
+
+            // edit → get, for both entry types
+            for secure in [false, true] {
+                let buf = editor_buffer(notes, Some("pw").filter(|_| !secure), secure);
+                assert!(!buf.starts_with(if secure { "\n" } else { "pw\n\n" }));
+                let edited = stored_notes(&buf, secure, leaky);
+                assert_eq!(strip_framing(&edited), notes);
+            }
+        }
+        assert_eq!(strip_framing("\n \r\nA=1\n\nB=2"), "A=1\n\nB=2");
+        assert_eq!(strip_framing("\n"), "");
+    }
+
+    #[test]
+    fn verbatim_content_must_survive_the_editor_buffer() {
+        let pem = "-----BEGIN KEY-----\nMIIB\n-----END KEY-----";
+        assert!(check_verbatim(pem).is_ok());
+        assert_eq!(
+            strip_framing(&editor_buffer(pem, None, true)),
+            format!("{pem}\n")
+        );
+        assert!(check_verbatim("{\n# comment\n}").is_err());
+        assert!(check_verbatim("\n{}").is_err());
+        assert!(check_verbatim("a\0b").is_err());
+        assert!(check_verbatim("{\r\n}").is_err());
+        assert!(check_verbatim("a\rb").is_err());
+        assert!(check_verbatim("{}\n").is_err());
+    }
+
+    #[test]
+    fn card_and_identity_entries_are_not_written() {
+        for kind in ["Card", "Identity"] {
+            let item: RbwItem = serde_json::from_str(&format!(
+                r#"{{"type":"{kind}","notes":"A=1","data":{{"number":"4111"}}}}"#
+            ))
+            .unwrap();
+            let err = item.ensure_env_storage().unwrap_err().to_string();
+            assert!(err.contains("unsupported entry type") && err.contains(kind));
+            // Refused before rbw is ever spawned.
+            let err = edit_item("dev", "bwenv", "A=2", &item).unwrap_err();
+            assert!(format!("{err:#}").contains("unsupported entry type"));
+        }
+        for json in [r#"{"type":"Login"}"#, r#"{"type":"Note"}"#, r#"{}"#] {
+            let item: RbwItem = serde_json::from_str(json).unwrap();
+            assert!(item.ensure_env_storage().is_ok());
+        }
+    }
+
+    #[test]
+    fn editor_buffer_secure_note_has_no_password_line() {
+        let item: RbwItem =
+            serde_json::from_str(r#"{"type":"Note","notes":"A=1","data":null}"#).unwrap();
+        assert!(item.is_secure_note());
+        assert_eq!(
+            editor_buffer("A=1", item.password(), item.is_secure_note()),
+            "A=1\n"
+        );
+    }
+}
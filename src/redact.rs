@@ -0,0 +1,126 @@
+//! Best-effort masking of injected secret values in a child's output.
+//!
+//! Used by `--redact`: the child's stdout/stderr are piped through a
+//! [`Redactor`] that replaces every occurrence of a secret with `***`.
+//! Matching is leftmost-longest over raw bytes, so binary output is handled,
+//! but output is passed through line by line (see [`Redactor::redact`]).
+
+use zeroize::Zeroize as _;
+
+/// Replacement written in place of a secret.
+const MASK: &[u8] = b"***";
+
+/// Values shorter than this are not redacted: masking every `1` or `true` in
+/// the output would make it unreadable while protecting nothing.
+pub const MIN_SECRET_LEN: usize = 4;
+
+pub struct Redactor {
+    /// Patterns bucketed by first byte, longest first within each bucket.
+    buckets: Vec<Vec<Vec<u8>>>,
+    max_len: usize,
+}
+
+impl Redactor {
+    pub fn new<'a>(secrets: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut patterns: Vec<Vec<u8>> = secrets
+            .into_iter()
+            .filter(|s| s.len() >= MIN_SECRET_LEN)
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        patterns.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        patterns.dedup();
+
+        let max_len = patterns.first().map_or(0, Vec::len);
+        let mut buckets: Vec<Vec<Vec<u8>>> = vec![Vec::new(); 256];
+        for p in patterns {
+            buckets[p[0] as usize].push(p);
+        }
+        Self { buckets, max_len }
+    }
+
+    /// Redact a prefix of `input`, returning the output and how many input
+    /// bytes were consumed.
+    ///
+    /// Unless `at_end` is set, scanning stops where a secret could still be
+    /// completed by bytes not yet read; the caller keeps the unconsumed tail
+    /// and retries once more input arrives.
+    pub fn redact(&self, input: &[u8], at_end: bool) -> (Vec<u8>, usize) {
+        let mut out = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < input.len() {
+            if !at_end && input.len() - i < self.max_len {
+                break;
+            }
+            let rest = &input[i..];
+            match self.buckets[rest[0] as usize]
+                .iter()
+                .find(|p| rest.starts_with(p))
+            {
+                Some(p) => {
+                    out.extend_from_slice(MASK);
+                    i += p.len();
+                }
+                None => {
+                    out.push(rest[0]);
+                    i += 1;
+                }
+            }
+        }
+        (out, i)
+    }
+}
+
+impl Drop for Redactor {
+    fn drop(&mut self) {
+        for p in self.buckets.iter_mut().flatten() {
+            p.zeroize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact_all(r: &Redactor, s: &str) -> String {
+        String::from_utf8(r.redact(s.as_bytes(), true).0).unwrap()
+    }
+
+    #[test]
+    fn masks_every_occurrence() {
+        let r = Redactor::new(["hunter2", "s3cr3t"]);
+        assert_eq!(
+            redact_all(&r, "pw=hunter2 token=s3cr3t again hunter2\n"),
+            "pw=*** token=*** again ***\n"
+        );
+    }
+
+    #[test]
+    fn prefers_longest_match() {
+        let r = Redactor::new(["abcd", "abcdef"]);
+        assert_eq!(redact_all(&r, "xabcdefx abcdx"), "x***x ***x");
+    }
+
+    #[test]
+    fn ignores_short_values() {
+        let r = Redactor::new(["1", "yes", "long-enough"]);
+        assert_eq!(redact_all(&r, "1 yes long-enough"), "1 yes ***");
+    }
+
+    #[test]
+    fn holds_back_possible_partial_match() {
+        let r = Redactor::new(["secret"]);
+        let (out, used) = r.redact(b"xx secr", false);
+        assert_eq!(out, b"xx");
+        assert_eq!(used, 2);
+        let (out, used) = r.redact(b" secret", false);
+        assert_eq!((out.as_slice(), used), (&b" ***"[..], 7));
+    }
+
+    #[test]
+    fn binary_input_passes_through() {
+        let r = Redactor::new(["\u{1}\u{2}\u{3}\u{4}"]);
+        let (out, _) = r.redact(&[0, 1, 2, 3, 4, 255], true);
+        assert_eq!(out, [0, b'*', b'*', b'*', 255]);
+    }
+}
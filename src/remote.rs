@@ -0,0 +1,119 @@
+//! `--remote [USER@]HOST`: run this bwenv invocation on another machine.
+//!
+//! For bastion setups where the vault is only unlocked on one host: bwenv
+//! re-runs itself there as `ssh HOST bwenv <same arguments>` (minus
+//! `--remote`) and exits with the remote exit status.  Stdout and stderr are
+//! streamed back unchanged, so `eval "$(bwenv --remote bastion export ns)"`
+//! works as it does locally — which also means whatever the remote bwenv
+//! prints (values included) crosses the SSH connection and lands here.
+//!
+//! A terminal is allocated (`ssh -t`) only when both stdin and stdout are
+//! terminals, so pinentry can prompt on the remote side in interactive use
+//! while piped output stays byte-for-byte (a pty would turn `\n` into
+//! `\r\n`).  Environment variables such as `BWENV_FOLDER` are not forwarded;
+//! the remote side uses its own.
+
+use crate::{child, store};
+use anyhow::{Result, bail};
+use std::io::IsTerminal as _;
+use std::process::Command;
+
+/// Command run on the remote host, unless `BWENV_REMOTE_COMMAND` names
+/// another (e.g. a full path outside the remote `PATH`).
+const DEFAULT_REMOTE_COMMAND: &str = "bwenv";
+const REMOTE_COMMAND_ENV: &str = "BWENV_REMOTE_COMMAND";
+
+/// Run bwenv with `args` (argv without the program name) on `host`, and
+/// return the exit code to exit with.
+pub fn run(host: &str, args: Vec<std::ffi::OsString>) -> Result<i32> {
+    if host.is_empty() || host.starts_with('-') {
+        bail!("invalid --remote host {host:?}");
+    }
+    let program = std::env::var(REMOTE_COMMAND_ENV)
+        .ok()
+        .filter(|c| !c.is_empty())
+        .unwrap_or_else(|| DEFAULT_REMOTE_COMMAND.to_string());
+    let remote = remote_command(&program, &strip_remote(args)?);
+
+    let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let mut cmd = Command::new("ssh");
+    cmd.arg(if interactive { "-t" } else { "-T" })
+        .arg("--")
+        .arg(host)
+        .arg(remote);
+    child::run(&mut cmd)
+}
+
+/// `args` without the `--remote HOST` (or `--remote=HOST`) that selected
+/// the host.  The first occurrence is the one clap parsed; a later one
+/// belongs to an exec-mode command and is kept.
+fn strip_remote(args: Vec<std::ffi::OsString>) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    let mut stripped = false;
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let Ok(arg) = arg.into_string() else {
+            bail!("--remote: arguments must be valid UTF-8");
+        };
+        if !stripped && arg == "--" {
+            stripped = true;
+        } else if !stripped && arg == "--remote" {
+            iter.next();
+            stripped = true;
+            continue;
+        } else if !stripped && arg.starts_with("--remote=") {
+            stripped = true;
+            continue;
+        }
+        out.push(arg);
+    }
+    Ok(out)
+}
+
+/// The command line for the remote shell: ssh joins its arguments with
+/// spaces and hands them to a shell, so every word is quoted.
+fn remote_command(program: &str, args: &[String]) -> String {
+    std::iter::once(program.to_string())
+        .chain(args.iter().map(|a| store::shell_quote(a)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(words: &[&str]) -> Vec<std::ffi::OsString> {
+        words.iter().map(Into::into).collect()
+    }
+
+    #[test]
+    fn strips_only_the_remote_flag_clap_parsed() {
+        assert_eq!(
+            strip_remote(args(&["--remote", "bastion", "export", "prod"])).unwrap(),
+            ["export", "prod"]
+        );
+        assert_eq!(
+            strip_remote(args(&["--remote=me@bastion", "list"])).unwrap(),
+            ["list"]
+        );
+        assert_eq!(
+            strip_remote(args(&["--remote", "b", "dev", "tool", "--remote", "x"])).unwrap(),
+            ["dev", "tool", "--remote", "x"]
+        );
+    }
+
+    #[test]
+    fn quotes_every_remote_word() {
+        let words = [
+            "dev".to_string(),
+            "sh".into(),
+            "-c".into(),
+            "echo $A; id".into(),
+        ];
+        assert_eq!(
+            remote_command("bwenv", &words),
+            "bwenv 'dev' 'sh' '-c' 'echo $A; id'"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Resolving the variables a command, `env` or `export` ends up with.
+//!
+//! One function, [`resolve_environment`], applies every step in a fixed
+//! order:
+//!
+//! 1. namespaces are layered in the order given; a later one overrides an
+//!    earlier one (with a warning), and a `PREFIX:` entry renames its keys
+//!    to `PREFIX_KEY` before it is layered (all but [`STORED_CMD_KEY`]);
+//! 2. the `--env-file` overlay wins over all namespaces, silently;
+//! 3. `--value-filter` runs over the merged text values;
+//! 4. `--expand-from-env` resolves `${VAR}` references in what is left.
+//!
+//! Fetching the namespaces is the caller's job, and nothing here prints or
+//! reads the process environment: warnings are returned, and variables for
+//! expansion come from a lookup function.  The value filter, when one is
+//! configured, is the only thing that runs a command.
+
+use crate::{filter, store};
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use zeroize::Zeroize as _;
+
+/// Reserved key holding the command `--use-stored-cmd` runs.  It is never
+/// injected into a command's environment, and a `PREFIX:` doesn't rename
+/// it, so a prefixed namespace can still carry the command.
+pub const STORED_CMD_KEY: &str = "__cmd";
+
+/// What to resolve: parsed namespaces in layering order, plus the optional
+/// overlay and expansion.
+pub struct Spec {
+    pub layers: Vec<Layer>,
+    /// `--env-file`: its label (the path) and contents.
+    pub overlay: Option<(String, store::Parsed)>,
+    /// `--expand-from-env`, and whether unset variables are allowed.
+    pub expand: Option<bool>,
+}
+
+/// One namespace's parsed notes.
+pub struct Layer {
+    /// The entry as given (`db:postgres`), used in warnings and origins.
+    pub label: String,
+    /// With the trailing `_` (`DB_`).
+    pub prefix: Option<String>,
+    pub parsed: store::Parsed,
+}
+
+impl Layer {
+    /// The layer for namespace list entry `entry`, whose notes parsed to
+    /// `parsed`.
+    pub fn new(entry: &str, parsed: store::Parsed) -> Self {
+        Layer {
+            label: entry.to_string(),
+            prefix: split_prefix(entry).0,
+            parsed,
+        }
+    }
+}
+
+/// The final variables.
+#[derive(Default)]
+pub struct Resolved {
+    pub pairs: HashMap<String, String>,
+    /// Which namespace (or overlay file) each key's value came from.
+    pub origins: HashMap<String, String>,
+    /// Keys whose values are base64 (`KEY!=`).
+    pub encoded: HashSet<String>,
+    /// Keys defined by more than one namespace, for the caller to report.
+    pub warnings: Vec<String>,
+    /// Keys the overlay replaced.
+    pub overridden: Vec<String>,
+}
+
+/// `PREFIX:namespace` in a namespace list (`db:postgres`): the namespace's
+/// keys are injected as `DB_<KEY>`.  Only a valid variable name counts as a
+/// prefix, so other names containing `:` are used whole; a leading `:`
+/// (`:app:prod`) forces that for a name that would otherwise look prefixed.
+pub fn split_prefix(entry: &str) -> (Option<String>, &str) {
+    if let Some(name) = entry.strip_prefix(':') {
+        return (None, name);
+    }
+    match entry.split_once(':') {
+        Some((prefix, ns)) if store::is_valid_env_key(prefix) && !ns.is_empty() => {
+            (Some(format!("{}_", prefix.to_ascii_uppercase())), ns)
+        }
+        _ => (None, entry),
+    }
+}
+
+/// Apply `spec` step by step (see the module docs).  `vars` looks up the
+/// variables `${VAR}` can refer to.  A prefixed key that clashes with
+/// another namespace's key is an error rather than a silent override.
+pub fn resolve_environment(spec: Spec, vars: impl Fn(&str) -> Option<String>) -> Result<Resolved> {
+    let mut resolved = Resolved::default();
+    let mut prefixed: HashSet<String> = HashSet::new();
+    for Layer {
+        label,
+        prefix,
+        mut parsed,
+    } in spec.layers
+    {
+        if let Some(prefix) = &prefix {
+            parsed.pairs = parsed
+                .pairs
+                .into_iter()
+                .map(|(k, v)| match k.as_str() {
+                    STORED_CMD_KEY => (k, v),
+                    _ => (format!("{prefix}{k}"), v),
+                })
+                .collect();
+            for k in parsed.encoded.iter_mut().filter(|k| *k != STORED_CMD_KEY) {
+                k.insert_str(0, prefix);
+            }
+        }
+        for k in parsed.pairs.keys() {
+            resolved.encoded.remove(k);
+        }
+        resolved.encoded.extend(parsed.encoded);
+        for (k, v) in parsed.pairs {
+            let renamed = prefix.is_some() && k != STORED_CMD_KEY;
+            if let Some(prev) = resolved.origins.get(&k) {
+                if renamed || prefixed.contains(&k) {
+                    bail!(
+                        "key \"{k}\" from \"{label}\" collides with the one from \"{prev}\"; \
+                         pick prefixes that keep them apart"
+                    );
+                }
+                resolved.warnings.push(format!(
+                    "key \"{k}\" defined in both \"{prev}\" and \"{label}\"; \
+                     using value from \"{label}\""
+                ));
+            }
+            if renamed {
+                prefixed.insert(k.clone());
+            }
+            resolved.pairs.insert(k.clone(), v);
+            resolved.origins.insert(k, label.clone());
+        }
+    }
+
+    if let Some((path, local)) = spec.overlay {
+        for k in local.pairs.keys() {
+            resolved.encoded.remove(k);
+        }
+        resolved.encoded.extend(local.encoded);
+        for (k, v) in local.pairs {
+            if resolved.pairs.contains_key(&k) {
+                resolved.overridden.push(k.clone());
+            }
+            resolved.pairs.insert(k.clone(), v);
+            resolved.origins.insert(k, path.clone());
+        }
+    }
+
+    let encoded = &resolved.encoded;
+    filter::apply(&mut resolved.pairs, |k| encoded.contains(k))?;
+    if let Some(allow_missing) = spec.expand {
+        expand(&mut resolved.pairs, encoded, vars, allow_missing)?;
+    }
+    Ok(resolved)
+}
+
+/// Resolve `${VAR}` references in every value except base64 ones.  Other
+/// keys being resolved are not visible, so a value can't refer to a sibling.
+fn expand(
+    pairs: &mut HashMap<String, String>,
+    encoded: &HashSet<String>,
+    vars: impl Fn(&str) -> Option<String>,
+    allow_missing: bool,
+) -> Result<()> {
+    let mut keys: Vec<String> = pairs
+        .keys()
+        .filter(|k| !encoded.contains(*k))
+        .cloned()
+        .collect();
+    keys.sort();
+    for key in keys {
+        let value = pairs.get_mut(&key).expect("key present");
+        let expanded = store::expand_vars(value, &vars, allow_missing)
+            .with_context(|| format!("failed to expand `{key}`"))?;
+        value.zeroize();
+        *value = expanded;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entry: &str, notes: &str) -> Layer {
+        Layer::new(entry, store::parse_checked(notes, true))
+    }
+
+    fn resolve(
+        layers: Vec<Layer>,
+        overlay: Option<&str>,
+        expand: Option<bool>,
+    ) -> Result<Resolved> {
+        let spec = Spec {
+            layers,
+            overlay: overlay.map(|o| (".env.local".to_string(), store::parse_checked(o, true))),
+            expand,
+        };
+        resolve_environment(spec, |name| {
+            (name == "HOME").then(|| "/home/me".to_string())
+        })
+    }
+
+    #[test]
+    fn namespace_entries_may_carry_a_prefix() {
+        assert_eq!(
+            split_prefix("db:postgres"),
+            (Some("DB_".into()), "postgres")
+        );
+        assert_eq!(
+            split_prefix("Cache:prod/redis"),
+            (Some("CACHE_".into()), "prod/redis")
+        );
+        assert_eq!(split_prefix("prod/db"), (None, "prod/db"));
+        assert_eq!(split_prefix("prod/db:eu"), (None, "prod/db:eu"));
+        assert_eq!(split_prefix("db:"), (None, "db:"));
+        assert_eq!(split_prefix(":app:prod"), (None, "app:prod"));
+    }
+
+    #[test]
+    fn later_layers_win_and_the_overlay_wins_over_all() {
+        let resolved = resolve(
+            vec![
+                layer("base", "A=1\nB=1\nBIN!=AA=="),
+                layer("dev", "B=2\nBIN=text"),
+            ],
+            Some("A=local"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(resolved.pairs["A"], "local");
+        assert_eq!(resolved.origins["A"], ".env.local");
+        assert_eq!(resolved.pairs["B"], "2");
+        assert_eq!(resolved.origins["B"], "dev");
+        // The winning definition decides whether a key is base64.
+        assert!(resolved.encoded.is_empty());
+        assert_eq!(resolved.warnings.len(), 2);
+        assert_eq!(resolved.overridden, ["A"]);
+    }
+
+    #[test]
+    fn prefixes_apply_before_layering_and_may_not_collide() {
+        let resolved = resolve(
+            vec![
+                layer("db:pg", "HOST=pg\nPW!=AA=="),
+                layer("cache:redis", "HOST=r"),
+            ],
+            Some("DB_HOST=localhost"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(resolved.pairs["DB_HOST"], "localhost");
+        assert_eq!(resolved.pairs["CACHE_HOST"], "r");
+        assert!(resolved.encoded.contains("DB_PW"));
+        assert!(resolved.warnings.is_empty());
+
+        let clash = vec![layer("db:pg", "HOST=pg"), layer("plain", "DB_HOST=x")];
+        assert!(resolve(clash, None, None).is_err());
+    }
+
+    #[test]
+    fn prefixes_leave_the_stored_command_key_alone() {
+        let resolved = resolve(
+            vec![
+                layer("base", "__cmd=make"),
+                layer("db:ops", "HOST=pg\n__cmd=make deploy"),
+            ],
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(resolved.pairs[STORED_CMD_KEY], "make deploy");
+        assert_eq!(resolved.origins[STORED_CMD_KEY], "db:ops");
+        assert!(!resolved.pairs.contains_key("DB___cmd"));
+        assert_eq!(resolved.pairs["DB_HOST"], "pg");
+    }
+
+    #[test]
+    fn expansion_runs_last_and_skips_base64() {
+        let resolved = resolve(
+            vec![layer("base", "DIR=${HOME}/a\nBIN!=JHt9")],
+            Some("CACHE=${HOME}/.cache"),
+            Some(false),
+        )
+        .unwrap();
+        assert_eq!(resolved.pairs["DIR"], "/home/me/a");
+        assert_eq!(resolved.pairs["CACHE"], "/home/me/.cache");
+        assert_eq!(resolved.pairs["BIN"], "JHt9");
+        assert!(resolve(vec![layer("base", "X=${NOPE}")], None, Some(false)).is_err());
+        assert_eq!(
+            resolve(vec![layer("base", "X=${NOPE}")], None, Some(true))
+                .unwrap()
+                .pairs["X"],
+            ""
+        );
+    }
+}
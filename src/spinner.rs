@@ -0,0 +1,80 @@
+//! Progress spinners shown on stderr while rbw works.
+//!
+//! All spinners go through [`start`], so `BWENV_SPINNER` applies everywhere:
+//! `dots` (the default), `line`, any other style name from the `spinners`
+//! crate (e.g. `arc`, `dots2`), or `none` to show nothing.
+
+use spinners::{Spinner, Spinners, Stream};
+use std::str::FromStr as _;
+use std::sync::OnceLock;
+
+const SPINNER_ENV: &str = "BWENV_SPINNER";
+
+/// A running spinner, or nothing when spinners are disabled.
+pub struct Progress(Option<Spinner>);
+
+impl Progress {
+    /// Stop the spinner and end its line.
+    pub fn stop(mut self) {
+        if let Some(sp) = self.0.as_mut() {
+            sp.stop_with_newline();
+        }
+    }
+}
+
+/// Start a spinner with `message` in the configured style.
+pub fn start(message: impl Into<String>) -> Progress {
+    Progress(style().map(|s| Spinner::with_stream(s, message.into(), Stream::Stderr)))
+}
+
+static STYLE: OnceLock<Option<Spinners>> = OnceLock::new();
+
+/// Show no spinners for the rest of the process, whatever `BWENV_SPINNER`
+/// says (for long-running loops that would print one per iteration).
+pub fn disable() {
+    STYLE.set(None).ok();
+}
+
+/// The configured style (read once), `None` for `none`.
+fn style() -> Option<Spinners> {
+    STYLE
+        .get_or_init(|| match std::env::var(SPINNER_ENV) {
+            Ok(raw) => parse_style(&raw).unwrap_or_else(|| {
+                eprintln!(
+                    "WARNING: ignoring invalid {SPINNER_ENV}={raw:?} (expected dots, line or none)"
+                );
+                Some(Spinners::Dots)
+            }),
+            Err(_) => Some(Spinners::Dots),
+        })
+        .clone()
+}
+
+/// `Some(None)` for `none`; `None` if `raw` names no known style.
+fn parse_style(raw: &str) -> Option<Option<Spinners>> {
+    let raw = raw.trim();
+    if raw.is_empty() || raw.eq_ignore_ascii_case("none") {
+        return Some(None);
+    }
+    // Style names are the crate's variant names (`Dots`, `Dots2`, `SimpleDots`);
+    // accept them with a lowercase first letter too.
+    let mut chars = raw.chars();
+    let first = chars.next()?.to_ascii_uppercase();
+    let name: String = std::iter::once(first).chain(chars).collect();
+    Spinners::from_str(&name).ok().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_styles() {
+        assert!(matches!(parse_style("dots"), Some(Some(Spinners::Dots))));
+        assert!(matches!(parse_style("line"), Some(Some(Spinners::Line))));
+        assert!(matches!(parse_style("Dots2"), Some(Some(Spinners::Dots2))));
+        assert!(matches!(parse_style("none"), Some(None)));
+        assert!(matches!(parse_style(""), Some(None)));
+        assert!(parse_style("sparkles-nope").is_none());
+    }
+}
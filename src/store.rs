@@ -1,10 +1,52 @@
 //! Parse and serialize KEY=VALUE pairs stored in a Bitwarden note's notes field.
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use zeroize::Zeroize as _;
+
+/// `--keep-cr`: only `\n` and `\r\n` end a line when parsing, so a lone `\r`
+/// stays part of the value.
+static KEEP_CR: AtomicBool = AtomicBool::new(false);
+
+/// Stop treating a lone `\r` as a line ending (see [`split_lines`]).
+pub fn set_keep_cr(keep: bool) {
+    KEEP_CR.store(keep, Ordering::Relaxed);
+}
+
+/// The lines of `notes` for parsing.  `\n`, `\r\n` and (unless `keep_cr`) a
+/// lone `\r` all end a line, so notes written on Windows or pasted from old
+/// Mac tools don't leave carriage returns in values.
+fn split_lines(notes: &str, keep_cr: bool) -> Vec<&str> {
+    if keep_cr {
+        return notes.lines().collect();
+    }
+    let mut lines = Vec::new();
+    let mut rest = notes;
+    while !rest.is_empty() {
+        let Some(end) = rest.find(['\n', '\r']) else {
+            lines.push(rest);
+            break;
+        };
+        lines.push(&rest[..end]);
+        let ending = if rest[end..].starts_with("\r\n") {
+            2
+        } else {
+            1
+        };
+        rest = &rest[end + ending..];
+    }
+    lines
+}
+
+/// [`split_lines`] under the current `--keep-cr` setting: how every reader
+/// and rewriter of notes splits them, so they agree on what a line is.
+fn note_lines(notes: &str) -> Vec<&str> {
+    split_lines(notes, KEEP_CR.load(Ordering::Relaxed))
+}
 
 /// Return `true` if `key` is a valid POSIX environment-variable name:
 /// `[A-Za-z_][A-Za-z0-9_]*` with no null bytes.
-fn is_valid_env_key(key: &str) -> bool {
+pub fn is_valid_env_key(key: &str) -> bool {
     if key.is_empty() || key.contains('\0') {
         return false;
     }
@@ -14,30 +56,284 @@ fn is_valid_env_key(key: &str) -> bool {
         && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
+/// Return `true` if `key` follows the uppercase convention (`--strict-keys`):
+/// `[A-Z_][A-Z0-9_]*`.
+pub fn is_conventional_key(key: &str) -> bool {
+    is_valid_env_key(key)
+        && key
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Replace every `${VAR}` in `value` with `lookup(VAR)`; `$${` is a literal
+/// `${`.  A reference `lookup` can't resolve is an error, or the empty
+/// string when `allow_missing`.
+pub fn expand_vars(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    allow_missing: bool,
+) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+        if let Some(escaped) = after.strip_prefix("${") {
+            out.push_str("${");
+            rest = escaped;
+        } else if let Some(body) = after.strip_prefix('{') {
+            let end = body
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("unterminated `${{` reference"))?;
+            let name = &body[..end];
+            if !is_valid_env_key(name) {
+                anyhow::bail!("invalid variable reference `${{{name}}}`");
+            }
+            match lookup(name) {
+                Some(v) => out.push_str(&v),
+                None if allow_missing => {}
+                None => anyhow::bail!("`${{{name}}}` is not set in the environment"),
+            }
+            rest = &body[end + 1..];
+        } else {
+            out.push('$');
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
 /// Parse note content into a map of env-var key → value.
 /// - Splits on the **first** `=` only (values may contain `=`).
 /// - Trims whitespace from both the key and the value.
+/// - A value wrapped in matching `"…"` or `'…'` is unwrapped and kept exactly
+///   as written inside the quotes (no escape sequences).
 /// - Skips blank lines and lines starting with `#`.
 /// - Skips and warns about lines whose key is not a valid POSIX env-var name
 ///   (catches empty keys, null bytes, and names with illegal characters).
+/// - A key defined more than once takes its last value, as in a shell.
+/// - A leading `export ` (as in pasted shell snippets) is ignored.
 pub fn parse(notes: &str) -> HashMap<String, String> {
-    let mut map = HashMap::new();
-    for line in notes.lines() {
-        let line = line.trim();
+    parse_checked(notes, true).pairs
+}
+
+/// Result of [`parse_checked`]: the pairs plus content worth flagging.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub pairs: HashMap<String, String>,
+    /// Keys defined more than once, in order of their first redefinition.
+    pub duplicates: Vec<String>,
+    /// Keys whose line carried a leading `export `.
+    pub exported: Vec<String>,
+    /// Keys whose (last) definition is a `KEY!=<base64>` binary value.  Their
+    /// value in `pairs` is the base64 text.
+    pub encoded: Vec<String>,
+    /// Every key, in the order of its first definition.
+    pub order: Vec<String>,
+}
+
+/// Strip a shell `export ` prefix from a trimmed line, if present.
+fn strip_export(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("export")?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim_start())
+}
+
+/// Like [`parse`], but also reports duplicate keys and `export` prefixes so
+/// callers can warn or refuse.  With `trim` off, unquoted values keep their
+/// surrounding whitespace.
+pub fn parse_checked(notes: &str, trim: bool) -> Parsed {
+    let mut parsed = Parsed::default();
+    for line in note_lines(notes) {
+        let line = line.trim_start();
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
+        let (line, exported) = match strip_export(line) {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
         if let Some((k, v)) = line.split_once('=') {
             let k = k.trim();
-            let v = v.trim();
+            let (k, encoded) = match k.strip_suffix(ENCODED_MARKER) {
+                Some(k) => (k, true),
+                None => (k, false),
+            };
+            let v = parse_value(v, trim);
             if is_valid_env_key(k) {
-                map.insert(k.to_string(), v.to_string());
+                if exported {
+                    parsed.exported.push(k.to_string());
+                }
+                parsed.encoded.retain(|e| e != k);
+                if encoded {
+                    parsed.encoded.push(k.to_string());
+                }
+                let previous = parsed.pairs.insert(k.to_string(), v.to_string());
+                if previous.is_none() {
+                    parsed.order.push(k.to_string());
+                } else if !parsed.duplicates.iter().any(|d| d == k) {
+                    parsed.duplicates.push(k.to_string());
+                }
             } else {
+                let line = line.trim_end();
                 eprintln!("WARNING: skipping line with invalid env-var key: {line:?}");
             }
         }
     }
-    map
+    parsed
+}
+
+/// Suffix on a key (`KEY!=…`) marking its value as base64-encoded binary.
+pub const ENCODED_MARKER: char = '!';
+
+/// A problem [`lint`] found on one line of a namespace.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Issue {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+    /// `true` for content the parser skips or can't use; `false` for content
+    /// that is merely questionable (duplicates, `export`), which only fails
+    /// under `--strict-parse`.
+    pub error: bool,
+}
+
+/// Check note content line by line.  Messages name keys but never include
+/// values.
+pub fn lint(notes: &str) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    let mut issue = |line: usize, message: String, error: bool| {
+        issues.push(Issue {
+            line,
+            message,
+            error,
+        })
+    };
+    let lines = note_lines(notes);
+    for (i, line) in lines.into_iter().enumerate() {
+        let n = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let rest = match strip_export(line) {
+            Some(rest) => {
+                issue(n, "`export` prefix is ignored".to_string(), false);
+                rest
+            }
+            None => line,
+        };
+        let Some((k, v)) = rest.split_once('=') else {
+            issue(n, "not a KEY=VALUE line (skipped)".to_string(), true);
+            continue;
+        };
+        let k = k.trim();
+        let (key, encoded) = match k.strip_suffix(ENCODED_MARKER) {
+            Some(key) => (key, true),
+            None => (k, false),
+        };
+        if !is_valid_env_key(key) {
+            issue(n, format!("invalid key {key:?} (skipped)"), true);
+            continue;
+        }
+        if encoded && crate::base64::decode(parse_value(v, true)).is_err() {
+            issue(
+                n,
+                format!("`{key}` is marked base64 but does not decode"),
+                true,
+            );
+        }
+        if let Some(first) = seen.insert(key, n) {
+            issue(
+                n,
+                format!("`{key}` is already defined on line {first}"),
+                false,
+            );
+        }
+    }
+    issues
+}
+
+/// The value part of a line (everything after the first `=`).
+fn parse_value(raw: &str, trim: bool) -> &str {
+    let trimmed = raw.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = trimmed
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    if trim { trimmed } else { raw }
+}
+
+/// Format a `KEY=VALUE` line, double-quoting the value when it would not
+/// otherwise read back unchanged (surrounding whitespace, or already looking
+/// quoted).
+fn format_line(key: &str, value: &str) -> String {
+    if parse_value(value, true) == value {
+        format!("{key}={value}")
+    } else {
+        format!("{key}=\"{value}\"")
+    }
+}
+
+/// Keys that differ between two parsed namespaces, each list sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EnvDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl EnvDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+pub fn diff(old: &HashMap<String, String>, new: &HashMap<String, String>) -> EnvDiff {
+    let mut d = EnvDiff::default();
+    for (key, value) in new {
+        match old.get(key) {
+            None => d.added.push(key.clone()),
+            Some(prev) if prev != value => d.changed.push(key.clone()),
+            Some(_) => {}
+        }
+    }
+    d.removed = old
+        .keys()
+        .filter(|k| !new.contains_key(*k))
+        .cloned()
+        .collect();
+    d.added.sort();
+    d.removed.sort();
+    d.changed.sort();
+    d
+}
+
+/// `fingerprint`: the hex SHA-256 of a canonical form of `pairs`, the same
+/// for the same keys and values however the notes are laid out.  The hashed
+/// bytes are `bwenv-fingerprint-v1\n` followed, for each key in byte order,
+/// by `T` (`B` for a base64 `KEY!=` value, hashed as its base64 text), then
+/// the key and the value as netstrings (`<byte length>:<bytes>,`).
+pub fn fingerprint(pairs: &HashMap<String, String>, encoded: &[String]) -> String {
+    let mut keys: Vec<&String> = pairs.keys().collect();
+    keys.sort();
+    let mut canonical = b"bwenv-fingerprint-v1\n".to_vec();
+    for key in keys {
+        canonical.push(if encoded.contains(key) { b'B' } else { b'T' });
+        for field in [key.as_bytes(), pairs[key].as_bytes()] {
+            canonical.extend_from_slice(format!("{}:", field.len()).as_bytes());
+            canonical.extend_from_slice(field);
+            canonical.push(b',');
+        }
+    }
+    let digest = crate::sha256::hex_digest(&canonical);
+    canonical.zeroize();
+    digest
 }
 
 /// Serialize a map into sorted `KEY=VALUE` lines.
@@ -45,32 +341,593 @@ pub fn serialize(pairs: &HashMap<String, String>) -> String {
     let mut keys: Vec<&String> = pairs.keys().collect();
     keys.sort();
     keys.iter()
-        .map(|k| format!("{}={}", k, pairs[*k]))
+        .map(|k| format_line(k, &pairs[*k]))
         .collect::<Vec<_>>()
         .join("\n")
 }
 
+/// Serialize a map into `export KEY='VALUE'` lines that are safe to `eval`
+/// in a POSIX shell, in the order of [`output_keys`].
+pub fn serialize_shell(pairs: &HashMap<String, String>, order: Option<&[String]>) -> String {
+    output_keys(pairs, order)
+        .iter()
+        .map(|k| format!("export {}={}\n", k, shell_quote(&pairs[*k])))
+        .collect()
+}
+
+/// The keys of `pairs` in output order: sorted, or in `order` (a
+/// [`Parsed::order`]) when given.  Keys `order` doesn't know come last,
+/// sorted, and keys it has but `pairs` doesn't are skipped.
+pub fn output_keys<'a>(
+    pairs: &'a HashMap<String, String>,
+    order: Option<&[String]>,
+) -> Vec<&'a String> {
+    let mut rest: Vec<&String> = pairs.keys().collect();
+    let mut keys = Vec::with_capacity(rest.len());
+    if let Some(order) = order {
+        let mut seen = std::collections::HashSet::new();
+        for key in order {
+            if let Some((k, _)) = pairs.get_key_value(key)
+                && seen.insert(k)
+            {
+                keys.push(k);
+            }
+        }
+        rest.retain(|k| !seen.contains(k));
+    }
+    rest.sort();
+    keys.extend(rest);
+    keys
+}
+
+/// Single-quote `value` for a POSIX shell (`'` becomes `'\''`).
+///
+/// Everything between single quotes is literal in a POSIX shell, so no value
+/// can end the quoting early.  Backslashes are put outside the quotes too
+/// (`\` becomes `'\\'`): fish treats `\'` inside single quotes as an escaped
+/// quote, so a value ending in `\` would otherwise run into the next line
+/// there.  `'\\'` means a single backslash in both.
+pub fn shell_quote(value: &str) -> String {
+    String::from_utf8(shell_quote_bytes(value.as_bytes())).expect("quoting keeps UTF-8 intact")
+}
+
+/// [`shell_quote`] for arbitrary bytes (e.g. decoded binary values).
+pub fn shell_quote_bytes(value: &[u8]) -> Vec<u8> {
+    let mut out = vec![b'\''];
+    for &b in value {
+        match b {
+            b'\'' => out.extend_from_slice(br"'\''"),
+            b'\\' => out.extend_from_slice(br"'\\'"),
+            _ => out.push(b),
+        }
+    }
+    out.push(b'\'');
+    out
+}
+
+/// Header comment marking notes as written by bwenv.
+pub const MANAGED_HEADER: &str = "# managed-by: bwenv";
+
+/// Return `true` if the first non-blank line is the [`MANAGED_HEADER`].
+pub fn is_managed(notes: &str) -> bool {
+    note_lines(notes)
+        .into_iter()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .is_some_and(|l| l == MANAGED_HEADER)
+}
+
+/// Prepend the [`MANAGED_HEADER`] unless it is already there.
+pub fn with_managed_header(notes: &str) -> String {
+    if is_managed(notes) {
+        notes.to_string()
+    } else if notes.is_empty() {
+        MANAGED_HEADER.to_string()
+    } else {
+        format!("{MANAGED_HEADER}\n{notes}")
+    }
+}
+
+/// Prefix of the comment line that documents the key directly below it.
+const DESC_PREFIX: &str = "# desc:";
+
+/// Return the key defined on `line`, if it is a `KEY=VALUE` line.
+fn line_key(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let line = strip_export(line).unwrap_or(line);
+    line.split_once('=').map(|(k, _)| {
+        let k = k.trim();
+        k.strip_suffix(ENCODED_MARKER).unwrap_or(k)
+    })
+}
+
+/// Return the description text if `line` is a `# desc:` comment.
+fn line_description(line: &str) -> Option<&str> {
+    line.trim().strip_prefix(DESC_PREFIX).map(str::trim)
+}
+
 /// Upsert a single key in existing note content, preserving other lines.
+///
+/// The first `KEY=` line is rewritten in place; any later duplicates are
+/// dropped so the new value is the only one.  New keys are appended.
 pub fn update(existing: &str, key: &str, value: &str) -> String {
-    let mut pairs = parse(existing);
-    pairs.insert(key.to_string(), value.to_string());
-    serialize(&pairs)
+    replace_line(existing, key, format_line(key, value))
 }
 
-/// Remove a single key from existing note content, preserving other lines.
-/// Returns `None` if the key was not present.
-pub fn remove(existing: &str, key: &str) -> Option<String> {
-    let mut pairs = parse(existing);
-    if pairs.remove(key).is_none() {
+/// Like [`update`], but add `value` to the end of the key's current value,
+/// joined by `sep` (just `value` when the key is absent or empty).  `None`
+/// for a `KEY!=<base64>` key, which can't be extended as text.
+pub fn append(existing: &str, key: &str, value: &str, sep: &str) -> Option<String> {
+    let parsed = parse_checked(existing, true);
+    if parsed.encoded.iter().any(|k| k == key) {
         return None;
     }
-    Some(serialize(&pairs))
+    let mut joined = match parsed.pairs.get(key) {
+        Some(current) if !current.is_empty() => format!("{current}{sep}{value}"),
+        _ => value.to_string(),
+    };
+    let updated = update(existing, key, &joined);
+    joined.zeroize();
+    Some(updated)
+}
+
+/// Like [`update`], but store `value_base64` as a binary `KEY!=<base64>` line.
+pub fn update_encoded(existing: &str, key: &str, value_base64: &str) -> String {
+    replace_line(
+        existing,
+        key,
+        format!("{key}{ENCODED_MARKER}={value_base64}"),
+    )
+}
+
+fn replace_line(existing: &str, key: &str, new_line: String) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut found = false;
+    for line in note_lines(existing) {
+        if line_key(line) == Some(key) {
+            if found {
+                continue;
+            }
+            found = true;
+            lines.push(new_line.clone());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !found {
+        lines.push(new_line);
+    }
+    join_lines(lines)
+}
+
+/// The same notes with every value emptied: keys, comments and descriptions
+/// stay in place, later duplicates of a key are dropped.
+pub fn blank_values(notes: &str) -> String {
+    let mut seen = std::collections::HashSet::new();
+    let mut lines: Vec<String> = Vec::new();
+    for line in note_lines(notes) {
+        match line_key(line) {
+            Some(key) if seen.insert(key) => lines.push(format!("{key}=")),
+            Some(_) => {}
+            None => lines.push(line.to_string()),
+        }
+    }
+    join_lines(lines)
+}
+
+/// Remove a single key (and its description) from existing note content,
+/// preserving other lines.  Returns `None` if the key was not present.
+pub fn remove(existing: &str, key: &str) -> Option<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut found = false;
+    for line in note_lines(existing) {
+        if line_key(line) == Some(key) {
+            found = true;
+            if lines.last().is_some_and(|l| line_description(l).is_some()) {
+                lines.pop();
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    found.then(|| join_lines(lines))
+}
+
+/// Return the description attached to `key` (the `# desc:` line directly
+/// above it).  Outer `None`: key not present; inner `None`: no description.
+pub fn description(notes: &str, key: &str) -> Option<Option<String>> {
+    let mut prev: Option<&str> = None;
+    for line in note_lines(notes) {
+        if line_key(line) == Some(key) {
+            return Some(prev.and_then(line_description).map(str::to_string));
+        }
+        prev = Some(line);
+    }
+    None
+}
+
+/// Every described key with its description, as [`description`] reads it.
+pub fn descriptions(notes: &str) -> HashMap<String, String> {
+    let mut out = HashMap::new();
+    let mut prev: Option<&str> = None;
+    for line in note_lines(notes) {
+        if let Some(key) = line_key(line)
+            && let Some(text) = prev.and_then(line_description)
+        {
+            out.entry(key.to_string())
+                .or_insert_with(|| text.to_string());
+        }
+        prev = Some(line);
+    }
+    out
+}
+
+/// Set (or, with an empty `text`, clear) the description of `key` without
+/// touching its value.  Returns `None` if the key was not present.
+pub fn set_description(existing: &str, key: &str, text: &str) -> Option<String> {
+    let text = text.trim();
+    let mut lines: Vec<String> = Vec::new();
+    let mut found = false;
+    for line in note_lines(existing) {
+        if !found && line_key(line) == Some(key) {
+            found = true;
+            if lines.last().is_some_and(|l| line_description(l).is_some()) {
+                lines.pop();
+            }
+            if !text.is_empty() {
+                lines.push(format!("{DESC_PREFIX} {text}"));
+            }
+        }
+        lines.push(line.to_string());
+    }
+    found.then(|| join_lines(lines))
+}
+
+fn join_lines(lines: Vec<String>) -> String {
+    lines.join("\n")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn fingerprint_ignores_layout_but_not_content() {
+        let fp = |notes: &str| {
+            let parsed = parse_checked(notes, true);
+            fingerprint(&parsed.pairs, &parsed.encoded)
+        };
+        let base = fp("A=1\nB=2");
+        assert_eq!(base, fp("# comment\nB=2\n\nA = 1\n"));
+        assert_eq!(base, fp("A=0\nB=2\nA=1"));
+        assert_ne!(base, fp("A=1\nB=3"));
+        assert_ne!(base, fp("A=1\nB=2\nC="));
+        // Netstrings keep key/value boundaries apart.
+        assert_ne!(fp("AB=C"), fp("A=BC"));
+        assert_ne!(fp("BIN=AA=="), fp("BIN!=AA=="));
+        // The worked example in the README.
+        assert_eq!(
+            fp("A=1\nBIN!=AA=="),
+            "a5cba3828da1e3940f585ff151b1db459021fac77cce9400ec8950a6a7adfa4a"
+        );
+    }
+
+    #[test]
+    fn expand_vars_from_lookup() {
+        let lookup = |name: &str| (name == "HOME").then(|| "/home/me".to_string());
+        let expand = |v: &str, allow| expand_vars(v, lookup, allow);
+        assert_eq!(expand("${HOME}/cache", false).unwrap(), "/home/me/cache");
+        assert_eq!(
+            expand("$HOME and $$ {x}", false).unwrap(),
+            "$HOME and $$ {x}"
+        );
+        assert_eq!(expand("$${HOME}", false).unwrap(), "${HOME}");
+        assert_eq!(expand("a${NOPE}b", true).unwrap(), "ab");
+        assert!(
+            expand("${NOPE}", false)
+                .unwrap_err()
+                .to_string()
+                .contains("NOPE")
+        );
+        assert!(expand("${HOME", false).is_err());
+        assert!(expand("${-x}", false).is_err());
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let old = parse("A=1\nB=2\nC=3");
+        let new = parse("B=2\nC=30\nD=4");
+        assert_eq!(
+            diff(&old, &new),
+            EnvDiff {
+                added: vec!["D".into()],
+                removed: vec!["A".into()],
+                changed: vec!["C".into()],
+            }
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn lint_reports_line_numbers_without_values() {
+        let notes = "# header\nA=1\nnot a pair secret\n1BAD=x\nexport B=2\nA=3\nC!=***\n";
+        let issues = lint(notes);
+        let got: Vec<(usize, bool)> = issues.iter().map(|i| (i.line, i.error)).collect();
+        assert_eq!(
+            got,
+            [(3, true), (4, true), (5, false), (6, false), (7, true)]
+        );
+        assert!(issues[3].message.contains("line 2"));
+        assert!(issues.iter().all(|i| !i.message.contains("secret")));
+        assert!(lint("A=1\n\n  # c\nB=\"x\"\n").is_empty());
+    }
+
+    #[test]
+    fn encoded_marker() {
+        let p = parse_checked("BIN!=AAE=\nTXT=x\nOVER!=AA==\nOVER=plain\n", true);
+        assert_eq!(p.pairs["BIN"], "AAE=");
+        assert_eq!(p.encoded, ["BIN"]);
+        assert_eq!(p.pairs["OVER"], "plain");
+
+        let notes = update_encoded("A=1\nBIN=old", "BIN", "AAE=");
+        assert_eq!(notes, "A=1\nBIN!=AAE=");
+        assert_eq!(update(&notes, "BIN", "text"), "A=1\nBIN=text");
+        assert_eq!(remove(&notes, "BIN").as_deref(), Some("A=1"));
+    }
+
+    #[test]
+    fn quoted_values_keep_inner_whitespace() {
+        let notes = "A=\"  padded  \"\nB='x '\nC=  bare  \nD=\"unterminated\n";
+        for trim in [true, false] {
+            let p = parse_checked(notes, trim).pairs;
+            assert_eq!(p["A"], "  padded  ");
+            assert_eq!(p["B"], "x ");
+            assert_eq!(p["D"], "\"unterminated");
+        }
+        assert_eq!(parse_checked(notes, true).pairs["C"], "bare");
+        assert_eq!(parse_checked(notes, false).pairs["C"], "  bare  ");
+    }
+
+    #[test]
+    fn written_values_read_back_exactly() {
+        for value in ["  padded ", "\"q\"", "'q'", "plain", "a \"b\" c", ""] {
+            let notes = update("", "K", value);
+            assert_eq!(parse(&notes)["K"], value, "notes: {notes:?}");
+        }
+        assert_eq!(update("", "K", " x"), "K=\" x\"");
+        assert_eq!(update("", "K", "x y"), "K=x y");
+    }
+
+    #[test]
+    fn serialize_shell_quotes_values() {
+        let pairs: HashMap<String, String> = [
+            ("B".to_string(), "it's $HOME".to_string()),
+            ("A".to_string(), String::new()),
+        ]
+        .into();
+        assert_eq!(
+            serialize_shell(&pairs, None),
+            "export A=''\nexport B='it'\\''s $HOME'\n"
+        );
+    }
+
+    #[test]
+    fn output_order_is_sorted_or_as_stored() {
+        let parsed = parse_checked("B=1\nA=2\nB=3\nC=4\n", true);
+        assert_eq!(parsed.order, ["B", "A", "C"]);
+        let mut pairs = parsed.pairs;
+        pairs.remove("A");
+        pairs.insert("AA".into(), "5".into());
+        assert_eq!(output_keys(&pairs, None), ["AA", "B", "C"]);
+        assert_eq!(output_keys(&pairs, Some(&parsed.order)), ["B", "C", "AA"]);
+        assert_eq!(
+            serialize_shell(&pairs, Some(&parsed.order)),
+            "export B='3'\nexport C='4'\nexport AA='5'\n"
+        );
+    }
+
+    /// Read one shell word of single-quoted runs and backslash escapes from
+    /// the start of `script`, the way a POSIX shell does (`fish`: also the
+    /// way fish does, where `\\` and `\'` are escapes inside single quotes).
+    /// Returns the word and the rest; `None` for anything else unquoted, so
+    /// a value that could reach the shell unquoted fails the test.
+    fn read_quoted_word(script: &[u8], fish: bool) -> Option<(Vec<u8>, &[u8])> {
+        let mut word = Vec::new();
+        let mut i = 0;
+        while i < script.len() {
+            match script[i] {
+                b'\'' => {
+                    i += 1;
+                    loop {
+                        match *script.get(i)? {
+                            b'\'' => break,
+                            b'\\' if fish && matches!(script.get(i + 1), Some(b'\\' | b'\'')) => {
+                                word.push(script[i + 1]);
+                                i += 2;
+                            }
+                            b => {
+                                word.push(b);
+                                i += 1;
+                            }
+                        }
+                    }
+                    i += 1;
+                }
+                b'\\' => {
+                    word.push(*script.get(i + 1)?);
+                    i += 2;
+                }
+                b'\n' | b' ' => break,
+                _ => return None,
+            }
+        }
+        Some((word, &script[i..]))
+    }
+
+    /// Deterministic xorshift, so failures reproduce without a `rand` crate.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Characters that mean something to a shell, heavily overrepresented.
+    const NASTY: &[char] = &[
+        '\'', '\\', '"', '$', '`', '(', ')', ';', '&', '|', '<', '>', '\n', '\r', '\t', ' ', '!',
+        '*', '?', '[', ']', '{', '}', '~', '#', '%', '=', 'a', 'Z', '0', 'é', '€', '\u{7f}',
+    ];
+
+    fn random_value(rng: &mut XorShift) -> String {
+        let len = rng.below(24);
+        (0..len)
+            .map(|_| match rng.below(4) {
+                0 => char::from_u32(1 + rng.below(0x2ff) as u32).unwrap_or('x'),
+                _ => NASTY[rng.below(NASTY.len())],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shell_quoting_survives_fuzzed_values() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..5000 {
+            let value = random_value(&mut rng);
+            let bytes_value: Vec<u8> = (0..rng.below(24))
+                .map(|_| 1 + rng.below(255) as u8)
+                .collect();
+            for fish in [false, true] {
+                let quoted = shell_quote(&value);
+                let (word, rest) = read_quoted_word(quoted.as_bytes(), fish)
+                    .unwrap_or_else(|| panic!("{value:?} left unquoted: {quoted:?}"));
+                assert_eq!(word, value.as_bytes(), "{quoted:?}");
+                assert!(
+                    rest.is_empty(),
+                    "{value:?} ended the word early: {quoted:?}"
+                );
+
+                let quoted = shell_quote_bytes(&bytes_value);
+                let (word, rest) = read_quoted_word(&quoted, fish).unwrap();
+                assert_eq!(word, bytes_value);
+                assert!(rest.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn serialized_exports_read_back_as_one_assignment_each() {
+        let mut rng = XorShift(42);
+        let pairs: HashMap<String, String> = (0..300)
+            .map(|i| (format!("K{i}"), random_value(&mut rng)))
+            .collect();
+        let script = serialize_shell(&pairs, None);
+        let mut rest = script.as_bytes();
+        let mut keys: Vec<&String> = pairs.keys().collect();
+        keys.sort();
+        for key in keys {
+            let prefix = format!("export {key}=");
+            assert!(
+                rest.starts_with(prefix.as_bytes()),
+                "lost sync before {key}"
+            );
+            let (word, tail) = read_quoted_word(&rest[prefix.len()..], false).unwrap();
+            assert_eq!(word, pairs[key].as_bytes());
+            rest = tail
+                .strip_prefix(b"\n")
+                .expect("one line ending per export");
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sh_evaluates_exports_to_the_stored_values() {
+        let mut rng = XorShift(7);
+        let pairs: HashMap<String, String> = (0..200)
+            .map(|i| (format!("K{i}"), random_value(&mut rng)))
+            .collect();
+        let mut keys: Vec<&String> = pairs.keys().collect();
+        keys.sort();
+        let mut script = serialize_shell(&pairs, None);
+        for key in &keys {
+            script.push_str(&format!("printf '%s\\0' \"${key}\"\n"));
+        }
+        let output = std::process::Command::new("sh")
+            .args(["-c", &script])
+            .env_clear()
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let printed: Vec<&[u8]> = output.stdout.split(|&b| b == 0).collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(printed[i], pairs[*key].as_bytes(), "{key}");
+        }
+    }
+
+    #[test]
+    fn backslashes_are_quoted_for_fish_too() {
+        assert_eq!(shell_quote(r"a\"), r"'a'\\''");
+        assert_eq!(shell_quote(r"\'"), r"''\\''\'''");
+        assert_eq!(shell_quote_bytes(b"\\"), br"''\\''");
+    }
+
+    #[test]
+    fn append_joins_with_separator() {
+        let notes = "# paths\nPATH=/usr/bin\nB=1";
+        assert_eq!(
+            append(notes, "PATH", "/opt/bin", ":").unwrap(),
+            "# paths\nPATH=/usr/bin:/opt/bin\nB=1"
+        );
+        assert_eq!(
+            append(notes, "NEW", "x", ":").unwrap(),
+            format!("{notes}\nNEW=x")
+        );
+        assert_eq!(append("A=", "A", "x", ",").unwrap(), "A=x");
+        assert_eq!(append("A=a", "A", "b c", ", ").unwrap(), "A=a, b c");
+        assert_eq!(append("BIN!=AAAA", "BIN", "x", ":"), None);
+    }
+
+    #[test]
+    fn crlf_and_lone_cr_end_lines() {
+        let notes = "A=1\r\nB=two words\r\n# c\r\rC=3\rD='x'\r\n";
+        let p = parse_checked(notes, false).pairs;
+        assert_eq!(p["A"], "1");
+        assert_eq!(p["B"], "two words");
+        assert_eq!(p["C"], "3");
+        assert_eq!(p["D"], "x");
+        assert_eq!(split_lines("A=1\r\n\nB=2", false), ["A=1", "", "B=2"]);
+        // --keep-cr: only a lone `\r` survives, as part of the value.
+        assert_eq!(split_lines("A=1\rB=2\r\nC=3", true), ["A=1\rB=2", "C=3"]);
+        assert_eq!(lint("A=1\rnot a line")[0].line, 2);
+    }
+
+    #[test]
+    fn rewrites_split_lone_cr_lines_like_parse() {
+        let notes = "# desc: first\rA=1\rB=2";
+        assert_eq!(update(notes, "B", "3"), "# desc: first\nA=1\nB=3");
+        assert_eq!(update(notes, "C", "4"), "# desc: first\nA=1\nB=2\nC=4");
+        assert_eq!(remove(notes, "A").unwrap(), "B=2");
+        assert_eq!(remove("A=1\rB=2", "B").unwrap(), "A=1");
+        assert_eq!(description(notes, "A"), Some(Some("first".to_string())));
+        assert_eq!(
+            set_description(notes, "B", "second").unwrap(),
+            "# desc: first\nA=1\n# desc: second\nB=2"
+        );
+        assert_eq!(blank_values(notes), "# desc: first\nA=\nB=");
+        assert!(is_managed(&format!("\r{MANAGED_HEADER}\rA=1")));
+    }
+
     #[test]
     fn parse_basic() {
         let m = parse("A=1\nB=hello=world\n");
@@ -101,6 +958,15 @@ mod tests {
         assert_eq!(m["A"], "1");
     }
 
+    #[test]
+    fn conventional_keys_are_uppercase() {
+        assert!(is_conventional_key("DATABASE_URL"));
+        assert!(is_conventional_key("_X2"));
+        assert!(!is_conventional_key("databaseUrl"));
+        assert!(!is_conventional_key("Path"));
+        assert!(!is_conventional_key("2X"));
+    }
+
     // H2: non-POSIX key names must be rejected
     #[test]
     fn parse_rejects_invalid_key_names() {
@@ -121,6 +987,42 @@ mod tests {
         assert_eq!(m["KEY"], "value");
     }
 
+    #[test]
+    fn parse_duplicates_last_wins_and_reported() {
+        let p = parse_checked("A=1\nB=2\nA=3\nA=4\nB=5\n", true);
+        assert_eq!(p.pairs["A"], "4");
+        assert_eq!(p.pairs["B"], "5");
+        assert_eq!(p.duplicates, vec!["A", "B"]);
+        assert!(parse_checked("A=1\nB=2", true).duplicates.is_empty());
+    }
+
+    #[test]
+    fn parse_strips_export_prefix() {
+        let p = parse_checked("export A=1\nexport\tB=2\nC=3\nexportD=4\n", true);
+        assert_eq!(p.pairs["A"], "1");
+        assert_eq!(p.pairs["B"], "2");
+        assert_eq!(p.pairs["C"], "3");
+        assert_eq!(p.pairs["exportD"], "4");
+        assert_eq!(p.exported, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn update_rewrites_exported_line_without_prefix() {
+        assert_eq!(update("export A=1\nB=2", "A", "9"), "A=9\nB=2");
+        assert_eq!(remove("export A=1\nB=2", "A").unwrap(), "B=2");
+    }
+
+    #[test]
+    fn managed_header_added_once_and_ignored_by_parse() {
+        let s = with_managed_header("A=1");
+        assert_eq!(s, "# managed-by: bwenv\nA=1");
+        assert!(is_managed(&s));
+        assert_eq!(with_managed_header(&s), s);
+        assert_eq!(parse(&s).len(), 1);
+        assert!(!is_managed("A=1\n# managed-by: bwenv"));
+        assert!(is_managed(&update(&s, "B", "2")));
+    }
+
     #[test]
     fn roundtrip() {
         let original = "A=1\nB=2\n";
@@ -145,8 +1047,70 @@ mod tests {
         assert_eq!(m["B"], "2");
     }
 
+    #[test]
+    fn blank_values_keeps_keys_and_comments() {
+        let notes = "# desc: token\nexport A=secret\n\nB!=AAEC\nA=again";
+        assert_eq!(blank_values(notes), "# desc: token\nA=\n\nB=");
+    }
+
     #[test]
     fn remove_missing_returns_none() {
         assert!(remove("A=1", "MISSING").is_none());
     }
+
+    #[test]
+    fn update_preserves_order_and_comments() {
+        let s = update("# db\nZ=1\nA=2", "Z", "9");
+        assert_eq!(s, "# db\nZ=9\nA=2");
+        let s = update(&s, "M", "3");
+        assert_eq!(s, "# db\nZ=9\nA=2\nM=3");
+    }
+
+    #[test]
+    fn update_drops_later_duplicates() {
+        assert_eq!(update("A=1\nB=2\nA=3", "A", "4"), "A=4\nB=2");
+    }
+
+    #[test]
+    fn description_roundtrip() {
+        let s = set_description("A=1\nB=2", "B", "database password").unwrap();
+        assert_eq!(s, "A=1\n# desc: database password\nB=2");
+        assert_eq!(
+            description(&s, "B"),
+            Some(Some("database password".to_string()))
+        );
+        assert_eq!(description(&s, "A"), Some(None));
+        assert_eq!(description(&s, "MISSING"), None);
+        assert_eq!(parse(&s)["B"], "2");
+    }
+
+    #[test]
+    fn descriptions_maps_each_described_key() {
+        let notes =
+            "# desc: token\nA=1\nB=2\n# desc: ignored\n\n# desc: pw\nC=3\n# desc: again\nA=4";
+        let descs = descriptions(notes);
+        assert_eq!(descs.len(), 2);
+        assert_eq!(descs["A"], "token");
+        assert_eq!(descs["C"], "pw");
+    }
+
+    #[test]
+    fn set_description_replaces_and_clears() {
+        let s = "# desc: old\nA=1";
+        assert_eq!(set_description(s, "A", "new").unwrap(), "# desc: new\nA=1");
+        assert_eq!(set_description(s, "A", "").unwrap(), "A=1");
+        assert!(set_description(s, "B", "x").is_none());
+    }
+
+    #[test]
+    fn update_keeps_description() {
+        let s = update("# desc: token\nA=1", "A", "2");
+        assert_eq!(s, "# desc: token\nA=2");
+    }
+
+    #[test]
+    fn remove_drops_description() {
+        let s = remove(" # desc: token\nA=1\nB=2", "A").unwrap();
+        assert_eq!(s, "B=2");
+    }
 }
@@ -0,0 +1,194 @@
+//! `--timings`: how long each rbw subprocess took, and `--trace-rbw`: the
+//! exact rbw command lines.
+//!
+//! Every rbw call in `rbw.rs` runs through [`timed`], which records the
+//! wall-clock time around the `output()`/`wait()` of the child.  Recording is
+//! off unless [`enable`] was called; [`report`] prints the collected table to
+//! stderr once, before bwenv execs the target command or exits.  Parallel
+//! fetches overlap, so the sum of the rows can exceed the wall time.
+//!
+//! With [`enable_trace`], each call is also printed to stderr as it finishes,
+//! as a shell command line that can be pasted to rerun it against rbw
+//! directly.  Only the program, arguments and environment changes are shown
+//! (bwenv never passes secrets in those); what is piped to rbw's stdin is
+//! replaced by its length.
+
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+struct Timings {
+    started: Instant,
+    calls: Vec<(String, Duration)>,
+}
+
+/// `None` while disabled.
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Start recording rbw call durations.
+pub fn enable() {
+    *TIMINGS.lock().unwrap_or_else(|e| e.into_inner()) = Some(Timings {
+        started: Instant::now(),
+        calls: Vec::new(),
+    });
+}
+
+/// Print every rbw call to stderr as it finishes.
+pub fn enable_trace() {
+    TRACE.store(true, Ordering::Relaxed);
+}
+
+/// Run `f` on `cmd`, recording its duration under the command line when
+/// timings are enabled.
+pub fn timed<T>(cmd: &mut Command, f: impl FnOnce(&mut Command) -> T) -> T {
+    timed_piped(cmd, None, f)
+}
+
+/// [`timed`] for a call that is fed `stdin_len` bytes on stdin, which the
+/// trace mentions without showing them.
+pub fn timed_piped<T>(
+    cmd: &mut Command,
+    stdin_len: Option<usize>,
+    f: impl FnOnce(&mut Command) -> T,
+) -> T {
+    let trace = TRACE.load(Ordering::Relaxed);
+    if !trace && TIMINGS.lock().unwrap_or_else(|e| e.into_inner()).is_none() {
+        return f(cmd);
+    }
+    let start = Instant::now();
+    let result = f(cmd);
+    let elapsed = start.elapsed();
+    if trace {
+        eprintln!("{}", trace_line(cmd, stdin_len, elapsed));
+    }
+    if let Some(t) = TIMINGS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        t.calls.push((label(cmd), elapsed));
+    }
+    result
+}
+
+/// Print the recorded table to stderr and stop recording.  A no-op when
+/// timings are disabled or already reported.
+pub fn report() {
+    let Some(t) = TIMINGS.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    eprint!("{}", format_table(&t.calls, t.started.elapsed()));
+}
+
+fn label(cmd: &Command) -> String {
+    let mut label = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        label.push(' ');
+        label.push_str(&arg.to_string_lossy());
+    }
+    label
+}
+
+/// `+ RBW_PROFILE=work rbw get --folder bwenv ns  # 12.3 ms`, quoted for
+/// sh.  An inherited variable bwenv removed shows up as `env -u NAME`.  A
+/// call that was fed stdin is commented out (`# + rbw edit ns  # stdin: …`):
+/// pasted without its input it would run rbw on an empty or terminal stdin.
+fn trace_line(cmd: &Command, stdin_len: Option<usize>, elapsed: Duration) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let (set, removed): (Vec<_>, Vec<_>) = cmd.get_envs().partition(|(_, v)| v.is_some());
+    if !removed.is_empty() {
+        words.push("env".to_string());
+        for (name, _) in removed {
+            words.push(format!("-u {}", quote(&name.to_string_lossy())));
+        }
+    }
+    for (name, value) in set {
+        let value = value.unwrap_or_default().to_string_lossy();
+        words.push(format!("{}={}", name.to_string_lossy(), quote(&value)));
+    }
+    words.push(quote(&cmd.get_program().to_string_lossy()));
+    words.extend(cmd.get_args().map(|a| quote(&a.to_string_lossy())));
+    let mut line = format!("+ {}  #", words.join(" "));
+    if let Some(len) = stdin_len {
+        line = format!("# {line} stdin: {len} bytes (not shown);");
+    }
+    line.push_str(&format!(" {:.1} ms", ms(elapsed)));
+    line
+}
+
+/// `word` as is when the shell would read it back unchanged, else in single
+/// quotes.
+fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:,=@%+".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn format_table(calls: &[(String, Duration)], wall: Duration) -> String {
+    let width = calls.iter().map(|(l, _)| l.len()).max().unwrap_or(0).max(5);
+    let mut out = String::new();
+    for (label, elapsed) in calls {
+        out.push_str(&format!("{label:<width$}  {:>9.1} ms\n", ms(*elapsed)));
+    }
+    let total: Duration = calls.iter().map(|(_, d)| *d).sum();
+    out.push_str(&format!(
+        "{:<width$}  {:>9.1} ms  ({} rbw call(s), {:.1} ms wall)\n",
+        "total",
+        ms(total),
+        calls.len(),
+        ms(wall)
+    ));
+    out
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_lists_calls_and_total() {
+        let calls = vec![
+            ("rbw unlocked".to_string(), Duration::from_millis(5)),
+            ("rbw get --raw ns".to_string(), Duration::from_millis(120)),
+        ];
+        let table = format_table(&calls, Duration::from_millis(130));
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("rbw unlocked      "));
+        assert!(lines[1].ends_with("120.0 ms"));
+        assert!(lines[2].starts_with("total"));
+        assert!(lines[2].contains("125.0 ms  (2 rbw call(s), 130.0 ms wall)"));
+    }
+
+    #[test]
+    fn trace_is_a_pasteable_command_line() {
+        let mut cmd = Command::new("rbw");
+        cmd.args(["get", "--folder", "my folder", "it's"])
+            .env("RBW_PROFILE", "work")
+            .env_remove("RBW_TTY");
+        assert_eq!(
+            trace_line(&cmd, None, Duration::from_micros(12_340)),
+            r"+ env -u RBW_TTY RBW_PROFILE=work rbw get --folder 'my folder' 'it'\''s'  # 12.3 ms"
+        );
+        let mut cmd = Command::new("rbw");
+        cmd.args(["edit", "ns"]);
+        let line = trace_line(&cmd, Some(42), Duration::from_millis(5));
+        assert_eq!(
+            line,
+            "# + rbw edit ns  # stdin: 42 bytes (not shown); 5.0 ms"
+        );
+    }
+
+    #[test]
+    fn label_is_the_command_line() {
+        let mut cmd = Command::new("rbw");
+        cmd.args(["get", "--folder", "bwenv", "ns"]);
+        assert_eq!(label(&cmd), "rbw get --folder bwenv ns");
+    }
+}
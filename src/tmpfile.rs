@@ -0,0 +1,134 @@
+//! Short-lived secret files for `with-file`, for tools that only read
+//! secrets from a path.
+//!
+//! The file lives in a fresh mode-0700 directory under `$XDG_RUNTIME_DIR`,
+//! or `/dev/shm` without one, so it is memory-backed and private to the
+//! user; when neither exists bwenv refuses rather than write the secret to
+//! disk.  macOS has neither, so there `XDG_RUNTIME_DIR` has to point at a
+//! RAM disk the user set up.  Dropping the [`SecretFile`] overwrites the
+//! contents with zeros and removes the file and its directory, whether or
+//! not the command succeeded.
+
+use crate::random;
+use anyhow::{Context, Result, bail};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Directories tried in order for the private directory.
+fn bases() -> Vec<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm")])
+        .collect()
+}
+
+/// A secret file that is wiped and removed when dropped.
+pub struct SecretFile {
+    dir: PathBuf,
+    path: PathBuf,
+    len: usize,
+}
+
+impl SecretFile {
+    /// Write `bytes` to a new file called `name` (mode 0600) in a private
+    /// directory of its own.
+    pub fn create(name: &str, bytes: &[u8]) -> Result<Self> {
+        Self::create_in(&bases(), name, bytes)
+    }
+
+    /// [`create`](Self::create) under the first of `bases` that exists.
+    fn create_in(bases: &[PathBuf], name: &str, bytes: &[u8]) -> Result<Self> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+            bail!("invalid file name {name:?}");
+        }
+        let Some(base) = bases.iter().find(|dir| dir.is_dir()) else {
+            bail!(
+                "no memory-backed directory for the secret file: set XDG_RUNTIME_DIR \
+                 (or provide /dev/shm; on macOS, point it at a RAM disk)"
+            );
+        };
+        let dir = base.join(format!(
+            "bwenv-{}",
+            random::generate(12, random::Charset::Alnum)?
+        ));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt as _;
+            builder.mode(0o700);
+        }
+        builder
+            .create(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+
+        let file = SecretFile {
+            path: dir.join(name),
+            dir,
+            len: bytes.len(),
+        };
+        file.write(bytes)
+            .with_context(|| format!("failed to write {}", file.path.display()))?;
+        Ok(file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let mut opts = std::fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt as _;
+            opts.mode(0o600);
+        }
+        let mut file = opts.open(&self.path)?;
+        file.write_all(bytes)?;
+        file.sync_all()
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        // Best effort: the command may have removed or replaced the file.
+        if let Ok(mut file) = std::fs::OpenOptions::new().write(true).open(&self.path) {
+            let _ = file.write_all(&vec![0; self.len]);
+            let _ = file.sync_all();
+        }
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_dir(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_is_private_and_gone_after_drop() {
+        let base = std::env::temp_dir().join(format!("bwenv-tmpfile-test-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let bases = [base.join("missing"), base.clone()];
+
+        let file = SecretFile::create_in(&bases, "creds.json", b"{\"k\":1}").unwrap();
+        let path = file.path().to_path_buf();
+        assert!(path.starts_with(&base));
+        assert_eq!(std::fs::read(&path).unwrap(), b"{\"k\":1}");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&path), 0o600);
+            assert_eq!(mode(path.parent().unwrap()), 0o700);
+        }
+        drop(file);
+        assert!(!path.exists());
+        assert!(!path.parent().unwrap().exists());
+        assert!(SecretFile::create_in(&bases, "../x", b"").is_err());
+        assert!(SecretFile::create_in(&bases[..1], "x", b"").is_err());
+        std::fs::remove_dir(&base).unwrap();
+    }
+}